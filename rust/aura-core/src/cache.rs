@@ -2,6 +2,7 @@
 
 use crate::Package;
 use r2d2_alpm::Alpm;
+use serde::Serialize;
 use std::cmp::Ordering;
 use std::collections::HashMap;
 use std::collections::HashSet;
@@ -11,6 +12,42 @@ use std::path::Path;
 use std::path::PathBuf;
 use std::process::Command;
 use std::time::SystemTime;
+use versions::Versioning;
+
+/// A cache-related error.
+#[derive(Debug)]
+pub enum Error {
+    /// A cache directory couldn't be read.
+    ReadDir(PathBuf, std::io::Error),
+    /// A tarball or signature file couldn't be deleted.
+    Remove(PathBuf, std::io::Error),
+    /// A backup directory couldn't be created.
+    Mkdir(PathBuf, std::io::Error),
+    /// A tarball or signature file couldn't be copied.
+    Copy(PathBuf, std::io::Error),
+}
+
+impl std::fmt::Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Error::ReadDir(p, e) => write!(f, "Failed to read {}: {}", p.display(), e),
+            Error::Remove(p, e) => write!(f, "Failed to remove {}: {}", p.display(), e),
+            Error::Mkdir(p, e) => write!(f, "Failed to create {}: {}", p.display(), e),
+            Error::Copy(p, e) => write!(f, "Failed to copy {}: {}", p.display(), e),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::ReadDir(_, e) => Some(e),
+            Error::Remove(_, e) => Some(e),
+            Error::Mkdir(_, e) => Some(e),
+            Error::Copy(_, e) => Some(e),
+        }
+    }
+}
 
 /// A validated path to a package tarball.
 #[derive(Debug, PartialEq, Eq)]
@@ -87,6 +124,70 @@ impl Ord for PkgPath {
     }
 }
 
+/// A package tarball within a cache, its filename parsed into its parts.
+#[derive(Debug, PartialEq, Eq)]
+pub struct CachedPkg {
+    /// The name of the package.
+    pub name: String,
+    /// The full version, including any epoch and the pkgrel.
+    pub version: Versioning,
+    /// The architecture the package was built for.
+    pub arch: String,
+    /// The path to the tarball itself.
+    pub path: PathBuf,
+    /// The tarball's `.sig` file, if there is one.
+    pub sig: Option<PathBuf>,
+}
+
+impl CachedPkg {
+    /// Parse a tarball path of the form `name-version-rel-arch.pkg.tar.*`.
+    ///
+    /// ```
+    /// use aura_core::cache::CachedPkg;
+    /// use std::path::PathBuf;
+    ///
+    /// let path = PathBuf::from("aura-bin-3.2.1-1-x86_64.pkg.tar.zst");
+    /// let pkg = CachedPkg::new(path).unwrap();
+    /// assert_eq!("aura-bin", pkg.name);
+    /// assert_eq!("3.2.1-1", pkg.version.to_string());
+    /// assert_eq!("x86_64", pkg.arch);
+    /// ```
+    pub fn new(path: PathBuf) -> Option<CachedPkg> {
+        let arch = path
+            .file_name()
+            .and_then(|file| file.to_str())
+            .and_then(|file| file.split_once(".pkg.tar"))
+            .and_then(|(stem, _)| stem.rsplit_once('-'))
+            .map(|(_, arch)| arch.to_string())?;
+        let pp = PkgPath::new(path)?;
+        let sig = Some(pp.sig_file()).filter(|sig| sig.is_file());
+        let Package { name, version } = pp.pkg;
+
+        let cp = CachedPkg {
+            name: name.into_owned(),
+            version,
+            arch,
+            path: pp.path,
+            sig,
+        };
+
+        Some(cp)
+    }
+
+    /// Delete this tarball and its `.sig` file, if there is one, yielding the
+    /// path of the removed tarball.
+    fn remove(self) -> Result<PathBuf, Error> {
+        if let Some(sig) = self.sig {
+            std::fs::remove_file(&sig).map_err(|e| Error::Remove(sig, e))?;
+        }
+
+        match std::fs::remove_file(&self.path) {
+            Ok(()) => Ok(self.path),
+            Err(e) => Err(Error::Remove(self.path, e)),
+        }
+    }
+}
+
 /// A description of the size of the package cache.
 pub struct CacheSize {
     /// The number of package files in the cache.
@@ -95,6 +196,26 @@ pub struct CacheSize {
     pub bytes: u64,
 }
 
+/// A breakdown of the disk usage of a single cache directory.
+#[derive(Debug, Default, Serialize)]
+pub struct CacheStats {
+    /// The number of package tarballs in the cache.
+    pub files: usize,
+    /// The bytes consumed by all tarballs and their signature files.
+    pub bytes: u64,
+    /// The bytes consumed by each package, keyed by name.
+    pub per_package: HashMap<String, u64>,
+}
+
+/// The outcome of a [`backup`].
+#[derive(Debug, PartialEq, Eq)]
+pub struct Backup {
+    /// The number of tarballs newly copied.
+    pub copied: usize,
+    /// The number of tarballs already present in the destination.
+    pub skipped: usize,
+}
+
 /// Cache statistics for a particular package.
 #[derive(Debug)]
 pub struct CacheInfo {
@@ -202,6 +323,144 @@ where
         .filter_map(PkgPath::new)
 }
 
+/// Every valid package tarball in the given cache directory, sorted by name
+/// and then version.
+pub fn cached_packages(cache_dir: &Path) -> Result<Vec<CachedPkg>, Error> {
+    let mut pkgs = std::fs::read_dir(cache_dir)
+        .map_err(|e| Error::ReadDir(cache_dir.to_path_buf(), e))?
+        .filter_map(|r| r.ok())
+        .filter_map(|de| CachedPkg::new(de.path()))
+        .collect::<Vec<_>>();
+    pkgs.sort_by(|a, b| a.name.cmp(&b.name).then_with(|| a.version.cmp(&b.version)));
+
+    Ok(pkgs)
+}
+
+/// Delete all but the newest `keep` versions of each package in the given
+/// cache directory, along with their `.sig` files. Yields the paths of the
+/// tarballs that were removed.
+pub fn clean(cache_dir: &Path, keep: usize) -> Result<Vec<PathBuf>, Error> {
+    let mut by_name: HashMap<String, Vec<CachedPkg>> = HashMap::new();
+
+    for cp in cached_packages(cache_dir)? {
+        by_name.entry(cp.name.clone()).or_default().push(cp);
+    }
+
+    let mut removed = Vec::new();
+
+    for mut group in by_name.into_values() {
+        // Already sorted oldest-first by `cached_packages`.
+        group.reverse();
+
+        for cp in group.into_iter().skip(keep) {
+            removed.push(cp.remove()?);
+        }
+    }
+
+    removed.sort();
+
+    Ok(removed)
+}
+
+/// Delete every tarball in the given cache directory, along with its `.sig`
+/// file, whose package is not among the `installed` names. Yields the paths of
+/// the tarballs that were removed.
+///
+/// The `installed` set is expected to be the names from the local ALPM
+/// database.
+pub fn clean_uninstalled(
+    cache_dir: &Path,
+    installed: &HashSet<String>,
+) -> Result<Vec<PathBuf>, Error> {
+    cached_packages(cache_dir)?
+        .into_iter()
+        .filter(|cp| !installed.contains(&cp.name))
+        .map(|cp| cp.remove())
+        .collect()
+}
+
+/// Copy every tarball in the given cache directory, along with its `.sig` file,
+/// into `dest`. Files whose names already exist there are never overwritten.
+pub fn backup(cache_dir: &Path, dest: &Path) -> Result<Backup, Error> {
+    std::fs::create_dir_all(dest).map_err(|e| Error::Mkdir(dest.to_path_buf(), e))?;
+
+    let mut report = Backup {
+        copied: 0,
+        skipped: 0,
+    };
+
+    for cp in cached_packages(cache_dir)? {
+        let files = std::iter::once(&cp.path).chain(cp.sig.as_ref());
+
+        for (i, from) in files.enumerate() {
+            let Some(to) = from.file_name().map(|name| dest.join(name)) else {
+                continue;
+            };
+
+            if to.exists() {
+                // Only the tarballs themselves are counted.
+                if i == 0 {
+                    report.skipped += 1;
+                }
+            } else {
+                std::fs::copy(from, &to).map_err(|e| Error::Copy(from.clone(), e))?;
+
+                if i == 0 {
+                    report.copied += 1;
+                }
+            }
+        }
+    }
+
+    Ok(report)
+}
+
+/// The total and per-package disk usage of the given cache directory. A
+/// directory that can't be read is considered empty.
+pub fn cache_stats(cache_dir: &Path) -> CacheStats {
+    let mut stats = CacheStats::default();
+
+    for cp in cached_packages(cache_dir).unwrap_or_default() {
+        let bytes: u64 = std::iter::once(&cp.path)
+            .chain(cp.sig.as_ref())
+            .filter_map(|p| p.metadata().ok())
+            .map(|meta| meta.len())
+            .sum();
+
+        stats.files += 1;
+        stats.bytes += bytes;
+        *stats.per_package.entry(cp.name).or_default() += bytes;
+    }
+
+    stats
+}
+
+/// Every version of a package available in the given cache directory, sorted
+/// newest-first as ALPM orders them. Useful for presenting a choice of
+/// downgrades.
+pub fn versions_of(cache_dir: &Path, pkg: &str) -> Vec<Versioning> {
+    let mut versions: Vec<_> = package_paths(&[cache_dir])
+        .filter(|pp| pp.pkg.name == pkg)
+        .map(|pp| pp.pkg.version)
+        .collect();
+    versions.sort_by(|a, b| alpm::vercmp(b.to_string(), a.to_string()));
+    versions.dedup_by(|a, b| alpm::vercmp(a.to_string(), b.to_string()).is_eq());
+
+    versions
+}
+
+/// The tarball of a specific package version in the given cache directory,
+/// suitable for handing to `pacman -U`.
+pub fn tarball_for(cache_dir: &Path, pkg: &str, version: &Versioning) -> Option<PathBuf> {
+    package_paths(&[cache_dir])
+        .filter(|pp| {
+            pp.pkg.name == pkg
+                && alpm::vercmp(pp.pkg.version.to_string(), version.to_string()).is_eq()
+        })
+        .map(|pp| pp.path)
+        .min()
+}
+
 /// Installed official packages that have no tarball in the cache.
 pub fn officials_missing_tarballs<'a>(
     alpm: &'a Alpm,
@@ -294,3 +553,248 @@ pub fn all_versions(caches: &[&Path]) -> HashMap<String, HashSet<String>> {
 
     map
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A fresh cache directory populated with the given (empty) files.
+    fn cache_with(name: &str, files: &[&str]) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("aura-cache-{}-{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+
+        for file in files {
+            std::fs::write(dir.join(file), "").unwrap();
+        }
+
+        dir
+    }
+
+    const FILES: &[&str] = &[
+        "aura-bin-4.0.0-1-x86_64.pkg.tar.zst",
+        "aura-bin-4.0.0-1-x86_64.pkg.tar.zst.sig",
+        "aura-bin-4.0.8-1-x86_64.pkg.tar.zst",
+        "aura-bin-4.0.8-1-x86_64.pkg.tar.zst.sig",
+        "aura-bin-3.2.9-2-x86_64.pkg.tar.zst",
+        "python-foo-1:1.0-1-any.pkg.tar.xz",
+        "python-foo-1:1.2-1-any.pkg.tar.xz",
+        "python-foo-2.0-1-any.pkg.tar.xz",
+        "README",
+    ];
+
+    #[test]
+    fn cached() {
+        let dir = cache_with("list", FILES);
+        let pkgs = cached_packages(&dir).unwrap();
+        let found: Vec<_> = pkgs
+            .iter()
+            .map(|cp| (cp.name.as_str(), cp.version.to_string(), cp.arch.as_str()))
+            .collect();
+
+        assert_eq!(
+            vec![
+                ("aura-bin", "3.2.9-2".to_string(), "x86_64"),
+                ("aura-bin", "4.0.0-1".to_string(), "x86_64"),
+                ("aura-bin", "4.0.8-1".to_string(), "x86_64"),
+                ("python-foo", "2.0-1".to_string(), "any"),
+                ("python-foo", "1:1.0-1".to_string(), "any"),
+                ("python-foo", "1:1.2-1".to_string(), "any"),
+            ],
+            found
+        );
+        assert!(pkgs[0].sig.is_none());
+        assert_eq!(
+            Some(dir.join("aura-bin-4.0.8-1-x86_64.pkg.tar.zst.sig")),
+            pkgs[2].sig
+        );
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn cleaning() {
+        let dir = cache_with("clean", FILES);
+        let removed = clean(&dir, 1).unwrap();
+
+        assert_eq!(
+            vec![
+                dir.join("aura-bin-3.2.9-2-x86_64.pkg.tar.zst"),
+                dir.join("aura-bin-4.0.0-1-x86_64.pkg.tar.zst"),
+                dir.join("python-foo-1:1.0-1-any.pkg.tar.xz"),
+                dir.join("python-foo-2.0-1-any.pkg.tar.xz"),
+            ],
+            removed
+        );
+
+        let mut left: Vec<_> = std::fs::read_dir(&dir)
+            .unwrap()
+            .filter_map(|r| r.ok())
+            .filter_map(|de| de.file_name().into_string().ok())
+            .collect();
+        left.sort();
+
+        assert_eq!(
+            vec![
+                "README",
+                "aura-bin-4.0.8-1-x86_64.pkg.tar.zst",
+                "aura-bin-4.0.8-1-x86_64.pkg.tar.zst.sig",
+                "python-foo-1:1.2-1-any.pkg.tar.xz",
+            ],
+            left
+        );
+
+        // Keeping more versions than exist removes nothing.
+        assert!(clean(&dir, 5).unwrap().is_empty());
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn downgrades() {
+        let dir = cache_with(
+            "versions",
+            &[
+                "foo-1.0-1-x86_64.pkg.tar.zst",
+                "foo-1.0-2-x86_64.pkg.tar.zst",
+                "foo-1.0-2-x86_64.pkg.tar.zst.sig",
+                "foo-1.0.1-1-x86_64.pkg.tar.zst",
+                "foo-2:0.9-1-x86_64.pkg.tar.zst",
+                "foo-1:3.0-1-x86_64.pkg.tar.zst",
+                "foo-1.0a-1-x86_64.pkg.tar.zst",
+                "foo-bar-5.0-1-x86_64.pkg.tar.zst",
+            ],
+        );
+        let found: Vec<_> = versions_of(&dir, "foo")
+            .into_iter()
+            .map(|v| v.to_string())
+            .collect();
+
+        assert_eq!(
+            vec!["2:0.9-1", "1:3.0-1", "1.0.1-1", "1.0-2", "1.0-1", "1.0a-1"],
+            found
+        );
+        assert!(versions_of(&dir, "baz").is_empty());
+
+        let v = Versioning::new("1.0-2").unwrap();
+        assert_eq!(
+            Some(dir.join("foo-1.0-2-x86_64.pkg.tar.zst")),
+            tarball_for(&dir, "foo", &v)
+        );
+        let v = Versioning::new("2:0.9-1").unwrap();
+        assert_eq!(
+            Some(dir.join("foo-2:0.9-1-x86_64.pkg.tar.zst")),
+            tarball_for(&dir, "foo", &v)
+        );
+        let v = Versioning::new("0.1-1").unwrap();
+        assert_eq!(None, tarball_for(&dir, "foo", &v));
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn stats() {
+        let dir = cache_with("stats", &[]);
+        let files: &[(&str, usize)] = &[
+            ("foo-1.0-1-x86_64.pkg.tar.zst", 100),
+            ("foo-1.0-1-x86_64.pkg.tar.zst.sig", 10),
+            ("foo-1.1-1-x86_64.pkg.tar.zst", 200),
+            ("bar-2.0-1-any.pkg.tar.xz", 50),
+            ("notes.txt", 1000),
+        ];
+
+        for (file, size) in files {
+            std::fs::write(dir.join(file), vec![0u8; *size]).unwrap();
+        }
+
+        let stats = cache_stats(&dir);
+        assert_eq!(3, stats.files);
+        assert_eq!(360, stats.bytes);
+        assert_eq!(Some(&310), stats.per_package.get("foo"));
+        assert_eq!(Some(&50), stats.per_package.get("bar"));
+        assert_eq!(2, stats.per_package.len());
+
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(0, cache_stats(&dir).files);
+    }
+
+    #[test]
+    fn uninstalled() {
+        let dir = cache_with(
+            "uninstalled",
+            &[
+                "foo-1.0-1-x86_64.pkg.tar.zst",
+                "foo-1.1-1-x86_64.pkg.tar.zst",
+                "bar-2.0-1-any.pkg.tar.zst",
+                "bar-2.0-1-any.pkg.tar.zst.sig",
+                "bar-2.1-1-any.pkg.tar.zst",
+            ],
+        );
+        let installed = HashSet::from(["foo".to_string()]);
+        let removed = clean_uninstalled(&dir, &installed).unwrap();
+
+        assert_eq!(
+            vec![
+                dir.join("bar-2.0-1-any.pkg.tar.zst"),
+                dir.join("bar-2.1-1-any.pkg.tar.zst"),
+            ],
+            removed
+        );
+        assert!(!dir.join("bar-2.0-1-any.pkg.tar.zst.sig").exists());
+
+        let left: Vec<_> = versions_of(&dir, "foo")
+            .into_iter()
+            .map(|v| v.to_string())
+            .collect();
+        assert_eq!(vec!["1.1-1", "1.0-1"], left);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn backups() {
+        let dir = cache_with(
+            "backup-src",
+            &[
+                "foo-1.0-1-x86_64.pkg.tar.zst",
+                "foo-1.0-1-x86_64.pkg.tar.zst.sig",
+                "bar-2.0-1-any.pkg.tar.zst",
+                "notes.txt",
+            ],
+        );
+        let dest =
+            std::env::temp_dir().join(format!("aura-cache-backup-dest-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dest);
+
+        let first = backup(&dir, &dest).unwrap();
+        assert_eq!(
+            Backup {
+                copied: 2,
+                skipped: 0
+            },
+            first
+        );
+        assert!(dest.join("foo-1.0-1-x86_64.pkg.tar.zst.sig").is_file());
+        assert!(!dest.join("notes.txt").exists());
+
+        // Existing files are left alone.
+        std::fs::write(dest.join("bar-2.0-1-any.pkg.tar.zst"), "precious").unwrap();
+        std::fs::write(dir.join("baz-3.0-1-any.pkg.tar.zst"), "").unwrap();
+
+        let second = backup(&dir, &dest).unwrap();
+        assert_eq!(
+            Backup {
+                copied: 1,
+                skipped: 2
+            },
+            second
+        );
+        assert_eq!(
+            "precious",
+            std::fs::read_to_string(dest.join("bar-2.0-1-any.pkg.tar.zst")).unwrap()
+        );
+
+        std::fs::remove_dir_all(&dir).unwrap();
+        std::fs::remove_dir_all(&dest).unwrap();
+    }
+}
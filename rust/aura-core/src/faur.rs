@@ -2,13 +2,17 @@
 
 use serde::Deserialize;
 use serde::Serialize;
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::Duration;
+use std::time::Instant;
 
 /// The main `faur` instance.
 pub const FAUR_URL: &str = "https://faur.fosskers.ca";
 
 /// Package information returned from a `faur` instance. Identical in format to
 /// the AUR's RPC, but yields empty lists for missing fields.
-#[derive(Deserialize, Serialize)]
+#[derive(Debug, Clone, Deserialize, Serialize)]
 #[serde(rename_all = "PascalCase")]
 pub struct Package {
     /// Dependencies only necessary for testing.
@@ -80,38 +84,158 @@ pub struct Package {
     pub version: String,
 }
 
+/// A `faur` instance, or anything else that answers its queries, found at some
+/// base URL. Defaults to the main instance at [`FAUR_URL`].
+///
+/// The client builds the URL of each query, but leaves the actual network call
+/// to a given fetch function.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FaurClient {
+    url: String,
+}
+
+impl Default for FaurClient {
+    fn default() -> Self {
+        FaurClient::new(FAUR_URL)
+    }
+}
+
+impl FaurClient {
+    /// A client of the instance at the given base URL, like that of a mirror or
+    /// private deployment.
+    pub fn new<S>(url: S) -> Self
+    where
+        S: Into<String>,
+    {
+        let mut url = url.into();
+
+        while url.ends_with('/') {
+            url.pop();
+        }
+
+        FaurClient { url }
+    }
+
+    /// The base URL of the instance.
+    pub fn url(&self) -> &str {
+        &self.url
+    }
+
+    /// Look up K-many packages by name in a database of N packages: `O(klogn)`
+    pub fn info<'a, I, F, E>(&self, pkgs: I, fetch: &F) -> Result<Vec<Package>, E>
+    where
+        F: Fn(&str) -> Result<Vec<Package>, E>,
+        I: IntoIterator<Item = &'a str>,
+    {
+        let s = combine_and_sanitize(pkgs);
+        let url = format!("{}/packages?names={}", self.url, s);
+        fetch(&url)
+    }
+
+    /// Look up packages whose names and/or descriptions contain all of the
+    /// K-many given terms: `O(klogn)`
+    pub fn search<'a, I, F, E>(&self, terms: I, fetch: &F) -> Result<Vec<Package>, E>
+    where
+        F: Fn(&str) -> Result<Vec<Package>, E>,
+        I: IntoIterator<Item = &'a str>,
+    {
+        let s = combine_and_sanitize(terms);
+        let url = format!("{}/packages?names={}&by=desc", self.url, s);
+        fetch(&url)
+    }
+
+    /// Look up packages that provide the given "package identity": `O(logn)`
+    pub fn provides<S, F, E>(&self, providing: S, fetch: &F) -> Result<Vec<Package>, E>
+    where
+        S: AsRef<str>,
+        F: Fn(&str) -> Result<Vec<Package>, E>,
+    {
+        let p = combine_and_sanitize([providing.as_ref()]);
+        let url = format!("{}/packages?names={}&by=prov", self.url, p);
+        fetch(&url)
+    }
+}
+
 /// Look up K-many packages by name in a database of N packages: `O(klogn)`
+///
+/// Queries the main instance. See [`FaurClient::info`] to use another.
 pub fn info<'a, I, F, E>(pkgs: I, fetch: &F) -> Result<Vec<Package>, E>
 where
     F: Fn(&str) -> Result<Vec<Package>, E>,
     I: IntoIterator<Item = &'a str>,
 {
-    let s = combine_and_sanitize(pkgs);
-    let url = format!("{}/packages?names={}", FAUR_URL, s);
-    fetch(&url)
+    FaurClient::default().info(pkgs, fetch)
 }
 
 /// Look up packages whose names and/or descriptions contain all of the K-many
 /// given terms: `O(klogn)`
+///
+/// Queries the main instance. See [`FaurClient::search`] to use another.
 pub fn search<'a, I, F, E>(terms: I, fetch: &F) -> Result<Vec<Package>, E>
 where
     F: Fn(&str) -> Result<Vec<Package>, E>,
     I: IntoIterator<Item = &'a str>,
 {
-    let s = combine_and_sanitize(terms);
-    let url = format!("{}/packages?names={}&by=desc", FAUR_URL, s);
-    fetch(&url)
+    FaurClient::default().search(terms, fetch)
 }
 
 /// Look up packages that provide the given "package identity": `O(logn)`
+///
+/// Queries the main instance. See [`FaurClient::provides`] to use another.
 pub fn provides<S, F, E>(providing: S, fetch: &F) -> Result<Vec<Package>, E>
 where
     S: AsRef<str>,
     F: Fn(&str) -> Result<Vec<Package>, E>,
 {
-    let p = combine_and_sanitize([providing.as_ref()]);
-    let url = format!("{}/packages?names={}&by=prov", FAUR_URL, p);
-    fetch(&url)
+    FaurClient::default().provides(providing, fetch)
+}
+
+/// An in-memory cache of Faur responses, keyed by the URL of each query, and
+/// so by the package names and kind of lookup involved.
+///
+/// Wrap a fetch function with [`Cache::fetch`] to avoid repeating identical
+/// queries, say across the many threads of a dependency resolution. Entries
+/// older than the cache's TTL are fetched afresh.
+pub struct Cache {
+    ttl: Duration,
+    entries: Mutex<HashMap<String, (Instant, Vec<Package>)>>,
+}
+
+impl Cache {
+    /// An empty cache whose entries live for the given duration.
+    pub fn new(ttl: Duration) -> Self {
+        Cache {
+            ttl,
+            entries: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Answer a query from the cache if possible, otherwise with the given
+    /// fetch function. Only successful responses are cached.
+    pub fn fetch<F, E>(&self, url: &str, fetch: &F) -> Result<Vec<Package>, E>
+    where
+        F: Fn(&str) -> Result<Vec<Package>, E>,
+    {
+        let hit = self.entries.lock().ok().and_then(|entries| {
+            entries
+                .get(url)
+                .filter(|(at, _)| at.elapsed() < self.ttl)
+                .map(|(_, pkgs)| pkgs.clone())
+        });
+
+        match hit {
+            Some(pkgs) => Ok(pkgs),
+            None => {
+                let pkgs = fetch(url)?;
+
+                if let Ok(mut entries) = self.entries.lock() {
+                    entries.insert(url.to_string(), (Instant::now(), pkgs.clone()));
+                }
+
+                Ok(pkgs)
+            }
+        }
+    }
 }
 
 fn combine_and_sanitize<'a, I>(terms: I) -> String
@@ -142,6 +266,103 @@ mod test {
         assert_eq!("libstdc%2B%2B5", res);
     }
 
+    #[test]
+    fn custom_endpoint() {
+        let client = FaurClient::new("http://localhost:8080/faur/");
+        let urls = Mutex::new(Vec::new());
+        let fetch = |url: &str| -> Result<Vec<Package>, ()> {
+            urls.lock().unwrap().push(url.to_string());
+            Ok(Vec::new())
+        };
+
+        client.info(["aura", "libstdc++5"], &fetch).unwrap();
+        client.search(["aur", "helper"], &fetch).unwrap();
+        client.provides("aura", &fetch).unwrap();
+        info(["aura"], &fetch).unwrap();
+
+        let expected = vec![
+            "http://localhost:8080/faur/packages?names=aura,libstdc%2B%2B5".to_string(),
+            "http://localhost:8080/faur/packages?names=aur,helper&by=desc".to_string(),
+            "http://localhost:8080/faur/packages?names=aura&by=prov".to_string(),
+            format!("{}/packages?names=aura", FAUR_URL),
+        ];
+        assert_eq!(expected, urls.into_inner().unwrap());
+        assert_eq!(FAUR_URL, FaurClient::default().url());
+    }
+
+    #[test]
+    fn cached_lookups() {
+        let file = File::open("tests/faur.json").unwrap();
+        let pkgs: Vec<Package> = serde_json::from_reader(BufReader::new(file)).unwrap();
+        let calls = Mutex::new(0);
+        let fetch = |_: &str| -> Result<Vec<Package>, ()> {
+            *calls.lock().unwrap() += 1;
+            Ok(pkgs.clone())
+        };
+
+        let cache = Cache::new(Duration::from_secs(60));
+        let cached = |url: &str| cache.fetch(url, &fetch);
+        assert_eq!(3, info(["aura"], &cached).unwrap().len());
+        assert_eq!(3, info(["aura"], &cached).unwrap().len());
+        assert_eq!(1, *calls.lock().unwrap());
+
+        provides("aura", &cached).unwrap();
+        assert_eq!(2, *calls.lock().unwrap());
+
+        let stale = Cache::new(Duration::ZERO);
+        stale.fetch("x", &fetch).unwrap();
+        stale.fetch("x", &fetch).unwrap();
+        assert_eq!(4, *calls.lock().unwrap());
+    }
+
+    #[test]
+    fn package_json() {
+        let file = File::open("tests/faur.json").unwrap();
+        let pkgs: Vec<Package> = serde_json::from_reader(BufReader::new(file)).unwrap();
+        let json = serde_json::to_value(&pkgs[0]).unwrap();
+        let keys: Vec<_> = json
+            .as_object()
+            .unwrap()
+            .keys()
+            .map(|k| k.as_str())
+            .collect();
+
+        // The AUR RPC's own field names, which scripts may already expect.
+        let mut expt = vec![
+            "CheckDepends",
+            "Conflicts",
+            "Depends",
+            "Description",
+            "FirstSubmitted",
+            "Groups",
+            "ID",
+            "Keywords",
+            "LastModified",
+            "License",
+            "Maintainer",
+            "MakeDepends",
+            "Name",
+            "NumVotes",
+            "OptDepends",
+            "OutOfDate",
+            "PackageBase",
+            "PackageBaseID",
+            "Popularity",
+            "Provides",
+            "Replaces",
+            "URL",
+            "URLPath",
+            "Version",
+        ];
+        expt.sort();
+        assert_eq!(expt, keys);
+
+        // And it survives the round trip.
+        let back: Package = serde_json::from_value(json).unwrap();
+        assert_eq!(pkgs[0].name, back.name);
+        assert_eq!(pkgs[0].version, back.version);
+    }
+
     #[test]
     fn package_parse() {
         let file = File::open("tests/faur.json").unwrap();
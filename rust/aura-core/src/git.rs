@@ -5,7 +5,13 @@ use log::debug;
 use std::path::Path;
 use std::path::PathBuf;
 use std::process::Command;
+use std::process::Output;
 use std::process::Stdio;
+use std::time::Duration;
+
+/// The ref under which the commit last built from a repository is kept. Being a
+/// ref, the commit survives later pulls, even those of shallow clones.
+const BUILT_REF: &str = "refs/aura/built";
 
 /// A git-related error.
 #[derive(Debug)]
@@ -18,6 +24,8 @@ pub enum Error {
     Pull(PathBuf),
     /// A git diff failed.
     Diff(PathBuf),
+    /// A git clone or pull failed because the remote couldn't be reached.
+    Network(PathBuf),
     /// Converting a git hash to a Rust string failed.
     ReadHash(std::string::FromUtf8Error),
 }
@@ -30,50 +38,157 @@ impl std::fmt::Display for Error {
             Error::Pull(p) => write!(f, "A git pull failed: {}", p.display()),
             Error::ReadHash(e) => write!(f, "Reading a git hash into Rust failed: {e}"),
             Error::Diff(p) => write!(f, "A git diff failed: {}", p.display()),
+            Error::Network(p) => write!(f, "A git remote was unreachable: {}", p.display()),
         }
     }
 }
 
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Io(e) => Some(e),
+            Error::ReadHash(e) => Some(e),
+            Error::Clone(_) | Error::Pull(_) | Error::Diff(_) | Error::Network(_) => None,
+        }
+    }
+}
+
+/// How often, and how patiently, to retry git operations that failed because
+/// of the network.
+#[derive(Debug, Clone, Copy)]
+pub struct Retry {
+    /// Attempts to make after the first has failed. Zero never retries.
+    pub retries: u32,
+    /// The wait before the first retry, which doubles before each after it.
+    pub backoff: Duration,
+}
+
+impl Default for Retry {
+    fn default() -> Self {
+        Retry {
+            retries: 3,
+            backoff: Duration::from_millis(500),
+        }
+    }
+}
+
+/// Run some git operation, retrying it with exponential backoff for as long
+/// as it fails with [`Error::Network`] and the given policy allows. Any other
+/// failure, like a pull that can't fast-forward, is returned immediately.
+pub fn retry<T, F>(policy: Retry, mut op: F) -> Result<T, Error>
+where
+    F: FnMut() -> Result<T, Error>,
+{
+    let mut wait = policy.backoff;
+
+    for _ in 0..policy.retries {
+        match op() {
+            Err(Error::Network(p)) => {
+                debug!("Retrying {} in {:?}.", p.display(), wait);
+                std::thread::sleep(wait);
+                wait = wait.saturating_mul(2);
+            }
+            res => return res,
+        }
+    }
+
+    op()
+}
+
 // FIXME This seems to succeed for non-existant repos!
 /// Perform a shallow clone frrom a given repository url, and save it to a given
 /// `Path` on the filesystem.
 pub fn shallow_clone(url: &Path, target: &Path) -> Result<(), Error> {
+    clone(url, target, true)
+}
+
+/// Clone a given repository url to a given `Path` on the filesystem. A
+/// `shallow` clone fetches only the latest commit.
+pub fn clone(url: &Path, target: &Path, shallow: bool) -> Result<(), Error> {
     debug!("Cloning {}", url.display());
 
     Command::new("git")
         .arg("clone")
-        .arg("--depth=1")
+        .apply(|c| if shallow { c.arg("--depth=1") } else { c })
         .arg(url)
         .arg(target)
-        .stdout(Stdio::null())
-        .stderr(Stdio::null())
-        .status()
-        .map_err(Error::Io)?
-        .success()
-        .then_some(())
-        .ok_or_else(|| Error::Clone(url.to_path_buf()))
+        .env("LC_ALL", "C")
+        .stdin(Stdio::null())
+        .output()
+        .map_err(Error::Io)
+        .and_then(|out| outcome(out, url, Error::Clone))
+}
+
+/// Is the given git repository a shallow clone?
+pub fn is_shallow(dir: &Path) -> bool {
+    dir.join(".git").join("shallow").is_file()
 }
 
 /// Given a `Path` that is known to be a Git repository, visit it and pull the
 /// latest commits.
 ///
 /// Uses the `--ff-only` merge strategy, so the commit history can't have
-/// diverged from the `origin` or this will fail.
+/// diverged from the `origin` or this will fail. Shallow clones know too little
+/// history to fast-forward, so they instead fetch only the latest commit and
+/// move to it, keeping any uncommitted local changes.
 pub fn pull(dir: &Path) -> Result<(), Error> {
     debug!("Pulling {}", dir.display());
 
+    if is_shallow(dir) {
+        run(dir, &["fetch", "--quiet", "--depth=1"], Error::Pull)?;
+        run(
+            dir,
+            &["reset", "--quiet", "--keep", "FETCH_HEAD"],
+            Error::Pull,
+        )
+    } else {
+        run(dir, &["pull", "--quiet", "--ff-only"], Error::Pull)
+    }
+}
+
+/// Silently run some git command within a repository.
+fn run(dir: &Path, args: &[&str], err: fn(PathBuf) -> Error) -> Result<(), Error> {
     Command::new("git")
-        .arg("pull")
-        .arg("--quiet")
-        .arg("--ff-only")
-        .stdout(Stdio::null())
-        .stderr(Stdio::null())
+        .args(args)
+        .env("LC_ALL", "C")
+        .stdin(Stdio::null())
         .current_dir(dir)
-        .status()
-        .map_err(Error::Io)?
-        .success()
-        .then_some(())
-        .ok_or_else(|| Error::Pull(dir.to_path_buf()))
+        .output()
+        .map_err(Error::Io)
+        .and_then(|out| outcome(out, dir, err))
+}
+
+/// Judge the output of a finished git command, telling network failures apart
+/// from others by what git reported. The commands are run with `LC_ALL=C`, so
+/// that this report is always in English.
+///
+/// Failed HTTP requests are only considered network failures if the server
+/// itself erred, since say a `403` or `404` won't change on a retry.
+fn outcome(out: Output, path: &Path, err: fn(PathBuf) -> Error) -> Result<(), Error> {
+    const NETWORK: &[&str] = &[
+        "could not resolve host",
+        "temporary failure in name resolution",
+        "failed to connect",
+        "connection refused",
+        "connection reset",
+        "connection timed out",
+        "operation timed out",
+        "the requested url returned error: 5",
+        "the remote end hung up",
+        "early eof",
+    ];
+
+    if out.status.success() {
+        Ok(())
+    } else {
+        let stderr = String::from_utf8_lossy(&out.stderr).to_lowercase();
+
+        if NETWORK.iter().any(|n| stderr.contains(n)) {
+            Err(Error::Network(path.to_path_buf()))
+        } else {
+            Err(err(path.to_path_buf()))
+        }
+    }
 }
 
 /// Given a `Path` to a known local git repo, find out the hash of its latest
@@ -107,3 +222,233 @@ pub fn diff(dir: &Path, hash: &str) -> Result<(), Error> {
         .then_some(())
         .ok_or_else(|| Error::Diff(dir.to_path_buf()))
 }
+
+/// Record the current `HEAD` of a repository as the commit last built, so that
+/// [`diff_since_last`] has something to compare against in later runs.
+pub fn mark_built(dir: &Path) -> Result<(), Error> {
+    debug!("Marking {} as built", dir.display());
+    run(dir, &["update-ref", BUILT_REF, "HEAD"], Error::Diff)
+}
+
+/// Record the given commit as the one last built, unless one already is. This
+/// carries over a record kept elsewhere, as by older versions of Aura.
+pub fn seed_built(dir: &Path, commit: &str) -> Result<(), Error> {
+    let marked = run(
+        dir,
+        &["rev-parse", "--verify", "--quiet", BUILT_REF],
+        Error::Diff,
+    )
+    .is_ok();
+
+    if marked {
+        return Ok(());
+    }
+
+    debug!("Seeding {} as built at {}", dir.display(), commit);
+    run(dir, &["update-ref", BUILT_REF, commit], Error::Diff)
+}
+
+/// The textual diff between the commit last marked by [`mark_built`] and the
+/// current `HEAD`, perhaps to be shown in a pager before building an updated
+/// package. Empty if nothing has changed, or the repository was never built.
+pub fn diff_since_last(dir: &Path) -> Result<String, Error> {
+    debug!("git diff since last build: {}", dir.display());
+
+    if run(
+        dir,
+        &["rev-parse", "--verify", "--quiet", BUILT_REF],
+        Error::Diff,
+    )
+    .is_err()
+    {
+        return Ok(String::new());
+    }
+
+    let out = Command::new("git")
+        .arg("diff")
+        .arg("--no-color")
+        .arg(BUILT_REF)
+        .arg("HEAD")
+        .stderr(Stdio::null())
+        .current_dir(dir)
+        .output()
+        .map_err(Error::Io)?;
+
+    out.status
+        .success()
+        .then(|| String::from_utf8_lossy(&out.stdout).into_owned())
+        .ok_or_else(|| Error::Diff(dir.to_path_buf()))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /// Run a git command in the given directory, which must succeed.
+    fn git(dir: &Path, args: &[&str]) -> String {
+        let out = Command::new("git")
+            .args(["-c", "user.name=aura", "-c", "user.email=aura@localhost"])
+            .args(args)
+            .current_dir(dir)
+            .stderr(Stdio::null())
+            .output()
+            .unwrap();
+        assert!(out.status.success(), "git {:?} failed", args);
+        String::from_utf8(out.stdout).unwrap().trim().to_string()
+    }
+
+    fn commit(dir: &Path, n: usize) {
+        std::fs::write(dir.join("PKGBUILD"), format!("pkgrel={n}\n")).unwrap();
+        git(dir, &["add", "--all"]);
+        git(dir, &["commit", "--quiet", "--message", &n.to_string()]);
+    }
+
+    #[test]
+    fn shallow_clones() {
+        let root = std::env::temp_dir().join(format!("aura-shallow-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&root);
+        let work = root.join("work");
+        std::fs::create_dir_all(&work).unwrap();
+        git(&work, &["init", "--quiet"]);
+        commit(&work, 1);
+        commit(&work, 2);
+        git(&root, &["clone", "--quiet", "--bare", "work", "bare.git"]);
+
+        // Local paths ignore `--depth`, so the remote must be a proper url.
+        let url = PathBuf::from(format!("file://{}", root.join("bare.git").display()));
+        let full = root.join("full");
+        let shallow = root.join("shallow");
+        clone(&url, &full, false).unwrap();
+        shallow_clone(&url, &shallow).unwrap();
+        assert_eq!("2", git(&full, &["rev-list", "--count", "HEAD"]));
+        assert_eq!("1", git(&shallow, &["rev-list", "--count", "HEAD"]));
+        assert!(is_shallow(&shallow));
+        assert!(!is_shallow(&full));
+
+        // Pulling brings in new work without deepening the history.
+        commit(&work, 3);
+        git(&work, &["push", "--quiet", "../bare.git", "HEAD"]);
+        pull(&shallow).unwrap();
+        assert_eq!(hash(&work).unwrap(), hash(&shallow).unwrap());
+        assert_eq!("1", git(&shallow, &["rev-list", "--count", "HEAD"]));
+
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn network_failures() {
+        use std::os::unix::process::ExitStatusExt;
+        use std::process::ExitStatus;
+
+        let failed = |stderr: &str| Output {
+            status: ExitStatus::from_raw(128 << 8),
+            stdout: Vec::new(),
+            stderr: stderr.as_bytes().to_vec(),
+        };
+        let judge = |stderr: &str| outcome(failed(stderr), Path::new("aura"), Error::Clone);
+
+        let unreachable = "fatal: unable to access 'https://aur.archlinux.org/aura.git/': Could not resolve host: aur.archlinux.org";
+        let unavailable = "fatal: unable to access 'https://aur.archlinux.org/aura.git/': The requested URL returned error: 503";
+        let forbidden = "fatal: unable to access 'https://aur.archlinux.org/aura.git/': The requested URL returned error: 403";
+        let missing = "fatal: unable to access 'https://aur.archlinux.org/aura.git/': The requested URL returned error: 404";
+
+        assert!(matches!(judge(unreachable), Err(Error::Network(_))));
+        assert!(matches!(judge(unavailable), Err(Error::Network(_))));
+        assert!(matches!(judge(forbidden), Err(Error::Clone(_))));
+        assert!(matches!(judge(missing), Err(Error::Clone(_))));
+    }
+
+    #[test]
+    fn retries() {
+        let policy = Retry {
+            retries: 3,
+            backoff: Duration::from_millis(1),
+        };
+        let net = || Error::Network(PathBuf::from("aura"));
+
+        // Two network failures, then success.
+        let mut calls = 0;
+        let res = retry(policy, || {
+            calls += 1;
+            if calls < 3 {
+                Err(net())
+            } else {
+                Ok(calls)
+            }
+        });
+        assert_eq!(3, res.unwrap());
+
+        // Other failures are not retried.
+        let mut calls = 0;
+        let res: Result<(), _> = retry(policy, || {
+            calls += 1;
+            Err(Error::Pull(PathBuf::from("aura")))
+        });
+        assert!(matches!(res, Err(Error::Pull(_))));
+        assert_eq!(1, calls);
+
+        // Nor is anything, if the policy forbids it.
+        let mut calls = 0;
+        let none = Retry {
+            retries: 0,
+            ..policy
+        };
+        let res: Result<(), _> = retry(none, || {
+            calls += 1;
+            Err(net())
+        });
+        assert!(matches!(res, Err(Error::Network(_))));
+        assert_eq!(1, calls);
+
+        // An unreachable remote is recognised as such.
+        let root = std::env::temp_dir().join(format!("aura-unreachable-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&root);
+        let url = Path::new("http://127.0.0.1:9/aura.git");
+        let res = clone(url, &root, true);
+        assert!(matches!(res, Err(Error::Network(_))), "{:?}", res);
+    }
+
+    #[test]
+    fn diffs_since_last_build() {
+        let root = std::env::temp_dir().join(format!("aura-built-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&root);
+        std::fs::create_dir_all(&root).unwrap();
+        git(&root, &["init", "--quiet"]);
+        commit(&root, 1);
+        assert_eq!("", diff_since_last(&root).unwrap());
+
+        mark_built(&root).unwrap();
+        assert_eq!("", diff_since_last(&root).unwrap());
+
+        commit(&root, 2);
+        let diff = diff_since_last(&root).unwrap();
+        assert!(diff.contains("-pkgrel=1"));
+        assert!(diff.contains("+pkgrel=2"));
+
+        mark_built(&root).unwrap();
+        assert_eq!("", diff_since_last(&root).unwrap());
+
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn seeded_builds() {
+        let root = std::env::temp_dir().join(format!("aura-seeded-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&root);
+        std::fs::create_dir_all(&root).unwrap();
+        git(&root, &["init", "--quiet"]);
+        commit(&root, 1);
+        let old = hash(&root).unwrap();
+        commit(&root, 2);
+
+        seed_built(&root, &old).unwrap();
+        assert!(diff_since_last(&root).unwrap().contains("+pkgrel=2"));
+
+        // An existing mark is never replaced.
+        mark_built(&root).unwrap();
+        seed_built(&root, &old).unwrap();
+        assert_eq!("", diff_since_last(&root).unwrap());
+
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+}
@@ -0,0 +1,70 @@
+//! Interaction with the user's `gpg` keyring.
+//!
+//! AUR packages often sign their sources, and list the keys they trust in the
+//! `validpgpkeys` field of their `PKGBUILD`. `makepkg` refuses to build such a
+//! package unless those keys are already known locally.
+
+use log::debug;
+use std::ops::Not;
+use std::process::Command;
+use std::process::Stdio;
+
+/// A gpg-related error.
+#[derive(Debug)]
+pub enum Error {
+    /// Some IO action failed.
+    Io(std::io::Error),
+    /// Fetching some keys from a keyserver failed.
+    Import(Vec<String>),
+}
+
+impl std::fmt::Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Error::Io(e) => write!(f, "{}", e),
+            Error::Import(ks) => write!(f, "Importing PGP keys failed: {}", ks.join(", ")),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Io(e) => Some(e),
+            Error::Import(_) => None,
+        }
+    }
+}
+
+/// Is the given key, by fingerprint or ID, present in the user's keyring?
+pub fn is_known(key: &str) -> bool {
+    Command::new("gpg")
+        .arg("--list-keys")
+        .arg(key)
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .status()
+        .is_ok_and(|s| s.success())
+}
+
+/// Those of the given keys that aren't present in the user's keyring.
+pub fn missing<'a, I>(keys: I) -> Vec<&'a str>
+where
+    I: IntoIterator<Item = &'a str>,
+{
+    keys.into_iter().filter(|k| is_known(k).not()).collect()
+}
+
+/// Fetch the given keys from a keyserver into the user's keyring.
+pub fn import(keys: &[&str]) -> Result<(), Error> {
+    debug!("Importing PGP keys: {:?}", keys);
+
+    Command::new("gpg")
+        .arg("--recv-keys")
+        .args(keys)
+        .status()
+        .map_err(Error::Io)?
+        .success()
+        .then_some(())
+        .ok_or_else(|| Error::Import(keys.iter().map(|k| k.to_string()).collect()))
+}
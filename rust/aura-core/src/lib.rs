@@ -8,7 +8,9 @@ pub mod cache;
 pub mod deps;
 pub mod faur;
 pub mod git;
+pub mod gpg;
 pub mod logs;
+pub mod pacman;
 pub mod snapshot;
 
 use alpm::AlpmList;
@@ -20,6 +22,7 @@ use log::debug;
 use r2d2_alpm::Alpm;
 use std::borrow::Cow;
 use std::cmp::Ordering;
+use std::collections::HashSet;
 use std::fs::DirEntry;
 use std::path::Path;
 use versions::Versioning;
@@ -232,6 +235,56 @@ where
         .flatten()
 }
 
+/// The facts about an installed package that determine whether anything still
+/// needs it.
+trait Installed {
+    /// The name of the package.
+    fn pkg_name(&self) -> &str;
+
+    /// Why the package was installed.
+    fn install_reason(&self) -> PackageReason;
+
+    /// The names of other installed packages that require it, even optionally.
+    fn needed_by(&self) -> Vec<String>;
+
+    /// Does some other installed package require it, even optionally?
+    fn is_needed(&self) -> bool {
+        !self.needed_by().is_empty()
+    }
+
+    /// Was this installed as a dependency, but is no longer needed by
+    /// anything?
+    fn is_orphan(&self) -> bool {
+        self.install_reason() == PackageReason::Depend && !self.is_needed()
+    }
+
+    /// Was this installed explicitly, and is it needed by nothing else?
+    fn is_elderly(&self) -> bool {
+        self.install_reason() == PackageReason::Explicit && !self.is_needed()
+    }
+}
+
+impl Installed for alpm::Package {
+    fn pkg_name(&self) -> &str {
+        self.name()
+    }
+
+    fn install_reason(&self) -> PackageReason {
+        self.reason()
+    }
+
+    fn needed_by(&self) -> Vec<String> {
+        self.required_by()
+            .into_iter()
+            .chain(self.optional_for())
+            .collect()
+    }
+
+    fn is_needed(&self) -> bool {
+        !self.required_by().is_empty() || !self.optional_for().is_empty()
+    }
+}
+
 /// All orphaned packages.
 ///
 /// An orphan is a package that was installed as a dependency, but whose parent
@@ -240,11 +293,56 @@ pub fn orphans<A>(alpm: &A) -> impl Iterator<Item = &alpm::Package>
 where
     A: AsRef<alpm::Alpm>,
 {
-    alpm.as_ref().localdb().pkgs().into_iter().filter(|p| {
-        p.reason() == PackageReason::Depend
-            && p.required_by().is_empty()
-            && p.optional_for().is_empty()
-    })
+    alpm.as_ref()
+        .localdb()
+        .pkgs()
+        .into_iter()
+        .filter(|p| p.is_orphan())
+}
+
+/// Every package that would be removed by repeatedly removing all orphans until
+/// none remain, like running `pacman -Rns $(pacman -Qtdq)` to a fixpoint.
+/// Removing one orphan may orphan the dependencies that only it needed.
+///
+/// Results are sorted by name.
+pub fn removable_closure<A>(alpm: &A) -> Vec<String>
+where
+    A: AsRef<alpm::Alpm>,
+{
+    closure(alpm.as_ref().localdb().pkgs())
+}
+
+fn closure<'a, P, I>(pkgs: I) -> Vec<String>
+where
+    P: Installed + 'a,
+    I: IntoIterator<Item = &'a P>,
+{
+    // Only dependencies are ever candidates for removal.
+    let deps: Vec<(&str, Vec<String>)> = pkgs
+        .into_iter()
+        .filter(|p| p.install_reason() == PackageReason::Depend)
+        .map(|p| (p.pkg_name(), p.needed_by()))
+        .collect();
+    let mut removed: HashSet<&str> = HashSet::new();
+
+    loop {
+        let next: Vec<&str> = deps
+            .iter()
+            .filter(|(name, _)| !removed.contains(name))
+            .filter(|(_, needed)| needed.iter().all(|n| removed.contains(n.as_str())))
+            .map(|(name, _)| *name)
+            .collect();
+
+        if next.is_empty() {
+            break;
+        }
+
+        removed.extend(next);
+    }
+
+    let mut names: Vec<_> = removed.into_iter().map(|n| n.to_string()).collect();
+    names.sort();
+    names
 }
 
 /// All packages neither required nor optionally required by any other package,
@@ -256,11 +354,11 @@ pub fn elderly<A>(alpm: &A) -> impl Iterator<Item = &alpm::Package>
 where
     A: AsRef<alpm::Alpm>,
 {
-    alpm.as_ref().localdb().pkgs().into_iter().filter(|p| {
-        p.reason() == PackageReason::Explicit
-            && p.required_by().is_empty()
-            && p.optional_for().is_empty()
-    })
+    alpm.as_ref()
+        .localdb()
+        .pkgs()
+        .into_iter()
+        .filter(|p| p.is_elderly())
 }
 
 /// Does the given `Path` point to a valid tarball that can can loaded by ALPM?
@@ -317,3 +415,76 @@ where
         .map(|meta| meta.len())
         .sum()
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct Fixture {
+        name: &'static str,
+        reason: PackageReason,
+        required_by: Vec<&'static str>,
+        optional_for: Vec<&'static str>,
+    }
+
+    impl Installed for Fixture {
+        fn pkg_name(&self) -> &str {
+            self.name
+        }
+
+        fn install_reason(&self) -> PackageReason {
+            self.reason
+        }
+
+        fn needed_by(&self) -> Vec<String> {
+            self.required_by
+                .iter()
+                .chain(self.optional_for.iter())
+                .map(|n| n.to_string())
+                .collect()
+        }
+    }
+
+    #[test]
+    fn orphan_detection() {
+        let pkg = |reason, required_by, optional_for| Fixture {
+            name: "foo",
+            reason,
+            required_by,
+            optional_for,
+        };
+
+        assert!(pkg(PackageReason::Depend, vec![], vec![]).is_orphan());
+        assert!(!pkg(PackageReason::Depend, vec!["foo"], vec![]).is_orphan());
+        assert!(!pkg(PackageReason::Depend, vec![], vec!["foo"]).is_orphan());
+        assert!(!pkg(PackageReason::Explicit, vec![], vec![]).is_orphan());
+
+        assert!(pkg(PackageReason::Explicit, vec![], vec![]).is_elderly());
+        assert!(!pkg(PackageReason::Explicit, vec!["foo"], vec![]).is_elderly());
+        assert!(!pkg(PackageReason::Depend, vec![], vec![]).is_elderly());
+    }
+
+    #[test]
+    fn orphan_closure() {
+        let pkg = |name, reason, required_by: &[&'static str]| Fixture {
+            name,
+            reason,
+            required_by: required_by.to_vec(),
+            optional_for: vec![],
+        };
+
+        // `a` depends on `b`, which depends on `c`. `d` is also needed by the
+        // explicitly installed `e`, so it must stay.
+        let pkgs = vec![
+            pkg("a", PackageReason::Depend, &[]),
+            pkg("b", PackageReason::Depend, &["a"]),
+            pkg("c", PackageReason::Depend, &["b"]),
+            pkg("d", PackageReason::Depend, &["a", "e"]),
+            pkg("e", PackageReason::Explicit, &[]),
+            pkg("f", PackageReason::Explicit, &["a"]),
+        ];
+
+        assert_eq!(vec!["a", "b", "c"], closure(&pkgs));
+        assert!(closure(&pkgs[1..]).is_empty());
+    }
+}
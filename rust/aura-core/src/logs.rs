@@ -1,9 +1,13 @@
 //! Log manipulation internals.
 
+use serde::Serialize;
 use std::fs::File;
 use std::io::BufRead;
 use std::io::BufReader;
 use std::path::Path;
+use time::macros::format_description;
+use time::OffsetDateTime;
+use time::PrimitiveDateTime;
 
 /// A collation of information about log entries for a particular package.
 #[derive(Debug)]
@@ -18,6 +22,139 @@ pub struct LogEntry {
     pub recent: Vec<String>,
 }
 
+/// Something that ALPM did to a package.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Action {
+    /// The package was newly installed.
+    Installed,
+    /// The package was reinstalled at the same version.
+    Reinstalled,
+    /// The package was upgraded to a newer version.
+    Upgraded,
+    /// The package was downgraded to an older version.
+    Downgraded,
+    /// The package was removed.
+    Removed,
+}
+
+impl Action {
+    fn parse(word: &str) -> Option<Action> {
+        match word {
+            "installed" => Some(Action::Installed),
+            "reinstalled" => Some(Action::Reinstalled),
+            "upgraded" => Some(Action::Upgraded),
+            "downgraded" => Some(Action::Downgraded),
+            "removed" => Some(Action::Removed),
+            _ => None,
+        }
+    }
+}
+
+/// A single package transaction recorded in the Pacman log.
+#[derive(Debug, PartialEq, Eq, Serialize)]
+pub struct Transaction {
+    /// When the action occurred. Old log lines without an offset are assumed
+    /// to be UTC.
+    pub timestamp: OffsetDateTime,
+    /// What happened to the package.
+    pub action: Action,
+    /// The name of the package.
+    pub package: String,
+    /// The package's version after the action, or the removed version.
+    pub version: String,
+    /// For upgrades and downgrades, the version before the action.
+    pub previous: Option<String>,
+}
+
+impl Transaction {
+    /// Parse a single line of the Pacman log, if it describes a package
+    /// transaction.
+    ///
+    /// ```
+    /// use aura_core::logs::Action;
+    /// use aura_core::logs::Transaction;
+    ///
+    /// let line = "[2024-06-12T09:30:00+0900] [ALPM] upgraded aura (4.0.7-1 -> 4.0.8-1)";
+    /// let entry = Transaction::parse(line).unwrap();
+    /// assert_eq!(Action::Upgraded, entry.action);
+    /// assert_eq!("aura", entry.package);
+    /// assert_eq!("4.0.8-1", entry.version);
+    /// assert_eq!(Some("4.0.7-1".to_string()), entry.previous);
+    /// ```
+    pub fn parse(line: &str) -> Option<Transaction> {
+        let (stamp, rest) = line.strip_prefix('[')?.split_once("] ")?;
+        let timestamp = timestamp(stamp)?;
+        // Very old logs lack the `[ALPM]` tag.
+        let rest = rest.strip_prefix("[ALPM] ").unwrap_or(rest);
+        let (action, rest) = rest.split_once(' ')?;
+        let action = Action::parse(action)?;
+        let (package, versions) = rest.split_once(" (")?;
+        let versions = versions.strip_suffix(')')?;
+        let (previous, version) = match versions.split_once(" -> ") {
+            Some((old, new)) => (Some(old.to_string()), new),
+            None => (None, versions),
+        };
+
+        let entry = Transaction {
+            timestamp,
+            action,
+            package: package.to_string(),
+            version: version.to_string(),
+            previous,
+        };
+
+        Some(entry)
+    }
+}
+
+/// Parse either the modern `2024-06-12T09:30:00+0900` or the legacy
+/// `2019-01-02 10:11` timestamp formats.
+fn timestamp(stamp: &str) -> Option<OffsetDateTime> {
+    let modern = format_description!(
+        "[year]-[month]-[day]T[hour]:[minute]:[second][offset_hour sign:mandatory][offset_minute]"
+    );
+    let legacy = format_description!("[year]-[month]-[day] [hour]:[minute]");
+
+    OffsetDateTime::parse(stamp, modern).ok().or_else(|| {
+        PrimitiveDateTime::parse(stamp, legacy)
+            .ok()
+            .map(|dt| dt.assume_utc())
+    })
+}
+
+/// Every package transaction in the Pacman log at the given [`Path`], read
+/// lazily. Lines that aren't transactions or that can't be parsed are skipped,
+/// and a missing log yields nothing.
+pub fn entries(path: &Path) -> impl Iterator<Item = Transaction> {
+    File::open(path)
+        .ok()
+        .into_iter()
+        .flat_map(|file| BufReader::new(file).lines().map_while(Result::ok))
+        .filter_map(|line| Transaction::parse(&line))
+}
+
+/// The full transaction history of one package, in chronological order.
+pub fn history(path: &Path, package: &str) -> Vec<Transaction> {
+    let mut es: Vec<_> = entries(path).filter(|e| e.package == package).collect();
+    // The log is normally already in order, but timezone changes can upset
+    // that. The sort is stable, so same-instant entries keep their log order.
+    es.sort_by_key(|e| e.timestamp);
+
+    es
+}
+
+/// All transactions that occurred between `from` and `to`, inclusive. Bounds
+/// and log timestamps are compared as instants, so differing UTC offsets are
+/// accounted for.
+pub fn between(
+    path: &Path,
+    from: OffsetDateTime,
+    to: OffsetDateTime,
+) -> impl Iterator<Item = Transaction> {
+    entries(path).filter(move |e| e.timestamp >= from && e.timestamp <= to)
+}
+
 /// Given a [`Path`] to the Pacman log, form some `LogEntry` statistics about a
 /// particular package.
 pub fn info(path: &Path, package: String) -> Option<LogEntry> {
@@ -46,3 +183,106 @@ pub fn info(path: &Path, package: String) -> Option<LogEntry> {
         Some(entry)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use time::macros::datetime;
+
+    const LOG: &str = "\
+[2019-01-02 10:11] [ALPM] installed foo (1.0-1)
+[2024-06-12T09:30:00+0900] [PACMAN] Running 'pacman -Syu'
+[2024-06-12T09:30:01+0900] [ALPM] upgraded foo (1.0-1 -> 1:1.1-2)
+[2024-06-12T09:30:02+0900] [ALPM] downgraded bar (2.0-1 -> 1.9-1)
+[2024-06-12T09:30:03+0900] [ALPM] removed baz (3.0-1)
+[2024-06-12T09:30:04+0900] [ALPM] upgraded qux (garbage
+[2024-06-12T09:30:05+0900] [ALPM] transaction completed
+[2024-06-12T09:30:06+0900] [ALPM] reinstalled python-foo (0.1-1)
+not a log line at all
+";
+
+    #[test]
+    fn log_entries() {
+        let path = std::env::temp_dir().join(format!("aura-pacman-log-{}", std::process::id()));
+        std::fs::write(&path, LOG).unwrap();
+
+        let es: Vec<_> = entries(&path).collect();
+        let found: Vec<_> = es
+            .iter()
+            .map(|e| {
+                (
+                    e.action,
+                    e.package.as_str(),
+                    e.version.as_str(),
+                    e.previous.as_deref(),
+                )
+            })
+            .collect();
+
+        assert_eq!(
+            vec![
+                (Action::Installed, "foo", "1.0-1", None),
+                (Action::Upgraded, "foo", "1:1.1-2", Some("1.0-1")),
+                (Action::Downgraded, "bar", "1.9-1", Some("2.0-1")),
+                (Action::Removed, "baz", "3.0-1", None),
+                (Action::Reinstalled, "python-foo", "0.1-1", None),
+            ],
+            found
+        );
+        assert_eq!(datetime!(2019-01-02 10:11 UTC), es[0].timestamp);
+        assert_eq!(datetime!(2024-06-12 09:30:01 +9), es[1].timestamp);
+
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(0, entries(&path).count());
+    }
+
+    #[test]
+    fn package_history() {
+        let log = "\
+[2024-06-12T10:00:00+0900] [ALPM] installed foo (1.0-1)
+[2024-06-12T10:00:01+0900] [ALPM] installed bar (2.0-1)
+[2024-06-12T02:30:00+0200] [ALPM] upgraded foo (1.0-1 -> 1.1-1)
+[2024-06-12T01:30:00+0000] [ALPM] removed foo (1.1-1)
+";
+        let path = std::env::temp_dir().join(format!("aura-pacman-history-{}", std::process::id()));
+        std::fs::write(&path, log).unwrap();
+
+        // The second `foo` line is logged after a timezone change, but happened
+        // before the first.
+        let actions: Vec<_> = history(&path, "foo")
+            .into_iter()
+            .map(|e| e.action)
+            .collect();
+        assert_eq!(
+            vec![Action::Upgraded, Action::Installed, Action::Removed],
+            actions
+        );
+        assert!(history(&path, "baz").is_empty());
+
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn date_range() {
+        let path = std::env::temp_dir().join(format!("aura-pacman-between-{}", std::process::id()));
+        std::fs::write(&path, LOG).unwrap();
+
+        // Bounds fall exactly on the `upgraded` and `reinstalled` lines.
+        let from = datetime!(2024-06-12 00:30:01 UTC);
+        let to = datetime!(2024-06-12 00:30:06 UTC);
+        let found: Vec<_> = between(&path, from, to).map(|e| e.action).collect();
+
+        assert_eq!(
+            vec![
+                Action::Upgraded,
+                Action::Downgraded,
+                Action::Removed,
+                Action::Reinstalled,
+            ],
+            found
+        );
+        assert_eq!(0, between(&path, to, from).count());
+
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,129 @@
+//! The configuration of pacman itself.
+//!
+//! `pacman.conf` is read through `pacman-conf`, which expands `Include`
+//! directives and fills in defaults exactly as pacman does, so the two can
+//! never disagree.
+
+use crate::aur::Ignores;
+use std::path::PathBuf;
+
+/// The parts of `pacman.conf` that matter when resolving and installing
+/// packages.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PacmanConf {
+    /// The sync repositories, in the order pacman consults them.
+    pub repos: Vec<Repo>,
+    /// Packages never to be upgraded, as with `IgnorePkg`.
+    pub ignore_pkg: Vec<String>,
+    /// Groups whose packages are never to be upgraded, as with `IgnoreGroup`.
+    pub ignore_group: Vec<String>,
+    /// Where downloaded package tarballs are kept, as with `CacheDir`.
+    pub cache_dirs: Vec<PathBuf>,
+    /// The location of the package databases, as with `DBPath`.
+    pub db_path: PathBuf,
+}
+
+/// A sync repository, like `[extra]`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Repo {
+    /// The name of the repository.
+    pub name: String,
+    /// The servers it can be downloaded from.
+    pub servers: Vec<String>,
+}
+
+impl PacmanConf {
+    /// Read the system's `pacman.conf`, usually `/etc/pacman.conf`.
+    pub fn new() -> Result<Self, pacmanconf::Error> {
+        pacmanconf::Config::new().map(Self::from)
+    }
+
+    /// Read a `pacman.conf` at a specific location.
+    pub fn from_file<P>(path: P) -> Result<Self, pacmanconf::Error>
+    where
+        P: AsRef<std::ffi::OsStr>,
+    {
+        pacmanconf::Config::from_file(path).map(Self::from)
+    }
+
+    /// The names of all sync repositories.
+    pub fn repo_names(&self) -> impl Iterator<Item = &str> {
+        self.repos.iter().map(|r| r.name.as_str())
+    }
+
+    /// The packages and groups that pacman would never upgrade.
+    pub fn ignores(&self) -> Ignores {
+        Ignores {
+            pkgs: self.ignore_pkg.iter().cloned().collect(),
+            groups: self.ignore_group.iter().cloned().collect(),
+        }
+    }
+}
+
+impl From<pacmanconf::Config> for PacmanConf {
+    fn from(c: pacmanconf::Config) -> Self {
+        let repos = c
+            .repos
+            .into_iter()
+            .map(|r| Repo {
+                name: r.name,
+                servers: r.servers,
+            })
+            .collect();
+
+        PacmanConf {
+            repos,
+            ignore_pkg: c.ignore_pkg,
+            ignore_group: c.ignore_group,
+            cache_dirs: c.cache_dir.into_iter().map(PathBuf::from).collect(),
+            db_path: PathBuf::from(c.db_path),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::collections::HashSet;
+
+    #[test]
+    fn from_config() {
+        // As `pacman-conf` prints it, with every `Include` already expanded.
+        let raw = "\
+# General options
+[options]
+DBPath = /var/lib/pacman/
+CacheDir = /var/cache/pacman/pkg/
+CacheDir = /mnt/cache/
+IgnorePkg = linux
+IgnorePkg = firefox
+IgnoreGroup = gnome
+
+[core]
+Server = https://mirror.example.org/core/os/x86_64
+
+# A custom repository.
+[mine]
+Server = file:///home/aura/repo
+Server = https://example.org/mine
+";
+        let conf = PacmanConf::from(raw.parse::<pacmanconf::Config>().unwrap());
+
+        assert_eq!(vec!["core", "mine"], conf.repo_names().collect::<Vec<_>>());
+        assert_eq!(2, conf.repos[1].servers.len());
+        assert_eq!(vec!["linux", "firefox"], conf.ignore_pkg);
+        assert_eq!(vec!["gnome"], conf.ignore_group);
+        assert_eq!(
+            vec![
+                PathBuf::from("/var/cache/pacman/pkg/"),
+                PathBuf::from("/mnt/cache/")
+            ],
+            conf.cache_dirs
+        );
+        assert_eq!(PathBuf::from("/var/lib/pacman/"), conf.db_path);
+
+        let ignores = conf.ignores();
+        assert_eq!(HashSet::from(["gnome".to_string()]), ignores.groups);
+        assert!(ignores.ignores("linux", []));
+    }
+}
@@ -1,5 +1,6 @@
 //! Snapshot manipulation internals.
 
+use alpm::PackageReason;
 use r2d2_alpm::Alpm;
 use serde::Deserialize;
 use serde::Serialize;
@@ -7,13 +8,55 @@ use std::collections::HashMap;
 use std::collections::HashSet;
 use std::fs::File;
 use std::io::BufReader;
+use std::io::BufWriter;
 use std::path::Path;
 use std::path::PathBuf;
+use time::macros::format_description;
 use time::OffsetDateTime;
+use versions::Versioning;
+
+/// A snapshot-related error.
+#[derive(Debug)]
+pub enum Error {
+    /// The snapshot's timestamp couldn't be formatted into a filename.
+    TimeFormat(time::error::Format),
+    /// A snapshot file couldn't be created.
+    OpenFile(PathBuf, std::io::Error),
+    /// A snapshot couldn't be written as JSON.
+    JsonWrite(PathBuf, serde_json::Error),
+    /// A snapshot file couldn't be parsed.
+    Corrupt(PathBuf, serde_json::Error),
+    /// A snapshot file couldn't be deleted.
+    Remove(PathBuf, std::io::Error),
+}
+
+impl std::fmt::Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Error::TimeFormat(e) => write!(f, "{}", e),
+            Error::OpenFile(p, e) => write!(f, "Failed to open {}: {}", p.display(), e),
+            Error::JsonWrite(p, e) => write!(f, "Failed to write {}: {}", p.display(), e),
+            Error::Corrupt(p, e) => write!(f, "Failed to parse {}: {}", p.display(), e),
+            Error::Remove(p, e) => write!(f, "Failed to remove {}: {}", p.display(), e),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::TimeFormat(e) => Some(e),
+            Error::OpenFile(_, e) => Some(e),
+            Error::JsonWrite(_, e) => Some(e),
+            Error::Corrupt(_, e) => Some(e),
+            Error::Remove(_, e) => Some(e),
+        }
+    }
+}
 
 /// All packages installed at some specific [`DateTime`]. Any "pinned" snapshot
 /// should never be considered for deletion.
-#[derive(Serialize, Deserialize)]
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
 pub struct Snapshot {
     /// The local date and time of when this snapshot was taken.
     pub time: OffsetDateTime,
@@ -21,6 +64,11 @@ pub struct Snapshot {
     pub pinned: bool,
     /// Every package name in the `Snapshot`, with its version.
     pub packages: HashMap<String, String>,
+    /// The subset of `packages` that were installed as dependencies. The rest
+    /// were installed explicitly. Empty for snapshots taken by older versions
+    /// of Aura.
+    #[serde(default)]
+    pub dependencies: HashSet<String>,
 }
 
 impl Snapshot {
@@ -29,21 +77,52 @@ impl Snapshot {
     pub fn from_alpm(alpm: &Alpm) -> Snapshot {
         let time = OffsetDateTime::now_utc();
 
-        let packages = alpm
-            .as_ref()
-            .localdb()
-            .pkgs()
+        let pkgs = alpm.as_ref().localdb().pkgs();
+
+        let packages = pkgs
             .iter()
             .map(|p| (p.name().to_owned(), p.version().as_str().to_owned()))
             .collect();
 
+        let dependencies = pkgs
+            .iter()
+            .filter(|p| p.reason() == PackageReason::Depend)
+            .map(|p| p.name().to_owned())
+            .collect();
+
         Snapshot {
             time,
             pinned: false,
             packages,
+            dependencies,
         }
     }
 
+    /// Was the given package explicitly installed when this `Snapshot` was
+    /// taken?
+    pub fn is_explicit(&self, package: &str) -> bool {
+        self.packages.contains_key(package) && !self.dependencies.contains(package)
+    }
+
+    /// Write this `Snapshot` as JSON into the given directory, named by its
+    /// timestamp. Yields the path of the new file.
+    pub fn write(&self, snapshots_d: &Path) -> Result<PathBuf, Error> {
+        let form = format_description!(
+            "[year].[month]([month repr:short]).[day].[hour].[minute].[second]"
+        );
+        let name = format!(
+            "{}.json",
+            self.time.format(form).map_err(Error::TimeFormat)?
+        );
+        let path = snapshots_d.join(name);
+
+        let file = File::create(&path).map_err(|e| Error::OpenFile(path.clone(), e))?;
+        serde_json::to_writer(BufWriter::new(file), self)
+            .map_err(|e| Error::JsonWrite(path.clone(), e))?;
+
+        Ok(path)
+    }
+
     /// Does this `Snapshot` match what is currently installed?
     pub fn current(&self, alpm: &Alpm) -> bool {
         alpm.as_ref()
@@ -63,6 +142,191 @@ impl Snapshot {
     }
 }
 
+/// A summary of a saved [`Snapshot`].
+#[derive(Debug, PartialEq, Serialize)]
+pub struct SnapshotMeta {
+    /// The file the snapshot was read from.
+    pub path: PathBuf,
+    /// When the snapshot was taken.
+    pub time: OffsetDateTime,
+    /// Should this snapshot never be removed?
+    pub pinned: bool,
+    /// The number of packages in the snapshot.
+    pub packages: usize,
+}
+
+/// The package differences between two [`Snapshot`]s.
+#[derive(Debug, Default, PartialEq, Serialize)]
+pub struct SnapshotDiff {
+    /// Packages only in the second snapshot, with their versions.
+    pub added: HashMap<String, String>,
+    /// Packages only in the first snapshot, with their versions.
+    pub removed: HashMap<String, String>,
+    /// Packages in both, but at different versions, with the old and new.
+    pub changed: HashMap<String, (String, String)>,
+}
+
+/// The steps needed to return the system to the state of some [`Snapshot`].
+#[derive(Debug, Default, Serialize)]
+pub struct RestorePlan {
+    /// Packages in the snapshot that are no longer installed, with the tarballs
+    /// to install them from.
+    pub install: HashMap<String, PathBuf>,
+    /// Packages installed at a different version than in the snapshot (usually
+    /// a newer one), with the tarballs of the snapshot's version.
+    pub downgrade: HashMap<String, PathBuf>,
+    /// Installed packages that weren't present in the snapshot at all.
+    pub remove: HashSet<String>,
+    /// Packages whose snapshot version has no tarball in the cache, with that
+    /// version.
+    pub missing: HashMap<String, String>,
+}
+
+/// Compare the currently installed packages against a [`Snapshot`] to determine
+/// what must be installed, downgraded, and removed to restore it. Tarballs are
+/// sought in the given cache directory.
+pub fn restore_plan(snapshot: &Snapshot, alpm: &Alpm, cache_dir: &Path) -> RestorePlan {
+    let installed: HashMap<&str, &str> = alpm
+        .as_ref()
+        .localdb()
+        .pkgs()
+        .iter()
+        .map(|p| (p.name(), p.version().as_str()))
+        .collect();
+
+    plan(snapshot, &installed, cache_dir)
+}
+
+fn plan(snapshot: &Snapshot, installed: &HashMap<&str, &str>, cache_dir: &Path) -> RestorePlan {
+    let mut plan = RestorePlan::default();
+
+    for (name, ver) in snapshot.packages.iter() {
+        let current = installed.get(name.as_str());
+        if current.is_some_and(|v| same_version(v, ver)) {
+            continue;
+        }
+
+        let tarball =
+            Versioning::new(ver).and_then(|v| crate::cache::tarball_for(cache_dir, name, &v));
+
+        match (tarball, current) {
+            (None, _) => {
+                plan.missing.insert(name.clone(), ver.clone());
+            }
+            (Some(t), None) => {
+                plan.install.insert(name.clone(), t);
+            }
+            (Some(t), Some(_)) => {
+                plan.downgrade.insert(name.clone(), t);
+            }
+        }
+    }
+
+    plan.remove = installed
+        .keys()
+        .filter(|name| !snapshot.packages.contains_key(**name))
+        .map(|name| name.to_string())
+        .collect();
+
+    plan
+}
+
+/// What changed in the installed packages between snapshots `a` and `b`.
+pub fn diff(a: &Snapshot, b: &Snapshot) -> SnapshotDiff {
+    let mut diff = SnapshotDiff::default();
+
+    for (name, old) in a.packages.iter() {
+        match b.packages.get(name) {
+            None => {
+                diff.removed.insert(name.clone(), old.clone());
+            }
+            Some(new) if !same_version(old, new) => {
+                diff.changed
+                    .insert(name.clone(), (old.clone(), new.clone()));
+            }
+            Some(_) => {}
+        }
+    }
+
+    for (name, new) in b.packages.iter() {
+        if !a.packages.contains_key(name) {
+            diff.added.insert(name.clone(), new.clone());
+        }
+    }
+
+    diff
+}
+
+/// Do two version strings represent the same version?
+fn same_version(a: &str, b: &str) -> bool {
+    match (Versioning::new(a), Versioning::new(b)) {
+        (Some(x), Some(y)) => x.cmp(&y).is_eq(),
+        _ => a == b,
+    }
+}
+
+/// Take a [`Snapshot`] of all currently installed packages and save it into the
+/// given directory.
+pub fn save(alpm: &Alpm, snapshots_d: &Path) -> Result<Snapshot, Error> {
+    let snap = Snapshot::from_alpm(alpm);
+    snap.write(snapshots_d)?;
+
+    Ok(snap)
+}
+
+/// A summary of every snapshot file in the given directory, newest-first.
+/// Files that couldn't be parsed are reported separately, and don't prevent the
+/// others from being listed.
+pub fn list(snapshots_d: &Path) -> (Vec<SnapshotMeta>, Vec<Error>) {
+    let mut metas = Vec::new();
+    let mut corrupt = Vec::new();
+
+    let paths = snapshots_d
+        .read_dir()
+        .into_iter()
+        .flatten()
+        .filter_map(|r| r.ok())
+        .map(|entry| entry.path())
+        .filter(|p| p.extension().is_some_and(|ext| ext == "json"));
+
+    for path in paths {
+        let parsed = File::open(&path)
+            .map_err(serde_json::Error::io)
+            .and_then(|f| serde_json::from_reader::<_, Snapshot>(BufReader::new(f)));
+
+        match parsed {
+            Ok(snap) => metas.push(SnapshotMeta {
+                path,
+                time: snap.time,
+                pinned: snap.pinned,
+                packages: snap.packages.len(),
+            }),
+            Err(e) => corrupt.push(Error::Corrupt(path, e)),
+        }
+    }
+
+    metas.sort_by_key(|meta| std::cmp::Reverse(meta.time));
+
+    (metas, corrupt)
+}
+
+/// Delete all but the newest `keep` snapshots in the given directory. Pinned
+/// and unparseable snapshots are never deleted, nor do they count towards
+/// `keep`. Yields the paths of the removed files.
+pub fn prune(snapshots_d: &Path, keep: usize) -> Result<Vec<PathBuf>, Error> {
+    let (metas, _) = list(snapshots_d);
+
+    metas
+        .into_iter()
+        .filter(|meta| !meta.pinned)
+        .skip(keep)
+        .map(|meta| match std::fs::remove_file(&meta.path) {
+            Ok(()) => Ok(meta.path),
+            Err(e) => Err(Error::Remove(meta.path, e)),
+        })
+        .collect()
+}
+
 /// An iterator of all legal [`Snapshot`]s.
 pub fn snapshots(snapshots_d: &Path) -> impl Iterator<Item = Snapshot> {
     snapshots_with_paths(snapshots_d).map(|(_, s)| s)
@@ -86,3 +350,183 @@ pub fn snapshots_with_paths(snapshots_d: &Path) -> impl Iterator<Item = (PathBuf
                 .map(|s| (p, s))
         })
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trip() {
+        let dir = std::env::temp_dir().join(format!("aura-snapshots-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+
+        let snap = Snapshot {
+            time: OffsetDateTime::from_unix_timestamp(1_700_000_000).unwrap(),
+            pinned: true,
+            packages: HashMap::from([
+                ("aura".to_string(), "4.0.8-1".to_string()),
+                ("alpm".to_string(), "1:15.0.0-1".to_string()),
+            ]),
+            dependencies: HashSet::from(["alpm".to_string()]),
+        };
+
+        let path = snap.write(&dir).unwrap();
+        assert_eq!(
+            Some("2023.11(Nov).14.22.13.20.json"),
+            path.file_name().and_then(|f| f.to_str())
+        );
+        assert!(snap.is_explicit("aura"));
+        assert!(!snap.is_explicit("alpm"));
+        assert!(!snap.is_explicit("pacman"));
+
+        let read: Vec<_> = snapshots_with_paths(&dir).collect();
+        assert_eq!(vec![(path, snap)], read);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn older_format() {
+        let json =
+            r#"{"time":[2023,318,22,13,20,0,0,0,0],"pinned":false,"packages":{"aura":"4.0.8-1"}}"#;
+        let snap: Snapshot = serde_json::from_str(json).unwrap();
+
+        assert!(snap.dependencies.is_empty());
+        assert!(snap.is_explicit("aura"));
+    }
+
+    #[test]
+    fn restoring() {
+        let cache = std::env::temp_dir().join(format!("aura-restore-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&cache);
+        std::fs::create_dir_all(&cache).unwrap();
+
+        for file in [
+            "foo-1.0-1-x86_64.pkg.tar.zst",
+            "foo-1.1-1-x86_64.pkg.tar.zst",
+            "bar-2.0-1-any.pkg.tar.zst",
+        ] {
+            std::fs::write(cache.join(file), "").unwrap();
+        }
+
+        let snap = Snapshot {
+            time: OffsetDateTime::from_unix_timestamp(1_700_000_000).unwrap(),
+            pinned: false,
+            packages: HashMap::from([
+                ("foo".to_string(), "1.0-1".to_string()),
+                ("bar".to_string(), "2.0-1".to_string()),
+                ("baz".to_string(), "3.0-1".to_string()),
+                ("qux".to_string(), "0.1-1".to_string()),
+                ("same".to_string(), "1.0-1".to_string()),
+            ]),
+            dependencies: HashSet::new(),
+        };
+        let installed = HashMap::from([
+            ("foo", "1.1-1"),
+            ("baz", "3.1-1"),
+            ("same", "1.0-1"),
+            ("extra", "1.0-1"),
+        ]);
+
+        let plan = plan(&snap, &installed, &cache);
+
+        assert_eq!(
+            HashMap::from([(
+                "foo".to_string(),
+                cache.join("foo-1.0-1-x86_64.pkg.tar.zst")
+            )]),
+            plan.downgrade
+        );
+        assert_eq!(
+            HashMap::from([("bar".to_string(), cache.join("bar-2.0-1-any.pkg.tar.zst"))]),
+            plan.install
+        );
+        assert_eq!(
+            HashMap::from([
+                ("baz".to_string(), "3.0-1".to_string()),
+                ("qux".to_string(), "0.1-1".to_string()),
+            ]),
+            plan.missing
+        );
+        assert_eq!(HashSet::from(["extra".to_string()]), plan.remove);
+
+        std::fs::remove_dir_all(&cache).unwrap();
+    }
+
+    #[test]
+    fn listing_and_pruning() {
+        let dir = std::env::temp_dir().join(format!("aura-snapshot-list-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+
+        let paths: Vec<_> = [(1, false, 1), (2, true, 2), (3, false, 3), (4, false, 4)]
+            .into_iter()
+            .map(|(day, pinned, count)| {
+                let snap = Snapshot {
+                    time: OffsetDateTime::from_unix_timestamp(day * 86_400).unwrap(),
+                    pinned,
+                    packages: (0..count)
+                        .map(|n| (format!("pkg{}", n), "1.0-1".to_string()))
+                        .collect(),
+                    dependencies: HashSet::new(),
+                };
+                snap.write(&dir).unwrap()
+            })
+            .collect();
+        let bad = dir.join("broken.json");
+        std::fs::write(&bad, "{\"time\":").unwrap();
+
+        let (metas, corrupt) = list(&dir);
+        let summary: Vec<_> = metas.iter().map(|m| (&m.path, m.packages)).collect();
+        assert_eq!(
+            vec![
+                (&paths[3], 4),
+                (&paths[2], 3),
+                (&paths[1], 2),
+                (&paths[0], 1)
+            ],
+            summary
+        );
+        assert!(metas[2].pinned);
+        assert!(matches!(corrupt.as_slice(), [Error::Corrupt(p, _)] if p == &bad));
+
+        // The pinned snapshot survives, despite being older.
+        let removed = prune(&dir, 1).unwrap();
+        assert_eq!(vec![paths[2].clone(), paths[0].clone()], removed);
+
+        let (metas, corrupt) = list(&dir);
+        let left: Vec<_> = metas.into_iter().map(|m| m.path).collect();
+        assert_eq!(vec![paths[3].clone(), paths[1].clone()], left);
+        assert_eq!(1, corrupt.len());
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn diffing() {
+        let snap = |packages: &[(&str, &str)]| Snapshot {
+            time: OffsetDateTime::from_unix_timestamp(1_700_000_000).unwrap(),
+            pinned: false,
+            packages: packages
+                .iter()
+                .map(|(n, v)| (n.to_string(), v.to_string()))
+                .collect(),
+            dependencies: HashSet::new(),
+        };
+        let a = snap(&[("foo", "1.0-1"), ("bar", "2.0-1"), ("same", "1.0-1")]);
+        let b = snap(&[("foo", "1.1-1"), ("baz", "3.0-1"), ("same", "1.0-1")]);
+
+        let expected = SnapshotDiff {
+            added: HashMap::from([("baz".to_string(), "3.0-1".to_string())]),
+            removed: HashMap::from([("bar".to_string(), "2.0-1".to_string())]),
+            changed: HashMap::from([(
+                "foo".to_string(),
+                ("1.0-1".to_string(), "1.1-1".to_string()),
+            )]),
+        };
+
+        assert_eq!(expected, diff(&a, &b));
+        assert_eq!(SnapshotDiff::default(), diff(&a, &a));
+    }
+}
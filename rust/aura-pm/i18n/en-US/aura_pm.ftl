@@ -4,8 +4,13 @@ language-name = English
 A-install-deps = Determining dependencies...
 A-install-repo-pkgs = Repository dependencies:
 A-install-aur-pkgs = AUR packages:
+A-plan-satisfied = Already installed:
 A-install-path-comp = Failed to extract final component of: { $path }
 A-install-ignored = { $file } is marked "ignored". Install anyway?
+A-install-pgp-missing = Some sources are signed by PGP keys that aren't in your keyring:
+A-install-pgp-import = Import them?
+A-install-pgp-skipped = Not importing them without confirmation. Their sources will fail to verify.
+A-install-migrated = These packages are now in the official repositories. Consider reinstalling them from there:
 
 A-build-prep = Preparing build directories...
 A-build-pkg = Building { $pkg }...
@@ -15,6 +20,7 @@ A-build-hotedit-install = Edit the .install file?
 A-build-fail = Package failed to build, citing:
 A-build-e-pkgctl = Building within an isolated chroot failed.
 A-build-e-makepkg = makepkg failed.
+A-build-e-flag = Aura already controls this makepkg flag: { $flag }
 A-build-e-edit = Failed to edit: { $file }
 A-build-e-tarball = Failed to move: { $file }
 A-build-e-filename = Failed to extract filename from: { $file }
@@ -49,6 +55,7 @@ A-u-comparing = Comparing package versions...
 A-u-no-upgrades = No AUR package upgrades necessary.
 A-u-to-upgrade = AUR packages to upgrade:
 A-u-git = VCS packages to rebuild:
+A-u-ignored = Ignoring these packages, as set in pacman.conf:
 
 A-w = Cloning { $package }...
 
@@ -91,6 +98,9 @@ L-recent = Recent Actions
 L-search-err = Searching your logs via { $cmd } failed.
 L-view-err = Failed to open your ALPM log.
 
+# Orphans (-O)
+O-adopt-missing = These packages aren't installed: { $pkgs }
+
 # Opening Pages (open)
 open-err = Failed to open { $url }.
 
@@ -189,6 +199,7 @@ pacman-misc = A call to pacman gave a non-zero exit code.
 dir-mkdir = Failed to create the directory: { $dir }.
 dir-home = Unable to determine Aura's config directory.
 dir-cache = Unable to determine Aura's cache directory.
+dir-clones = Unable to prepare the directory of AUR package clones.
 
 # Dependency Resolution
 dep-exist = The package { $pkg } does not exist.
@@ -196,14 +207,29 @@ dep-exist-par = The dependency { $pkg } of { $par } does not exist.
 dep-graph = The dependency graph was somehow malformed.
 dep-cycle = Dependency cycle detected: { $cycle }
 dep-multi = There were multiple errors during dependency resolution.
+dep-json = Failed to render the installation plan as JSON.
+dep-repo = The package { $pkg } is only available from the disallowed repository { $repo }.
+dep-drift = The local clone of { $pkg } no longer matches its pinned manifest entry.
+dep-ambiguous = { $pkg } is provided by multiple packages: { $cands }
+dep-base = The clone directory { $dir } holds the differently named { $base }.
+dep-audit = The PKGBUILD of { $pkg } was rejected: { $reason }
+dep-timeout = Parsing { $file } took too long.
+dep-generate = Failed to generate a .SRCINFO within { $dir }.
+dep-conflict = { $a } and { $b } conflict with each other.
+dep-build = The package { $pkg } failed to build.
 
 # Git Operations
 git-diff = A git diff failed for: { $file }
 git-hash = Reading a git hash into Rust failed.
 git-pull = A git pull failed: { $dir }
 git-clone = A git clone failed: { $dir }
+git-network = A git remote couldn't be reached: { $dir }
 git-io = Calling git somehow failed.
 
+# GPG
+gpg-io = Calling gpg somehow failed.
+gpg-import = Importing PGP keys failed: { $keys }
+
 # Faur Calls
 faur-fetch = Calling the metadata server utterly failed: { $pkg }
 faur-unknown = Unknown package: { $pkg }
@@ -217,6 +243,7 @@ err-file-del = Failed to delete: { $file }
 err-file-open = Failed to open file handle to: { $file }
 err-file-write = Failed to write file: { $file }
 err-json-decode = Failed to decode JSON from: { $url }
+err-json-read = Failed to read JSON from: { $file }
 err-json-write = Failed to write JSON to: { $file }
 err-mutex = A mutex was poisoned.
 err-pool-create = Failed to create an ALPM connection pool.
@@ -226,6 +253,7 @@ err-srcinfo = Failed to parse .SRCINFO: { $file }
 err-sudo = Running Aura with sudo is not necessary.
 err-time-conv = Failed to convert a timestamp.
 err-time-format = Failed to format a time string.
+err-timeout = No response was received in time from: { $url }
 err-user-input = Failed to get user input.
 err-utf8 = A UTF-8 conversion failed.
 err-write = Somehow failed to write to stdout.
@@ -15,20 +15,25 @@ use crate::utils::ResultVoid;
 use crate::utils::NOTHING;
 use crate::yellow;
 use applying::Apply;
+use aura_core::aur::dependencies::DepKinds;
 use aura_core::aur::dependencies::Resolution;
-use aura_core::Package;
+use aura_core::aur::dependencies::ResolveOpts;
+use aura_core::faur::FaurClient;
+use aura_core::git::Retry;
 use colored::ColoredString;
 use colored::Colorize;
 use i18n_embed::fluent::FluentLanguageLoader;
 use i18n_embed::LanguageLoader;
 use i18n_embed_fl::fl;
+use itertools::Itertools;
 use linya::Progress;
 use log::debug;
 use log::error;
 use log::info;
+use r2d2::Pool;
 use r2d2_alpm::Alpm;
+use r2d2_alpm::AlpmManager;
 use rayon::prelude::*;
-use srcinfo::Srcinfo;
 use std::collections::HashSet;
 use std::fs::File;
 use std::io::BufRead;
@@ -40,6 +45,7 @@ use std::path::Path;
 use std::path::PathBuf;
 use std::process::Command;
 use std::sync::Mutex;
+use std::time::Duration;
 use time::OffsetDateTime;
 use validated::Validated;
 
@@ -49,15 +55,13 @@ pub(crate) enum Error {
     Backup(crate::command::snapshot::Error),
     Fetch(crate::fetch::Error),
     Git(aura_core::git::Error),
+    Gpg(aura_core::gpg::Error),
     Build(build::Error),
     Deps(aura_core::aur::dependencies::Error<crate::fetch::Error>),
     Pacman(crate::pacman::Error),
     Env(crate::env::Error),
     Aur(aura_core::aur::Error),
-    Srcinfo(PathBuf, srcinfo::Error),
-    PathComponent(PathBuf),
     FileOpen(PathBuf, std::io::Error),
-    FileWrite(PathBuf, std::io::Error),
     DateConv(time::error::ComponentRange),
     NoPackages,
     Cancelled,
@@ -71,15 +75,13 @@ impl Nested for Error {
         match self {
             Error::Fetch(e) => e.nested(),
             Error::Git(e) => e.nested(),
+            Error::Gpg(e) => e.nested(),
             Error::Build(e) => e.nested(),
             Error::Deps(e) => e.nested(),
             Error::Pacman(e) => e.nested(),
             Error::Env(e) => e.nested(),
             Error::Aur(e) => e.nested(),
-            Error::Srcinfo(_, e) => error!("{e}"),
-            Error::PathComponent(_) => {}
             Error::FileOpen(_, e) => error!("{e}"),
-            Error::FileWrite(_, e) => error!("{e}"),
             Error::NoPackages => {}
             Error::Cancelled => {}
             Error::Stdout => {}
@@ -96,18 +98,16 @@ impl Localised for Error {
         match self {
             Error::Fetch(e) => e.localise(fll),
             Error::Git(e) => e.localise(fll),
+            Error::Gpg(e) => e.localise(fll),
             Error::Build(e) => e.localise(fll),
             Error::Deps(e) => e.localise(fll),
             Error::Pacman(e) => e.localise(fll),
             Error::Env(e) => e.localise(fll),
             Error::Aur(e) => e.localise(fll),
-            Error::Srcinfo(p, _) => fl!(fll, "err-srcinfo", file = p.utf8()),
-            Error::PathComponent(p) => fl!(fll, "A-install-path-comp", path = p.utf8()),
             Error::Cancelled => fl!(fll, "common-cancelled"),
             Error::NoPackages => fl!(fll, "common-no-packages"),
             Error::Stdout => fl!(fll, "err-write"),
             Error::FileOpen(p, _) => fl!(fll, "err-file-open", file = p.utf8()),
-            Error::FileWrite(p, _) => fl!(fll, "err-file-write", file = p.utf8()),
             Error::DateConv(_) => fl!(fll, "err-time-conv"),
             Error::Backup(e) => e.localise(fll),
             Error::ReadDir(p, _) => fl!(fll, "err-read-dir", dir = p.utf8()),
@@ -123,13 +123,23 @@ pub(crate) enum Mode {
 }
 
 /// View AUR package information.
-pub(crate) fn info(fll: &FluentLanguageLoader, packages: &[String]) -> Result<(), Error> {
+pub(crate) fn info(
+    fll: &FluentLanguageLoader,
+    faur: &FaurClient,
+    packages: &[String],
+    json: bool,
+) -> Result<(), Error> {
     info!("-Ai on {:?}", packages);
-    let r: Vec<aura_core::faur::Package> = aura_core::faur::info(
-        packages.iter().map(|s| s.as_str()),
-        &crate::fetch::fetch_json,
-    )
-    .map_err(Error::Fetch)?;
+    let r: Vec<aura_core::faur::Package> = faur
+        .info(
+            packages.iter().map(|s| s.as_str()),
+            &crate::fetch::fetch_json,
+        )
+        .map_err(Error::Fetch)?;
+
+    if json {
+        return crate::utils::json(&r).map_err(|_| Error::Stdout);
+    }
     let mut w = BufWriter::new(std::io::stdout());
 
     let repo = fl!(fll, "A-i-repo");
@@ -210,36 +220,41 @@ pub(crate) fn info(fll: &FluentLanguageLoader, packages: &[String]) -> Result<()
     Ok(())
 }
 
+#[allow(clippy::too_many_arguments)]
 pub(crate) fn provides<S>(
     alpm: &Alpm,
+    faur: &FaurClient,
     alpha: bool,
     rev: bool,
     limit: Option<usize>,
     quiet: bool,
+    json: bool,
     providing: S,
 ) -> Result<(), Error>
 where
     S: AsRef<str>,
 {
-    let mut matches: Vec<aura_core::faur::Package> =
-        aura_core::faur::provides(providing, &crate::fetch::fetch_json).map_err(Error::Fetch)?;
+    let mut matches: Vec<aura_core::faur::Package> = faur
+        .provides(providing, &crate::fetch::fetch_json)
+        .map_err(Error::Fetch)?;
 
     matches.sort_by(|a, b| a.name.cmp(&b.name));
 
-    render_search(alpm, alpha, rev, limit, quiet, matches);
-
-    Ok(())
+    render_search(alpm, alpha, rev, limit, quiet, json, matches)
 }
 
 /// Search the AUR via a search string.
 ///
 /// Thanks to `clap`, the `terms` slice is guaranteed to be non-empty.
+#[allow(clippy::too_many_arguments)]
 pub(crate) fn search(
     alpm: &Alpm,
+    faur: &FaurClient,
     alpha: bool,
     rev: bool,
     limit: Option<usize>,
     quiet: bool,
+    json: bool,
     terms: Vec<String>,
 ) -> Result<(), Error> {
     debug!("Searching for: {:?}", terms);
@@ -253,17 +268,16 @@ pub(crate) fn search(
 
     debug!("Sanitized terms: {:?}", cleaned);
 
-    let matches: Vec<aura_core::faur::Package> = aura_core::faur::search(
-        cleaned.iter().map(|s| s.as_str()),
-        &crate::fetch::fetch_json,
-    )
-    .map_err(Error::Fetch)?;
+    let matches: Vec<aura_core::faur::Package> = faur
+        .search(
+            cleaned.iter().map(|s| s.as_str()),
+            &crate::fetch::fetch_json,
+        )
+        .map_err(Error::Fetch)?;
 
     debug!("Search matches: {}", matches.len());
 
-    render_search(alpm, alpha, rev, limit, quiet, matches);
-
-    Ok(())
+    render_search(alpm, alpha, rev, limit, quiet, json, matches)
 }
 
 /// Render some search results. Orders by vote count by default.
@@ -273,8 +287,9 @@ fn render_search(
     rev: bool,
     limit: Option<usize>,
     quiet: bool,
+    json: bool,
     mut matches: Vec<aura_core::faur::Package>,
-) {
+) -> Result<(), Error> {
     let db = alpm.alpm.localdb();
     let rep = "aur/".magenta();
 
@@ -289,6 +304,11 @@ fn render_search(
     }
     let to_take = limit.unwrap_or(matches.len());
 
+    if json {
+        matches.truncate(to_take);
+        return crate::utils::json(&matches).map_err(|_| Error::Stdout);
+    }
+
     for p in matches.into_iter().take(to_take) {
         if quiet {
             println!("{}", p.name);
@@ -310,11 +330,13 @@ fn render_search(
             println!("    {}", p.description.unwrap_or_default());
         }
     }
+
+    Ok(())
 }
 
 /// View a package's PKGBUILD.
-pub(crate) fn pkgbuild(pkg: &str, clone_d: &Path) -> Result<(), Error> {
-    let path = aura_core::aur::clone_path_of_pkgbase(clone_d, pkg, &crate::fetch::fetch_json)
+pub(crate) fn pkgbuild(pkg: &str, clone_d: &Path, faur: &FaurClient) -> Result<(), Error> {
+    let path = aura_core::aur::clone_path_of_pkgbase(clone_d, pkg, faur, &crate::fetch::fetch_json)
         .map_err(Error::Aur)?
         .join("PKGBUILD");
 
@@ -362,7 +384,10 @@ pub(crate) fn clone_aur_repos(fll: &FluentLanguageLoader, pkgs: &[String]) -> Re
         .map(|p| {
             let pkg = p.as_str();
             aura!(fll, "A-w", package = pkg);
-            aura_core::aur::clone_aur_repo(None, p).void()
+            aura_core::git::retry(Retry::default(), || {
+                aura_core::aur::clone_aur_repo(None, p, true)
+            })
+            .void()
         })
         .collect::<Result<(), aura_core::git::Error>>()
         .map_err(Error::Git)?;
@@ -391,7 +416,7 @@ pub(crate) fn refresh(fll: &FluentLanguageLoader, clone_d: &Path) -> Result<(),
     if let Validated::Fail(errors) = uniques
         .into_par_iter()
         .map(|path| {
-            let res = aura_core::git::pull(&path);
+            let res = aura_core::git::retry(Retry::default(), || aura_core::git::pull(&path));
             progress.lock().unwrap().inc_and_draw(&pull_bar, 1);
             res
         })
@@ -468,14 +493,11 @@ fn install_work(
     let rslv = if env.aur.skipdepcheck {
         Resolution::build_these(pkgs)
     } else {
-        aura_core::aur::dependencies::resolve(
-            pool,
-            &crate::fetch::fetch_json,
-            &env.aur.clones,
-            env.aur.nocheck,
-            pkgs,
-        )
-        .map_err(Error::Deps)?
+        // Split packages and shared dependencies are often looked up repeatedly.
+        let cache = aura_core::faur::Cache::new(Duration::from_secs(300));
+        let fetch = |url: &str| cache.fetch(url, &crate::fetch::fetch_json);
+        let opts = resolve_opts(env, pool, &fetch);
+        aura_core::aur::dependencies::resolve(&opts, pkgs).map_err(Error::Deps)?
     };
 
     debug!("Satisfied: {:?}", rslv.satisfied);
@@ -501,17 +523,61 @@ fn install_work(
     aura!(fll, "A-install-aur-pkgs");
     to_build.iter().for_each(|p| println!(" {p}"));
 
+    // --- Former AUR packages now in the official repos --- //
+    if rslv.migrated.is_empty().not() {
+        yellow!(fll, "A-install-migrated");
+        rslv.migrated.iter().for_each(|p| println!(" {}", p.cyan()));
+    }
+
+    // --- Signed sources need their keys to be known --- //
+    let missing_keys: Vec<&str> = rslv
+        .pgp_keys
+        .values()
+        .flatten()
+        .map(|k| k.as_str())
+        .unique()
+        .apply(aura_core::gpg::missing);
+    if missing_keys.is_empty().not() {
+        yellow!(fll, "A-install-pgp-missing");
+        missing_keys.iter().for_each(|k| println!(" {k}"));
+    }
+
     if env.aur.noconfirm.not() {
         // Proceed if the user accepts.
         proceed!(fll, env, "proceed").ok_or(Error::Cancelled)?;
     }
 
+    // Keys are never trusted without asking, so makepkg will refuse to build
+    // their sources instead.
+    if missing_keys.is_empty().not() {
+        if env.general.noconfirm {
+            yellow!(fll, "A-install-pgp-skipped");
+        } else if proceed!(fll, env, "A-install-pgp-import").is_some() {
+            aura_core::gpg::import(&missing_keys).map_err(Error::Gpg)?;
+        }
+    }
+
     if matches!(mode, Mode::Upgrade) && env.backups.automatic {
         let alpm = env.alpm().map_err(Error::Env)?;
         crate::command::snapshot::save(fll, &alpm, env.backups.snapshots.as_path())
             .map_err(Error::Backup)?;
     }
 
+    // --- Only the requested members of split packages are installed --- //
+    let members: Vec<String> = to_build
+        .iter()
+        .flat_map(|b| {
+            if env.aur.asdeps {
+                b.members.iter().map(|m| m.as_str()).collect()
+            } else {
+                b.requested_members(pkgs)
+            }
+        })
+        .map(|m| m.to_string())
+        .collect();
+    let members: HashSet<&str> = members.iter().map(|m| m.as_str()).collect();
+    debug!("Members to install: {:?}", members);
+
     // --- Determine the best build order --- //
     let is_single = to_build.len() == 1;
     let order: Vec<Vec<String>> =
@@ -543,7 +609,7 @@ fn install_work(
             &alpm,
             &env.general.editor,
             is_single,
-            pkgs,
+            &members,
             clone_paths,
         )
         .map_err(Error::Build)?;
@@ -571,8 +637,9 @@ fn install_work(
                 .map_err(Error::Pacman)?;
 
             builts
-                .into_iter()
-                .try_for_each(|b| update_hash(&env.aur.hashes, &b.clone))?;
+                .iter()
+                .try_for_each(|b| aura_core::git::mark_built(&b.clone))
+                .map_err(Error::Git)?;
         }
     }
 
@@ -580,16 +647,68 @@ fn install_work(
     Ok(())
 }
 
-fn update_hash(hashes: &Path, clone: &Path) -> Result<(), Error> {
-    let hash = aura_core::git::hash(clone).map_err(Error::Git)?;
-    let base = clone
-        .components()
-        .last()
-        .map(|c| c.as_os_str())
-        .ok_or_else(|| Error::PathComponent(clone.to_path_buf()))?;
+/// Resolution options as configured by the user.
+fn resolve_opts<'a, F>(
+    env: &'a Env,
+    pool: Pool<AlpmManager>,
+    fetch: &'a F,
+) -> ResolveOpts<'a, AlpmManager, F> {
+    let mut opts = ResolveOpts::new(pool, fetch, &env.aur.clones);
+    opts.faur = Some(&env.aur.faur);
+    if env.aur.nocheck.not() {
+        opts.dep_kinds |= DepKinds::CHECK;
+    }
+    opts.arch = env
+        .pacman
+        .architecture
+        .iter()
+        .map(|a| a.as_str())
+        .find(|a| *a != "auto")
+        .or(Some(aura_core::aur::dependencies::host_arch()));
+    opts
+}
+
+/// Show what installing the given packages would do, without doing it.
+pub(crate) fn plan<'a, I>(
+    fll: &FluentLanguageLoader,
+    env: &Env,
+    json: bool,
+    raw_pkgs: I,
+) -> Result<(), Error>
+where
+    I: IntoIterator<Item = &'a str>,
+{
+    let pkgs: HashSet<_> = raw_pkgs.into_iter().collect();
+
+    if pkgs.is_empty() {
+        return Err(Error::NoPackages);
+    }
+
+    let pool = env.alpm_pool().map_err(Error::Env)?;
+    let cache = aura_core::faur::Cache::new(Duration::from_secs(300));
+    let fetch = |url: &str| cache.fetch(url, &crate::fetch::fetch_json);
+    let opts = resolve_opts(env, pool, &fetch);
+    let plan = aura_core::aur::dependencies::plan(&opts, &pkgs).map_err(Error::Deps)?;
+
+    if json {
+        return crate::utils::json(&plan).map_err(|_| Error::Stdout);
+    }
+
+    if plan.satisfied.is_empty().not() {
+        aura!(fll, "A-plan-satisfied");
+        plan.satisfied.iter().for_each(|p| println!(" {p}"));
+    }
+
+    if plan.to_install.is_empty().not() {
+        aura!(fll, "A-install-repo-pkgs");
+        plan.to_install.iter().for_each(|p| println!(" {p}"));
+    }
+
+    aura!(fll, "A-install-aur-pkgs");
+    for (n, tier) in plan.to_build.iter().enumerate() {
+        println!(" {}. {}", n + 1, tier.iter().map(|p| p.cyan()).join(" "));
+    }
 
-    let full = hashes.join(base);
-    std::fs::write(&full, hash).map_err(|e| Error::FileWrite(full, e))?;
     Ok(())
 }
 
@@ -604,11 +723,28 @@ pub(crate) fn upgrade<'a>(
     debug!("Will ignore: {:?}", env.aur.ignores);
 
     // --- Query database for all non-repo packages --- //
-    let foreigns: Vec<aura_core::Package<'a>> = aura_core::foreign_packages(alpm)
+    let ignores = aura_core::pacman::PacmanConf::from(env.pacman.clone()).ignores();
+    let (skipped, foreigns): (Vec<_>, Vec<_>) =
+        aura_core::foreign_packages(alpm).partition(|p| ignores.ignores(p.name(), p.groups()));
+    let foreigns: Vec<aura_core::Package<'a>> = foreigns
+        .into_iter()
         .filter_map(aura_core::Package::from_alpm)
         .collect();
+
+    if dryrun.not() && skipped.is_empty().not() {
+        yellow!(fll, "A-u-ignored");
+        skipped
+            .iter()
+            .map(|p| p.name())
+            .sorted()
+            .for_each(|p| println!(" {p}"));
+    }
     debug!("Foreign packages: {}", foreigns.len());
-    let foreign_names: HashSet<_> = foreigns.iter().map(|p| p.name.as_ref()).collect();
+    let foreign_names: HashSet<_> = foreigns
+        .iter()
+        .map(|p| p.name.as_ref())
+        .chain(skipped.iter().map(|p| p.name()))
+        .collect();
     let filtered: Vec<_> = foreigns
         .iter()
         .filter(|p| {
@@ -622,66 +758,40 @@ pub(crate) fn upgrade<'a>(
         .collect();
     debug!("After excluding ignores and debugs: {}", filtered.len());
 
-    // --- Ensure they all have local clones --- //
-    if dryrun.not() {
-        aura!(fll, "A-u-fetch-info");
-    }
-    let clones: HashSet<PathBuf> = filtered
-        .par_iter()
-        .map(|p| p.name.as_ref())
-        .filter_map(|p| {
-            let rpath = aura_core::aur::clone_path_of_pkgbase(
-                &env.aur.clones,
-                p,
-                &crate::fetch::fetch_json,
-            );
-
-            match rpath {
-                Ok(path) => Some(Ok(path)),
-                Err(aura_core::aur::Error::PackageDoesNotExist(p)) => {
-                    if dryrun.not() && env.aur.warn_unknowns {
-                        yellow!(fll, "faur-unknown", pkg = p);
-                    }
-                    None
-                }
-                Err(e) => Some(Err(e)),
-            }
-        })
-        .collect::<Result<HashSet<_>, aura_core::aur::Error>>()
-        .map_err(Error::Aur)?;
-    debug!("Unique clones: {}", clones.len());
-
     // --- Compare versions to determine what to upgrade --- //
     if dryrun.not() {
         aura!(fll, "A-u-comparing");
     }
-    info!("Reading .SRCINFO files...");
-    let srcinfos = clones
-        .into_par_iter()
-        .map(|path| {
-            let full = path.join(".SRCINFO");
-            Srcinfo::parse_file(&full).map_err(|e| Error::Srcinfo(full, e))
+    let db = alpm.alpm.localdb();
+    let installed: Vec<_> = filtered
+        .iter()
+        .filter_map(|p| {
+            let groups = db
+                .pkg(p.name.as_ref())
+                .ok()?
+                .groups()
+                .iter()
+                .map(|g| g.to_string())
+                .collect();
+            Some((p.name.to_string(), p.version.clone(), groups))
         })
-        .collect::<Result<Vec<_>, Error>>()?;
+        .collect();
     info!("Pulling AUR data...");
-    let from_api: Vec<aura_core::faur::Package> = aura_core::faur::info(
-        srcinfos.iter().map(|p| p.base.pkgbase.as_str()),
+    let updates = aura_core::aur::updates(
+        &installed,
+        &env.aur.faur,
         &crate::fetch::fetch_json,
+        &ignores,
     )
-    .map_err(Error::Fetch)?;
-    debug!("Packages pulled: {}", from_api.len());
-    let db = alpm.alpm.localdb();
-    let mut to_upgrade: Vec<(aura_core::Package<'_>, aura_core::Package<'_>)> = from_api
-        .into_iter()
-        .filter_map(|new| db.pkg(new.name.as_str()).ok().map(|old| (old, new)))
-        .filter_map(
-            |(old, new)| match (Package::from_alpm(old), Package::from_faur(new)) {
-                (Some(o), Some(n)) => Some((o, n)),
-                _ => None,
-            },
-        )
-        .filter(|(old, new)| old < new)
-        .collect();
+    .map_err(Error::Aur)?;
+
+    if dryrun.not() && env.aur.warn_unknowns {
+        for p in updates.unknown.iter() {
+            yellow!(fll, "faur-unknown", pkg = p.as_str());
+        }
+    }
+
+    let to_upgrade = updates.available;
     debug!("Packages to upgrade: {}", to_upgrade.len());
 
     // --- Account for VCS packages --- //
@@ -692,7 +802,7 @@ pub(crate) fn upgrade<'a>(
                 let n = p.name.as_ref();
                 n.ends_with("-git") || n.ends_with("-hg") || n.ends_with("-svn")
             })
-            .filter(|p| to_upgrade.iter().all(|(old, _)| p.name != old.name))
+            .filter(|p| to_upgrade.iter().all(|u| p.name != u.name))
             .collect()
     } else {
         Vec::new()
@@ -709,24 +819,23 @@ pub(crate) fn upgrade<'a>(
         if dryrun.not() {
             aura!(fll, "A-u-to-upgrade");
         }
-        to_upgrade.sort_by(|(a, _), (b, _)| a.name.cmp(&b.name));
         let longest_name = to_upgrade
             .iter()
-            .map(|(old, _)| old.name.chars().count())
+            .map(|u| u.name.chars().count())
             .max()
             .unwrap_or(0);
         let longest_version = to_upgrade
             .iter()
-            .map(|(old, _)| old.version.to_string().chars().count())
+            .map(|u| u.old.to_string().chars().count())
             .max()
             .unwrap_or(0);
 
-        for (old, new) in to_upgrade.iter() {
+        for u in to_upgrade.iter() {
             println!(
                 " {:n$} :: {:v$} -> {}",
-                old.name.cyan(),
-                old.version.to_string().truecolor(128, 128, 128),
-                new.version.to_string().bold(),
+                u.name.cyan(),
+                u.old.to_string().truecolor(128, 128, 128),
+                u.new.to_string().bold(),
                 n = longest_name,
                 v = longest_version,
             );
@@ -747,7 +856,7 @@ pub(crate) fn upgrade<'a>(
 
         let names = to_upgrade
             .iter()
-            .map(|(old, _)| old.name.as_ref())
+            .map(|u| u.name.as_str())
             .chain(vcs.iter().map(|p| p.name.as_ref()));
 
         install(fll, &env, Mode::Upgrade, names)?;
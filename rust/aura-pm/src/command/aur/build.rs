@@ -10,6 +10,7 @@ use crate::utils::ResultVoid;
 use crate::yellow;
 use aura_core::aur::dependencies::Interdeps;
 use aura_core::cache::PkgPath;
+use aura_core::git::Retry;
 use colored::Colorize;
 use i18n_embed::fluent::FluentLanguageLoader;
 use i18n_embed_fl::fl;
@@ -40,6 +41,7 @@ pub(crate) enum Error {
     ReadDir(PathBuf, std::io::Error),
     Pkglist(PathBuf, std::io::Error),
     Makepkg,
+    MakepkgFlag(String),
     PkgctlBuild,
     Cancelled,
     Permissions(PathBuf),
@@ -63,6 +65,7 @@ impl Nested for Error {
             Error::ReadDir(_, e) => error!("{e}"),
             Error::Pkglist(_, e) => error!("{e}"),
             Error::Makepkg => {}
+            Error::MakepkgFlag(_) => {}
             Error::Cancelled => {}
             Error::PkgctlBuild => {}
             Error::Permissions(_) => {}
@@ -82,6 +85,7 @@ impl Localised for Error {
             Error::Cancelled => fl!(fll, "common-cancelled"),
             Error::EditFail(p) => fl!(fll, "A-build-e-edit", file = p.utf8()),
             Error::Makepkg => fl!(fll, "A-build-e-makepkg"),
+            Error::MakepkgFlag(f) => fl!(fll, "A-build-e-flag", flag = f.as_str()),
             Error::PkgctlBuild => fl!(fll, "A-build-e-pkgctl"),
             Error::CreateDir(p, _) => fl!(fll, "dir-mkdir", dir = p.utf8()),
             Error::ReadDir(p, _) => fl!(fll, "err-read-dir", dir = p.utf8()),
@@ -146,7 +150,7 @@ fn build_one(
 ) -> Result<Built, Error> {
     // Attempt a quick `git pull` to avoid the issue of building stale versions
     // of a package if the user forgot to `-Ay` recently.
-    if let Err(e) = aura_core::git::pull(&clone) {
+    if let Err(e) = aura_core::git::retry(Retry::default(), || aura_core::git::pull(&clone)) {
         warn!("{e}");
         yellow!(fll, "A-build-pull");
     }
@@ -187,8 +191,10 @@ fn build_one(
         .ok()
         .map_err(Error::CopyBuildFiles)?;
 
+    carry_over_hash(&env.aur.hashes, &clone, base);
+
     if env.aur.diff {
-        show_diffs(fll, env, &clone, base)?;
+        show_diffs(fll, env, &clone)?;
     }
 
     if env.aur.hotedit {
@@ -206,7 +212,10 @@ fn build_one(
         // `mkdir -p` from there, which it can't. Hacking `HOME=/tmp` also
         // doesn't work, since the user of `pkgbuild build` needs to be a sudoer
         // in order to download packages for the chroot.
-        let tarballs = if env.is_root.not() && env.aur.chroot.contains(base) {
+        let strategy = BuildStrategy::choose(env.is_root, &env.aur.chroot, base);
+        debug!("Building {} via {:?}", base, strategy);
+
+        let tarballs = if let BuildStrategy::CleanChroot = strategy {
             let dbs = alpm.as_ref().syncdbs();
             let aur_deps: Vec<_> = info
                 .base
@@ -268,8 +277,17 @@ fn build_one(
         // NOTE 2024-07-27 As a matter of policy, this call failing should not
         // fail the entire rest of the build process, so we just catch it and
         // warn.
-        if let Err(e) = Command::new("rm").arg("-rf").arg(&build_dir).status() {
-            warn!("Removing build dir {} failed: {}", build_dir.display(), e);
+        // A `BUILDDIR` set in `makepkg.conf` holds the bulk of the artefacts.
+        let elsewhere = env
+            .makepkg
+            .as_ref()
+            .and_then(|m| m.builddir())
+            .map(|d| d.join(base));
+
+        for dir in std::iter::once(&build_dir).chain(elsewhere.as_ref()) {
+            if let Err(e) = Command::new("rm").arg("-rf").arg(dir).status() {
+                warn!("Removing build dir {} failed: {}", dir.display(), e);
+            }
         }
     }
 
@@ -294,37 +312,33 @@ fn all_install_files(clone: &Path) -> Vec<PathBuf> {
         .unwrap_or_default()
 }
 
-fn show_diffs(
-    fll: &FluentLanguageLoader,
-    env: &Env,
-    clone: &Path,
-    pkgbase: &str,
-) -> Result<(), Error> {
-    let hashes = env.aur.hashes.as_path();
-
-    // Silently skip over any hashes that couldn't be read. This is most likely
-    // due to the package being installed for the first time, thus having no
-    // history to compare to.
-    match hash_of_last_install(hashes, pkgbase) {
-        Err(e) => warn!("Couldn't read latest hash of {}: {}", pkgbase, e),
-        Ok(prev_hash) => {
-            let curr_hash = aura_core::git::hash(clone).map_err(Error::GitDiff)?;
-
-            if prev_hash != curr_hash && proceed!(fll, env, "A-build-diff").is_some() {
-                aura_core::git::diff(clone, &prev_hash).map_err(Error::GitDiff)?;
-                proceed!(fll, env, "proceed").ok_or(Error::Cancelled)?;
-            }
+/// Mark the commit that an older version of Aura recorded as last built within
+/// the clone itself, then forget the old record. Failing to do so merely leaves
+/// nothing to diff against, say if a shallow clone lacks that commit.
+fn carry_over_hash(hashes: &Path, clone: &Path, pkgbase: &str) {
+    let path = hashes.join(pkgbase);
+
+    if let Ok(hash) = std::fs::read_to_string(&path) {
+        if let Err(e) = aura_core::git::seed_built(clone, hash.trim()) {
+            warn!("Couldn't carry over the last built commit of {pkgbase}: {e}");
         }
-    }
 
-    Ok(())
+        let _ = std::fs::remove_file(&path);
+    }
 }
 
-/// What AUR repo git hash is associated with the last time a given package was
-/// installed?
-fn hash_of_last_install(hashes: &Path, pkgbase: &str) -> Result<String, std::io::Error> {
-    let path = hashes.join(pkgbase);
-    std::fs::read_to_string(path).map(|s| s.trim().to_string())
+/// Show what has changed in a clone since it was last built, if anything.
+fn show_diffs(fll: &FluentLanguageLoader, env: &Env, clone: &Path) -> Result<(), Error> {
+    // A package being built for the first time has no history to compare to,
+    // and so no diff.
+    let diff = aura_core::git::diff_since_last(clone).map_err(Error::GitDiff)?;
+
+    if diff.is_empty().not() && proceed!(fll, env, "A-build-diff").is_some() {
+        println!("{diff}");
+        proceed!(fll, env, "proceed").ok_or(Error::Cancelled)?;
+    }
+
+    Ok(())
 }
 
 fn shellcheck(fll: &FluentLanguageLoader, env: &Env, build_d: &Path) -> Result<(), Error> {
@@ -398,19 +412,32 @@ fn edit(editor: &str, file: PathBuf) -> Result<(), Error> {
     Ok(())
 }
 
+/// How a package is to be built.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum BuildStrategy {
+    /// Via `makepkg`, against the live system.
+    Direct,
+    /// Via `pkgctl build`, within a clean chroot that holds nothing but the
+    /// package's declared dependencies. This catches missing `makedepends`.
+    CleanChroot,
+}
+
+impl BuildStrategy {
+    /// Packages configured to be built in a chroot are, unless we're root.
+    fn choose(is_root: bool, chroot: &HashSet<String>, pkgbase: &str) -> Self {
+        if is_root.not() && chroot.contains(pkgbase) {
+            BuildStrategy::CleanChroot
+        } else {
+            BuildStrategy::Direct
+        }
+    }
+}
+
 fn pkgctl_build(within: &Path, deps: &[PkgPath]) -> Result<Vec<PkgPath>, Error> {
     debug!("Running `pkgctl build` within {}", within.display());
     debug!("AUR deps to inject: {:?}", deps);
 
-    let mut cmd = Command::new("pkgctl");
-    cmd.arg("build");
-
-    for dep in deps {
-        cmd.arg("-I");
-        cmd.arg(dep.as_path());
-    }
-
-    cmd.current_dir(within)
+    pkgctl_command(within, deps)
         .status()
         .map_err(|_| Error::PkgctlBuild)?
         .success()
@@ -430,40 +457,30 @@ fn makepkg(env: &Env, within: &Path) -> Result<Vec<PkgPath>, Error> {
         None => None,
     };
 
-    let mut cmd = if let Some(u) = user {
+    if let Some(u) = user {
         user_permissions(within, u)?;
-
-        let mut c = Command::new(env.sudo());
-        c.arg("-u").arg(u).arg("makepkg");
-        c
-    } else {
-        Command::new("makepkg")
-    };
-
-    // TODO Remove or rethink
-    //
-    // 2024-06-12
-    //
-    // Yes, this isn't enough to get around packages that don't want to be
-    // configured more than once.
-    //
-    // 2024-07-01
-    //
-    // The issues is that we _do_ want to leave build artefacts behind in
-    // general to speed up rebuilds.
-    cmd.arg("-f");
-
-    if env.aur.nocheck {
-        cmd.arg("--nocheck");
     }
 
-    if env.aur.skipinteg {
-        cmd.arg("--skipinteg");
-    }
-
-    if env.aur.skippgpcheck {
-        cmd.arg("--skippgpcheck");
-    }
+    let own = [
+        // TODO Remove or rethink
+        //
+        // 2024-06-12
+        //
+        // Yes, this isn't enough to get around packages that don't want to be
+        // configured more than once.
+        //
+        // 2024-07-01
+        //
+        // The issues is that we _do_ want to leave build artefacts behind in
+        // general to speed up rebuilds.
+        Some("-f"),
+        env.aur.nocheck.then_some("--nocheck"),
+        env.aur.skipinteg.then_some("--skipinteg"),
+        env.aur.skippgpcheck.then_some("--skippgpcheck"),
+    ];
+    let own: Vec<_> = own.into_iter().flatten().collect();
+    let sudo = user.map(|u| (env.sudo(), u));
+    let mut cmd = makepkg_command(sudo, &own, &env.aur.makepkgflags)?;
 
     debug!("Running makepkg within: {}", within.display());
 
@@ -480,6 +497,54 @@ fn makepkg(env: &Env, within: &Path) -> Result<Vec<PkgPath>, Error> {
     tarball_paths(user, within)
 }
 
+/// Flags that Aura controls itself, and which thus can never be given as extra
+/// user arguments.
+const RESERVED_FLAGS: &[&str] = &["--noconfirm"];
+
+/// A `makepkg` call, possibly run as another user via `sudo`. The user's
+/// `extra` flags follow Aura's `own`, and may not collide with them or with any
+/// reserved flag.
+fn makepkg_command(
+    sudo: Option<(&str, &str)>,
+    own: &[&str],
+    extra: &[String],
+) -> Result<Command, Error> {
+    if let Some(flag) = extra
+        .iter()
+        .find(|f| own.contains(&f.as_str()) || RESERVED_FLAGS.contains(&f.as_str()))
+    {
+        return Err(Error::MakepkgFlag(flag.clone()));
+    }
+
+    let mut cmd = if let Some((sudo, u)) = sudo {
+        let mut c = Command::new(sudo);
+        c.arg("-u").arg(u).arg("makepkg");
+        c
+    } else {
+        Command::new("makepkg")
+    };
+
+    cmd.args(own).args(extra);
+
+    Ok(cmd)
+}
+
+/// A `pkgctl build` call, injecting the given AUR packages into the chroot,
+/// since it can't find them in any repository itself. These were built in some
+/// earlier tier of the build order.
+fn pkgctl_command(within: &Path, deps: &[PkgPath]) -> Command {
+    let mut cmd = Command::new("pkgctl");
+    cmd.arg("build");
+
+    for dep in deps {
+        cmd.arg("-I");
+        cmd.arg(dep.as_path());
+    }
+
+    cmd.current_dir(within);
+    cmd
+}
+
 /// Grant write permissions to the given build directory for the given build user.
 fn user_permissions(within: &Path, user: &str) -> Result<(), Error> {
     debug!("Setting a+w permissions within: {}", within.display());
@@ -585,3 +650,112 @@ fn build_check(
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::ffi::OsStr;
+
+    #[test]
+    fn strategy() {
+        let chroot = HashSet::from(["foo".to_string()]);
+
+        assert_eq!(
+            BuildStrategy::CleanChroot,
+            BuildStrategy::choose(false, &chroot, "foo")
+        );
+        assert_eq!(
+            BuildStrategy::Direct,
+            BuildStrategy::choose(false, &chroot, "bar")
+        );
+        assert_eq!(
+            BuildStrategy::Direct,
+            BuildStrategy::choose(true, &chroot, "foo")
+        );
+    }
+
+    #[test]
+    fn chroot_command() {
+        let within = Path::new("/tmp/aura/builds/foo");
+        let deps: Vec<_> = [
+            "/var/cache/aura/bar-1.0-1-x86_64.pkg.tar.zst",
+            "/var/cache/aura/baz-2.0-1-any.pkg.tar.zst",
+        ]
+        .into_iter()
+        .filter_map(|p| PkgPath::new(PathBuf::from(p)))
+        .collect();
+        assert_eq!(2, deps.len());
+
+        let cmd = pkgctl_command(within, &deps);
+        let args: Vec<_> = cmd.get_args().collect();
+        let expected: Vec<&OsStr> = vec![
+            "build".as_ref(),
+            "-I".as_ref(),
+            deps[0].as_path().as_os_str(),
+            "-I".as_ref(),
+            deps[1].as_path().as_os_str(),
+        ];
+
+        assert_eq!("pkgctl", cmd.get_program());
+        assert_eq!(expected, args);
+        assert_eq!(Some(within), cmd.get_current_dir());
+    }
+
+    #[test]
+    fn makepkg_flags() {
+        let extra = vec!["-c".to_string(), "--holdver".to_string()];
+        let Ok(cmd) = makepkg_command(Some(("sudo", "nobody")), &["-f", "--nocheck"], &extra)
+        else {
+            panic!("Valid flags were rejected");
+        };
+        let args: Vec<_> = cmd.get_args().collect();
+        let expected: Vec<&OsStr> = [
+            "-u",
+            "nobody",
+            "makepkg",
+            "-f",
+            "--nocheck",
+            "-c",
+            "--holdver",
+        ]
+        .iter()
+        .map(|a| a.as_ref())
+        .collect();
+
+        assert_eq!("sudo", cmd.get_program());
+        assert_eq!(expected, args);
+
+        let Ok(cmd) = makepkg_command(None, &["-f"], &extra) else {
+            panic!("Valid flags were rejected");
+        };
+        assert_eq!("makepkg", cmd.get_program());
+        assert_eq!(3, cmd.get_args().count());
+
+        let bad = vec!["-c".to_string(), "--noconfirm".to_string()];
+        match makepkg_command(None, &["-f"], &bad) {
+            Err(Error::MakepkgFlag(f)) => assert_eq!("--noconfirm", f),
+            _ => panic!("Conflicting flag was accepted"),
+        }
+
+        let dup = vec!["--nocheck".to_string()];
+        match makepkg_command(None, &["-f", "--nocheck"], &dup) {
+            Err(Error::MakepkgFlag(f)) => assert_eq!("--nocheck", f),
+            _ => panic!("Duplicated flag was accepted"),
+        }
+    }
+
+    #[test]
+    fn makepkg_flags_unset_by_aura() {
+        let extra = vec!["--nocheck".to_string(), "--skippgpcheck".to_string()];
+        let Ok(cmd) = makepkg_command(None, &["-f"], &extra) else {
+            panic!("Flags unused by Aura were rejected");
+        };
+        let args: Vec<_> = cmd.get_args().collect();
+        let expected: Vec<&OsStr> = ["-f", "--nocheck", "--skippgpcheck"]
+            .iter()
+            .map(|a| a.as_ref())
+            .collect();
+
+        assert_eq!(expected, args);
+    }
+}
@@ -8,12 +8,17 @@ use crate::utils::NOTHING;
 use applying::Apply;
 use colored::*;
 use i18n_embed::fluent::FluentLanguageLoader;
+use i18n_embed_fl::fl;
+use log::error;
 use r2d2_alpm::Alpm;
+use std::collections::BTreeMap;
 use std::ops::Not;
 
 pub(crate) enum Error {
     Removal(crate::pacman::Error),
     Adopt(crate::pacman::Error),
+    NotInstalled(Vec<String>),
+    Json(serde_json::Error),
 }
 
 impl Nested for Error {
@@ -21,6 +26,8 @@ impl Nested for Error {
         match self {
             Error::Removal(e) => e.nested(),
             Error::Adopt(e) => e.nested(),
+            Error::NotInstalled(_) => {}
+            Error::Json(e) => error!("{e}"),
         }
     }
 }
@@ -30,13 +37,24 @@ impl Localised for Error {
         match self {
             Error::Removal(e) => e.localise(fll),
             Error::Adopt(e) => e.localise(fll),
+            Error::NotInstalled(ps) => fl!(fll, "O-adopt-missing", pkgs = ps.join(", ")),
+            Error::Json(_) => fl!(fll, "err-write"),
         }
     }
 }
 
 /// Print the name of each orphaned package.
-pub(crate) fn list(alpm: &Alpm) {
-    aura_core::orphans(alpm).for_each(|o| println!("{} {}", o.name(), o.version()))
+pub(crate) fn list(alpm: &Alpm, json: bool) -> Result<(), Error> {
+    if json {
+        let orphans: BTreeMap<_, _> = aura_core::orphans(alpm)
+            .map(|o| (o.name(), o.version().as_str()))
+            .collect();
+
+        crate::utils::json(&orphans).map_err(Error::Json)
+    } else {
+        aura_core::orphans(alpm).for_each(|o| println!("{} {}", o.name(), o.version()));
+        Ok(())
+    }
 }
 
 /// Print the name of each "elderly" package. In theory these are all explicitly
@@ -47,10 +65,31 @@ pub(crate) fn elderly(alpm: &Alpm) {
 }
 
 /// Sets a package's install reason to "as explicit". An alias for `-D --asexplicit`.
-pub(crate) fn adopt(env: &Env, packages: Vec<String>) -> Result<(), Error> {
+///
+/// Fails up front, naming them, if any of the packages aren't installed.
+pub(crate) fn adopt(env: &Env, alpm: &Alpm, packages: Vec<String>) -> Result<(), Error> {
+    let db = alpm.as_ref().localdb();
+    let missing = not_installed(|p| db.pkg(p).is_ok(), &packages);
+
+    if missing.is_empty().not() {
+        return Err(Error::NotInstalled(missing));
+    }
+
     crate::pacman::sudo_pacman(env, "-D", ["--asexplicit"], packages).map_err(Error::Adopt)
 }
 
+/// Those of the given packages which aren't installed, in their original order.
+fn not_installed<F>(is_installed: F, packages: &[String]) -> Vec<String>
+where
+    F: Fn(&str) -> bool,
+{
+    packages
+        .iter()
+        .filter(|p| is_installed(p).not())
+        .cloned()
+        .collect()
+}
+
 /// Uninstall all orphan packages.
 ///
 /// Will fail if the process does not have permission to create the lockfile,
@@ -70,3 +109,22 @@ pub(crate) fn remove(env: &Env, alpm: &Alpm, fll: &FluentLanguageLoader) -> Resu
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashSet;
+
+    #[test]
+    fn adopting_uninstalled() {
+        let installed = HashSet::from(["foo", "bar"]);
+        let is_installed = |p: &str| installed.contains(p);
+        let packages = ["baz", "foo", "qux", "bar"].map(String::from);
+
+        assert_eq!(
+            vec!["baz".to_string(), "qux".to_string()],
+            not_installed(is_installed, &packages)
+        );
+        assert!(not_installed(is_installed, &packages[1..2]).is_empty());
+    }
+}
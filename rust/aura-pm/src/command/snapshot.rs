@@ -17,8 +17,6 @@ use r2d2_alpm::Alpm;
 use std::cmp::Ordering;
 use std::collections::HashMap;
 use std::collections::HashSet;
-use std::fs::File;
-use std::io::BufWriter;
 use std::ops::Not;
 use std::path::Path;
 use std::path::PathBuf;
@@ -27,9 +25,8 @@ use time::macros::format_description;
 pub(crate) enum Error {
     Pacman(crate::pacman::Error),
     Readline(std::io::Error),
-    JsonWrite(PathBuf, serde_json::Error),
+    Save(aura_core::snapshot::Error),
     DeleteFile(PathBuf, std::io::Error),
-    OpenFile(PathBuf, std::io::Error),
     TimeFormat(time::error::Format),
     Cancelled,
     NoSnapshots,
@@ -40,9 +37,8 @@ impl Nested for Error {
         match self {
             Error::Pacman(e) => e.nested(),
             Error::Readline(e) => error!("{e}"),
-            Error::JsonWrite(_, e) => error!("{e}"),
+            Error::Save(e) => e.nested(),
             Error::DeleteFile(_, e) => error!("{e}"),
-            Error::OpenFile(_, e) => error!("{e}"),
             Error::Cancelled => {}
             Error::NoSnapshots => {}
             Error::TimeFormat(e) => error!("{e}"),
@@ -55,11 +51,10 @@ impl Localised for Error {
         match self {
             Error::Pacman(e) => e.localise(fll),
             Error::Readline(_) => fl!(fll, "err-user-input"),
-            Error::JsonWrite(p, _) => fl!(fll, "err-json-write", file = p.utf8()),
+            Error::Save(e) => e.localise(fll),
             Error::Cancelled => fl!(fll, "common-cancelled"),
             Error::NoSnapshots => fl!(fll, "B-none"),
             Error::DeleteFile(p, _) => fl!(fll, "err-file-del", file = p.utf8()),
-            Error::OpenFile(p, _) => fl!(fll, "err-file-open", file = p.utf8()),
             Error::TimeFormat(_) => fl!(fll, "err-time-format"),
         }
     }
@@ -77,17 +72,7 @@ struct StateDiff<'a> {
 }
 
 pub(crate) fn save(fll: &FluentLanguageLoader, alpm: &Alpm, snapshots: &Path) -> Result<(), Error> {
-    let snap = Snapshot::from_alpm(alpm);
-    let form =
-        format_description!("[year].[month]([month repr:short]).[day].[hour].[minute].[second]");
-    let name = format!(
-        "{}.json",
-        snap.time.format(form).map_err(Error::TimeFormat)?
-    );
-    let path = snapshots.join(name);
-
-    let file = BufWriter::new(File::create(&path).map_err(|e| Error::OpenFile(path.clone(), e))?);
-    serde_json::to_writer(file, &snap).map_err(|e| Error::JsonWrite(path, e))?;
+    aura_core::snapshot::save(alpm, snapshots).map_err(Error::Save)?;
     green!(fll, "B-saved");
 
     Ok(())
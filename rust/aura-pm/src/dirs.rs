@@ -13,6 +13,7 @@ pub enum Error {
     Mkdir(PathBuf, std::io::Error),
     XdgHome(std::env::VarError),
     XdgCache(std::env::VarError),
+    Clones(std::io::Error),
 }
 
 impl Nested for Error {
@@ -21,6 +22,7 @@ impl Nested for Error {
             Error::Mkdir(_, e) => error!("{e}"),
             Error::XdgHome(e) => error!("{e}"),
             Error::XdgCache(e) => error!("{e}"),
+            Error::Clones(e) => error!("{e}"),
         }
     }
 }
@@ -31,6 +33,7 @@ impl Localised for Error {
             Error::Mkdir(p, _) => fl!(fll, "dir-mkdir", dir = p.utf8()),
             Error::XdgHome(_) => fl!(fll, "dir-home"),
             Error::XdgCache(_) => fl!(fll, "dir-cache"),
+            Error::Clones(_) => fl!(fll, "dir-clones"),
         }
     }
 }
@@ -91,17 +94,21 @@ pub(crate) fn snapshot() -> Result<PathBuf, Error> {
 
 /// The full path to the directory of AUR package `git` clones.
 ///
-/// Creates the directory if it doesn't exist.
+/// Creates the directory if it doesn't exist. Unless overridden by `AURDEST`,
+/// this is [`aura_core::aur::default_clone_dir`].
 pub(crate) fn clones() -> Result<PathBuf, Error> {
-    let path = std::env::var("AURDEST")
-        .map(PathBuf::from)
-        .or_else(|_| aura_xdg_cache().map(|p| p.join("packages")))?;
+    match std::env::var("AURDEST") {
+        Err(_) => aura_core::aur::default_clone_dir().map_err(Error::Clones),
+        Ok(dest) => {
+            let path = PathBuf::from(dest);
 
-    if path.is_dir().not() {
-        std::fs::create_dir_all(&path).map_err(|e| Error::Mkdir(path.clone(), e))?;
-    }
+            if path.is_dir().not() {
+                std::fs::create_dir_all(&path).map_err(|e| Error::Mkdir(path.clone(), e))?;
+            }
 
-    Ok(path)
+            Ok(path)
+        }
+    }
 }
 
 /// The full path to the build directory.
@@ -130,16 +137,9 @@ pub(crate) fn tarballs() -> Result<PathBuf, Error> {
     Ok(path)
 }
 
-/// The full path to the directory of git hashes that indicate the last time an
-/// AUR package was built and installed.
-///
-/// Creates the directory if it doesn't exist.
-pub(crate) fn hashes() -> Result<PathBuf, Error> {
-    let path = aura_xdg_cache()?.join("hashes");
-
-    if path.is_dir().not() {
-        std::fs::create_dir_all(&path).map_err(|e| Error::Mkdir(path.clone(), e))?;
-    }
-
-    Ok(path)
+/// The directory in which older versions of Aura recorded the git hash of each
+/// AUR package when it was last built and installed. These are now recorded
+/// within each clone instead.
+pub(crate) fn legacy_hashes() -> Result<PathBuf, Error> {
+    Ok(aura_xdg_cache()?.join("hashes"))
 }
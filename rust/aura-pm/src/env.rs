@@ -5,6 +5,7 @@ use crate::error::Nested;
 use crate::localization::identifier_from_locale;
 use crate::localization::Localised;
 use crate::makepkg::Makepkg;
+use aura_core::faur::FaurClient;
 use i18n_embed_fl::fl;
 use log::debug;
 use log::error;
@@ -289,7 +290,9 @@ struct RawAur {
     build: Option<PathBuf>,
     cache: Option<PathBuf>,
     clones: Option<PathBuf>,
+    /// Only read to carry over what older versions of Aura recorded there.
     hashes: Option<PathBuf>,
+    faur_url: Option<String>,
     builduser: Option<String>,
     #[serde(default)]
     chroot: HashSet<String>,
@@ -313,6 +316,8 @@ struct RawAur {
     nocheck: bool,
     #[serde(default)]
     reverse: bool,
+    #[serde(default)]
+    makepkgflags: Vec<String>,
     warn_unknowns: Option<bool>,
 }
 
@@ -321,7 +326,13 @@ pub(crate) struct Aur {
     pub(crate) build: PathBuf,
     pub(crate) cache: PathBuf,
     pub(crate) clones: PathBuf,
+    /// Where older versions of Aura recorded the commit each package was last
+    /// built from.
+    #[serde(skip_serializing)]
     pub(crate) hashes: PathBuf,
+    /// The Faur instance to query for AUR package metadata.
+    #[serde(skip_serializing)]
+    pub(crate) faur: FaurClient,
     pub(crate) builduser: Option<String>,
     /// Packages to build via `pkgctl build`.
     pub(crate) chroot: HashSet<String>,
@@ -365,6 +376,8 @@ pub(crate) struct Aur {
     /// Give installed packages the "non-explicit" status.
     #[serde(skip_serializing)]
     pub(crate) asdeps: bool,
+    /// Extra arguments to pass along to every `makepkg` call.
+    pub(crate) makepkgflags: Vec<String>,
 }
 
 impl Aur {
@@ -374,7 +387,8 @@ impl Aur {
             build: dirs::builds()?,
             cache: dirs::tarballs()?,
             clones: dirs::clones()?,
-            hashes: dirs::hashes()?,
+            hashes: dirs::legacy_hashes()?,
+            faur: FaurClient::default(),
             builduser: None,
             chroot: HashSet::new(),
             ignores: HashSet::new(),
@@ -392,6 +406,7 @@ impl Aur {
             skippgpcheck: false,
             reverse: false,
             asdeps: false,
+            makepkgflags: Vec::new(),
         };
 
         Ok(a)
@@ -462,6 +477,7 @@ impl Aur {
         // Harmless clone, as we don't expect many "ignores" to be passed on the
         // command line.
         self.ignores.extend(flags.ignore.clone());
+        self.makepkgflags.extend(flags.makepkg.clone());
     }
 }
 
@@ -472,13 +488,14 @@ impl TryFrom<RawAur> for Aur {
         let build = raw.build.map(Ok).unwrap_or_else(dirs::builds)?;
         let cache = raw.cache.map(Ok).unwrap_or_else(dirs::tarballs)?;
         let clones = raw.clones.map(Ok).unwrap_or_else(dirs::clones)?;
-        let hashes = raw.hashes.map(Ok).unwrap_or_else(dirs::hashes)?;
+        let hashes = raw.hashes.map(Ok).unwrap_or_else(dirs::legacy_hashes)?;
 
         let a = Aur {
             build,
             cache,
             clones,
             hashes,
+            faur: raw.faur_url.map(FaurClient::new).unwrap_or_default(),
             builduser: raw.builduser,
             chroot: raw.chroot,
             ignores: raw.ignores,
@@ -496,6 +513,7 @@ impl TryFrom<RawAur> for Aur {
             skippgpcheck: false,
             reverse: raw.reverse,
             asdeps: false,
+            makepkgflags: raw.makepkgflags,
         };
 
         Ok(a)
@@ -573,4 +591,22 @@ mod test {
         let exp: HashSet<_> = ["foo".to_string(), "bar".to_string()].into();
         assert_eq!(exp, aur.ignores);
     }
+
+    #[test]
+    fn faur_url() {
+        let dirs = r#"
+[aur]
+build = "/tmp/aura/builds"
+cache = "/tmp/aura/cache"
+clones = "/tmp/aura/clones"
+"#;
+        let aur = |toml: &str| {
+            let raw = basic_toml::from_str::<RawEnv>(toml).unwrap().aur.unwrap();
+            Aur::try_from(raw).unwrap()
+        };
+
+        let custom = format!("{dirs}faur_url = \"http://localhost:8080/\"\n");
+        assert_eq!("http://localhost:8080", aur(&custom).faur.url());
+        assert_eq!(aura_core::faur::FAUR_URL, aur(dirs).faur.url());
+    }
 }
@@ -134,11 +134,33 @@ impl Nested for aura_core::git::Error {
             aura_core::git::Error::Clone(_) => {}
             aura_core::git::Error::Pull(_) => {}
             aura_core::git::Error::Diff(_) => {}
+            aura_core::git::Error::Network(_) => {}
             aura_core::git::Error::ReadHash(e) => error!("{e}"),
         }
     }
 }
 
+impl Nested for aura_core::gpg::Error {
+    fn nested(&self) {
+        match self {
+            aura_core::gpg::Error::Io(e) => error!("{e}"),
+            aura_core::gpg::Error::Import(_) => {}
+        }
+    }
+}
+
+impl Nested for aura_core::snapshot::Error {
+    fn nested(&self) {
+        match self {
+            aura_core::snapshot::Error::TimeFormat(e) => error!("{e}"),
+            aura_core::snapshot::Error::OpenFile(_, e) => error!("{e}"),
+            aura_core::snapshot::Error::JsonWrite(_, e) => error!("{e}"),
+            aura_core::snapshot::Error::Corrupt(_, e) => error!("{e}"),
+            aura_core::snapshot::Error::Remove(_, e) => error!("{e}"),
+        }
+    }
+}
+
 impl Nested for aura_core::aur::Error {
     fn nested(&self) {
         match self {
@@ -166,6 +188,17 @@ where
             deps::Error::MalformedGraph => {}
             deps::Error::CyclicDep(_) => {}
             deps::Error::Faur(e) => e.nested(),
+            deps::Error::Json(e) => error!("{e}"),
+            deps::Error::DisallowedRepo(_, _) => {}
+            deps::Error::ManifestDrift(_, _) => {}
+            deps::Error::AmbiguousFaur(_, _) => {}
+            deps::Error::AmbiguousProvider(_, _) => {}
+            deps::Error::BaseMismatch(_, _) => {}
+            deps::Error::AuditRejected(_, _) => {}
+            deps::Error::SrcinfoParseTimeout(_) => {}
+            deps::Error::SrcinfoGeneration(_) => {}
+            deps::Error::Conflict(_, _) => {}
+            deps::Error::Build(_, e) => e.nested(),
         }
     }
 }
@@ -8,10 +8,16 @@ use i18n_embed_fl::fl;
 use log::debug;
 use log::error;
 use serde::de::DeserializeOwned;
+use std::time::Duration;
+
+/// How long to wait for a remote endpoint to connect, and separately to send
+/// its reply, before giving up on it.
+pub(crate) const TIMEOUT: Duration = Duration::from_secs(30);
 
 pub enum Error {
     Curl(curl::Error),
     Json(String, serde_json::Error),
+    Timeout(String),
 }
 
 impl Nested for Error {
@@ -19,6 +25,7 @@ impl Nested for Error {
         match self {
             Error::Curl(e) => error!("{e}"),
             Error::Json(_, e) => error!("{e}"),
+            Error::Timeout(_) => {}
         }
     }
 }
@@ -28,12 +35,22 @@ impl Localised for Error {
         match self {
             Error::Curl(e) => fl!(fll, "err-curl", err = e.to_string()),
             Error::Json(url, _) => fl!(fll, "err-json-decode", url = url.as_str()),
+            Error::Timeout(url) => fl!(fll, "err-timeout", url = url.as_str()),
         }
     }
 }
 
 /// Fetch JSON from some given endpoint.
 pub(crate) fn fetch_json<T>(url: &str) -> Result<T, Error>
+where
+    T: DeserializeOwned,
+{
+    fetch_json_within(url, TIMEOUT)
+}
+
+/// Fetch JSON from some given endpoint, giving up if it takes longer than the
+/// given timeout to either connect or reply.
+pub(crate) fn fetch_json_within<T>(url: &str, timeout: Duration) -> Result<T, Error>
 where
     T: DeserializeOwned,
 {
@@ -43,6 +60,10 @@ where
     let mut data = Vec::new();
     handle.url(url).map_err(Error::Curl)?;
     handle.fail_on_error(true).map_err(Error::Curl)?;
+    handle.connect_timeout(timeout).map_err(Error::Curl)?;
+    // Abort transfers that stall entirely for the given time.
+    handle.low_speed_limit(1).map_err(Error::Curl)?;
+    handle.low_speed_time(timeout).map_err(Error::Curl)?;
 
     // Blocked off to allow `data` to be borrowed again down below.
     {
@@ -52,9 +73,48 @@ where
             Ok(bytes.len())
         })
         .map_err(Error::Curl)?;
-        tx.perform().map_err(Error::Curl)?;
+        tx.perform().map_err(|e| {
+            if e.is_operation_timedout() {
+                Error::Timeout(url.to_string())
+            } else {
+                Error::Curl(e)
+            }
+        })?;
     }
 
     let json = serde_json::from_slice(&data).map_err(|e| Error::Json(url.to_string(), e))?;
     Ok(json)
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::net::TcpListener;
+    use std::time::Instant;
+
+    #[test]
+    fn silent_server_times_out() {
+        // Connections are accepted by the OS, but no reply is ever sent.
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/packages", listener.local_addr().unwrap());
+        let timeout = Duration::from_secs(1);
+
+        let start = Instant::now();
+        let res: Result<Vec<aura_core::faur::Package>, _> = fetch_json_within(&url, timeout);
+
+        assert!(matches!(res, Err(Error::Timeout(u)) if u == url));
+        assert!(start.elapsed() < timeout * 5);
+    }
+
+    #[test]
+    fn unroutable_address_fails_quickly() {
+        let timeout = Duration::from_secs(1);
+
+        let start = Instant::now();
+        let res: Result<Vec<aura_core::faur::Package>, _> =
+            fetch_json_within("http://10.255.255.1/packages", timeout);
+
+        assert!(res.is_err());
+        assert!(start.elapsed() < timeout * 5);
+    }
+}
@@ -1118,6 +1118,9 @@ pub struct Orphans {
     /// Display all explicitly installed, top-level packages.
     #[clap(group = "orphans", long, short = 'e')]
     pub elderly: bool,
+    /// Output the orphans as JSON, as a map of names to versions.
+    #[clap(long)]
+    pub json: bool,
 }
 
 /// View various configuration settings and files.
@@ -1241,6 +1244,10 @@ pub struct Aur {
     #[clap(long, short, display_order = 2)]
     pub quiet: bool,
 
+    /// [-i/-s/-v/-d] Output results as JSON.
+    #[clap(long, display_order = 2)]
+    pub json: bool,
+
     /// Open a given package's AUR page.
     #[clap(group = "aur", long, short, value_name = "package", display_order = 1)]
     pub open: Option<String>,
@@ -1298,7 +1305,8 @@ pub struct Aur {
     )]
     pub ignore: Vec<String>,
 
-    /// [-u] Show available upgrades, but do not perform them.
+    /// [-u] Show available upgrades, but do not perform them. Otherwise, show what
+    /// would be installed and built, and in what order.
     #[clap(long, short = 'd', display_order = 3)]
     pub dryrun: bool,
 
@@ -1338,6 +1346,16 @@ pub struct Aur {
     #[clap(long, display_order = 5)]
     pub skippgpcheck: bool,
 
+    /// (Makepkg) Pass an extra flag to makepkg (can be used more than once).
+    #[clap(
+        long,
+        value_name = "flag",
+        allow_hyphen_values = true,
+        action(ArgAction::Append),
+        display_order = 5
+    )]
+    pub makepkg: Vec<String>,
+
     /// Packages to install.
     pub packages: Vec<String>,
 }
@@ -112,11 +112,39 @@ impl Localised for aura_core::git::Error {
             aura_core::git::Error::Clone(p) => fl!(fll, "git-clone", dir = p.utf8()),
             aura_core::git::Error::Pull(p) => fl!(fll, "git-pull", dir = p.utf8()),
             aura_core::git::Error::Diff(p) => fl!(fll, "git-diff", file = p.utf8()),
+            aura_core::git::Error::Network(p) => fl!(fll, "git-network", dir = p.utf8()),
             aura_core::git::Error::ReadHash(_) => fl!(fll, "git-hash"),
         }
     }
 }
 
+impl Localised for aura_core::gpg::Error {
+    fn localise(&self, fll: &FluentLanguageLoader) -> String {
+        match self {
+            aura_core::gpg::Error::Io(_) => fl!(fll, "gpg-io"),
+            aura_core::gpg::Error::Import(ks) => fl!(fll, "gpg-import", keys = ks.join(", ")),
+        }
+    }
+}
+
+impl Localised for aura_core::snapshot::Error {
+    fn localise(&self, fll: &FluentLanguageLoader) -> String {
+        match self {
+            aura_core::snapshot::Error::TimeFormat(_) => fl!(fll, "err-time-format"),
+            aura_core::snapshot::Error::OpenFile(p, _) => {
+                fl!(fll, "err-file-open", file = p.utf8())
+            }
+            aura_core::snapshot::Error::JsonWrite(p, _) => {
+                fl!(fll, "err-json-write", file = p.utf8())
+            }
+            aura_core::snapshot::Error::Corrupt(p, _) => {
+                fl!(fll, "err-json-read", file = p.utf8())
+            }
+            aura_core::snapshot::Error::Remove(p, _) => fl!(fll, "err-file-del", file = p.utf8()),
+        }
+    }
+}
+
 impl Localised for aura_core::aur::Error {
     fn localise(&self, fll: &FluentLanguageLoader) -> String {
         match self {
@@ -173,6 +201,39 @@ where
                 fl!(fll, "dep-cycle", cycle = rendered)
             }
             deps::Error::Faur(e) => e.localise(fll),
+            deps::Error::Json(_) => fl!(fll, "dep-json"),
+            deps::Error::DisallowedRepo(p, r) => {
+                fl!(fll, "dep-repo", pkg = p.as_str(), repo = r.as_str())
+            }
+            deps::Error::ManifestDrift(p, _) => fl!(fll, "dep-drift", pkg = p.as_str()),
+            deps::Error::AmbiguousFaur(p, cs) => {
+                fl!(
+                    fll,
+                    "dep-ambiguous",
+                    pkg = p.as_str(),
+                    cands = cs.join(", ")
+                )
+            }
+            deps::Error::AmbiguousProvider(p, cs) => {
+                fl!(
+                    fll,
+                    "dep-ambiguous",
+                    pkg = p.as_str(),
+                    cands = cs.join(", ")
+                )
+            }
+            deps::Error::BaseMismatch(d, b) => {
+                fl!(fll, "dep-base", dir = d.as_str(), base = b.as_str())
+            }
+            deps::Error::AuditRejected(p, r) => {
+                fl!(fll, "dep-audit", pkg = p.as_str(), reason = r.as_str())
+            }
+            deps::Error::SrcinfoParseTimeout(p) => fl!(fll, "dep-timeout", file = p.as_str()),
+            deps::Error::SrcinfoGeneration(p) => fl!(fll, "dep-generate", dir = p.utf8()),
+            deps::Error::Conflict(a, b) => {
+                fl!(fll, "dep-conflict", a = a.as_str(), b = b.as_str())
+            }
+            deps::Error::Build(p, _) => fl!(fll, "dep-build", pkg = p.as_str()),
         }
     }
 }
@@ -148,30 +148,39 @@ fn work(args: Args, env: Env, fll: &FluentLanguageLoader) -> Result<(), Error> {
         SubCmd::DepTest(_) => pacman(&env, false)?,
         SubCmd::Upgrade(u) => pacman(&env, u.needs_sudo())?,
         // --- AUR Packages --- //
-        SubCmd::Aur(a) if a.info.is_empty().not() => aur::info(fll, &a.info)?,
+        SubCmd::Aur(a) if a.info.is_empty().not() => {
+            aur::info(fll, &env.aur.faur, &a.info, a.json)?
+        }
         SubCmd::Aur(a) if a.search.is_empty().not() => aur::search(
             &env.alpm()?,
+            &env.aur.faur,
             a.abc,
             env.aur.reverse,
             a.limit,
             a.quiet,
+            a.json,
             a.search,
         )?,
         SubCmd::Aur(a) if a.provides.is_some() => aur::provides(
             &env.alpm()?,
+            &env.aur.faur,
             a.abc,
             a.reverse,
             a.limit,
             a.quiet,
+            a.json,
             a.provides.unwrap(),
         )?,
         SubCmd::Aur(a) if a.open.is_some() => aur::open(&a.open.unwrap())?,
         SubCmd::Aur(a) if a.pkgbuild.is_some() => {
-            aur::pkgbuild(&a.pkgbuild.unwrap(), &env.aur.clones)?
+            aur::pkgbuild(&a.pkgbuild.unwrap(), &env.aur.clones, &env.aur.faur)?
         }
         SubCmd::Aur(a) if a.wclone.is_empty().not() => aur::clone_aur_repos(fll, &a.wclone)?,
         SubCmd::Aur(a) if a.sysupgrade => aur::upgrade(fll, &env.alpm()?, env, a.dryrun)?,
         SubCmd::Aur(a) if a.refresh => aur::refresh(fll, &env.aur.clones)?,
+        SubCmd::Aur(a) if a.dryrun => {
+            aur::plan(fll, &env, a.json, a.packages.iter().map(|s| s.as_str()))?
+        }
         SubCmd::Aur(a) => aur::install(
             fll,
             &env,
@@ -206,9 +215,9 @@ fn work(args: Args, env: Env, fll: &FluentLanguageLoader) -> Result<(), Error> {
         SubCmd::Log(l) => logs::view(env.alpm_log(), l.before, l.after)?,
         // --- Orphan Packages --- //
         SubCmd::Orphans(o) if o.abandon => orphans::remove(&env, &env.alpm()?, fll)?,
-        SubCmd::Orphans(o) if !o.adopt.is_empty() => orphans::adopt(&env, o.adopt)?,
+        SubCmd::Orphans(o) if !o.adopt.is_empty() => orphans::adopt(&env, &env.alpm()?, o.adopt)?,
         SubCmd::Orphans(o) if o.elderly => orphans::elderly(&env.alpm()?),
-        SubCmd::Orphans(_) => orphans::list(&env.alpm()?),
+        SubCmd::Orphans(o) => orphans::list(&env.alpm()?, o.json)?,
         // --- PKGBUILD Analysis --- //
         // SubCmd::Analysis(_) => unimplemented!(),
         // --- Configuration --- //
@@ -3,10 +3,9 @@
 
 use crate::dirs::xdg_config;
 use applying::Apply;
-use std::fs::File;
-use std::io::BufRead;
-use std::io::BufReader;
+use std::collections::HashMap;
 use std::ops::Not;
+use std::path::Path;
 use std::path::PathBuf;
 
 /// The default filepath of the global Makepkg configuration.
@@ -35,54 +34,155 @@ pub(crate) struct Makepkg {
     /// Not a setting per se, but the original path to the config file that was
     /// detected.
     pub(crate) path: PathBuf,
+    /// Every variable set, like `MAKEFLAGS` or `PKGDEST`.
+    vars: HashMap<String, String>,
 }
 
 impl Makepkg {
     /// Attempt to read certain makepkg settings from the filesystem.
+    ///
+    /// Like `makepkg` itself, this reads the global config first, and then lets
+    /// any user config override it.
     pub(crate) fn new() -> Result<Self, Error> {
         let path = conf_location();
+        let global = global_conf();
+        let texts = if path == global {
+            vec![std::fs::read_to_string(&path).map_err(Error::Io)?]
+        } else {
+            // The user's own config is enough, if the global one is missing.
+            let user = std::fs::read_to_string(&path).map_err(Error::Io)?;
+            std::fs::read_to_string(&global)
+                .into_iter()
+                .chain([user])
+                .collect()
+        };
 
-        let (packager, check) = path
-            .as_path()
-            .apply(File::open)
-            .map_err(Error::Io)?
-            .apply(BufReader::new)
-            .lines()
-            .map_while(Result::ok)
-            .map(|line| line.trim().to_string())
-            .filter(|line| line.is_empty().not())
-            .filter(|line| line.starts_with('#').not())
-            // FIXME 2024-07-01 Generalise this into parsing a `HashMap`.
-            //
-            // And then extract the fields you need. However, some fields cross
-            // more than one line, so the parsing itself isn't entirely
-            // straight-forward. For now, I don't want to make a new library
-            // just for that.
-            .fold((None, false), |(p, c), line| {
-                if line.starts_with("PACKAGER=") {
-                    (extract_value(&line).map(|s| s.to_string()), c)
-                } else if line.starts_with("BUILDENV=") {
-                    let list = extract_value(&line).map(extract_list).unwrap_or_default();
-                    (p, list.contains(&"check"))
-                } else {
-                    (p, c)
-                }
-            });
+        let vars = layered(texts.iter().map(|t| t.as_str()));
+        let packager = vars.get("PACKAGER").cloned();
+        let check = vars
+            .get("BUILDENV")
+            .is_some_and(|v| extract_list(v).contains(&"check"));
 
         Ok(Makepkg {
             packager,
             check,
             path,
+            vars,
         })
     }
+
+    /// The value of some variable, like `MAKEFLAGS`.
+    pub(crate) fn var(&self, key: &str) -> Option<&str> {
+        self.vars.get(key).map(|v| v.as_str())
+    }
+
+    /// Where `makepkg` extracts sources and builds packages, if not in the
+    /// directory of the `PKGBUILD` itself.
+    pub(crate) fn builddir(&self) -> Option<&Path> {
+        self.var("BUILDDIR").map(Path::new)
+    }
+}
+
+/// The variables set across several `makepkg.conf` files, those of later files
+/// overriding those of earlier ones.
+fn layered<'a, I>(texts: I) -> HashMap<String, String>
+where
+    I: IntoIterator<Item = &'a str>,
+{
+    texts.into_iter().flat_map(parse).collect()
+}
+
+/// Parse the variables set in a `makepkg.conf`, which is a shell script.
+///
+/// Arrays, like `BUILDENV=(...)`, are kept whole, and may span several lines.
+/// Anything fancier than simple assignment is ignored.
+fn parse(text: &str) -> HashMap<String, String> {
+    let mut vars = HashMap::new();
+    let mut pending: Option<(String, String)> = None;
+
+    for line in text.lines().map(|l| l.trim()) {
+        if let Some((key, mut array)) = pending.take() {
+            array.push(' ');
+            array.push_str(strip_comment(line));
+
+            if array.ends_with(')') {
+                vars.insert(key, array);
+            } else {
+                pending = Some((key, array));
+            }
+
+            continue;
+        }
+
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        let line = line.strip_prefix("export ").unwrap_or(line);
+        let Some((key, raw)) = line.split_once('=') else {
+            continue;
+        };
+
+        if key.is_empty()
+            || key
+                .chars()
+                .any(|c| c.is_ascii_alphanumeric().not() && c != '_')
+        {
+            continue;
+        }
+
+        let raw = raw.trim();
+
+        if raw.starts_with('(') {
+            let array = strip_comment(raw).to_string();
+
+            if array.ends_with(')') {
+                vars.insert(key.to_string(), array);
+            } else {
+                pending = Some((key.to_string(), array));
+            }
+        } else if let Some(value) = extract_value(line) {
+            vars.insert(key.to_string(), value.to_string());
+        }
+    }
+
+    vars
+}
+
+/// The value of a shell assignment, without any surrounding quotes or trailing
+/// comment.
+fn unquote(v: &str) -> &str {
+    let v = v.trim();
+
+    if let Some(rest) = v.strip_prefix('"') {
+        rest.split_once('"').map_or(rest, |(inner, _)| inner)
+    } else if let Some(rest) = v.strip_prefix('\'') {
+        rest.split_once('\'').map_or(rest, |(inner, _)| inner)
+    } else {
+        strip_comment(v)
+    }
+}
+
+fn strip_comment(line: &str) -> &str {
+    line.split_once('#')
+        .filter(|(before, _)| before.is_empty() || before.ends_with(char::is_whitespace))
+        .map_or(line, |(before, _)| before)
+        .trim()
 }
 
 fn extract_value(line: &str) -> Option<&str> {
-    line.split_once('=').map(|(_, v)| v.trim_matches('"'))
+    line.split_once('=').map(|(_, v)| unquote(v))
 }
 
 fn extract_list(s: &str) -> Vec<&str> {
-    s.trim_matches(['(', ')']).split(' ').collect()
+    s.trim_matches(['(', ')']).split_whitespace().collect()
+}
+
+/// The global config, which may be overridden via `MAKEPKG_CONF`.
+fn global_conf() -> PathBuf {
+    std::env::var("MAKEPKG_CONF")
+        .map(PathBuf::from)
+        .unwrap_or_else(|_| PathBuf::from(GLOBAL_MAKEPKG_CONF))
 }
 
 fn strong_local_conf() -> Option<PathBuf> {
@@ -113,9 +213,7 @@ pub(crate) fn conf_location() -> PathBuf {
     match (strong, weak) {
         (Some(pb), _) if pb.is_file() => pb,
         (_, Some(pb)) if pb.is_file() => pb,
-        (_, _) => std::env::var("MAKEPKG_CONF")
-            .map(PathBuf::from)
-            .unwrap_or_else(|_| PathBuf::from(GLOBAL_MAKEPKG_CONF)),
+        (_, _) => global_conf(),
     }
 }
 
@@ -139,4 +237,42 @@ mod tests {
 
         assert_eq!(e, r);
     }
+
+    #[test]
+    fn variables() {
+        let conf = r#"
+#!/hint/bash
+# A comment.
+PACKAGER="Colin Woodbury <colin@fosskers.ca>"
+MAKEFLAGS='-j8'
+PKGDEST=/home/colin/packages # Where they go.
+export BUILDDIR=/tmp/makepkg
+BUILDENV=(!distcc color
+          !ccache check # Run the tests.
+          !sign)
+  #SRCDEST=/home/colin/sources
+"#;
+        let vars = parse(conf);
+
+        assert_eq!("Colin Woodbury <colin@fosskers.ca>", vars["PACKAGER"]);
+        assert_eq!("-j8", vars["MAKEFLAGS"]);
+        assert_eq!("/home/colin/packages", vars["PKGDEST"]);
+        assert_eq!("/tmp/makepkg", vars["BUILDDIR"]);
+        assert_eq!(
+            vec!["!distcc", "color", "!ccache", "check", "!sign"],
+            extract_list(&vars["BUILDENV"])
+        );
+        assert!(vars.contains_key("SRCDEST").not());
+    }
+
+    #[test]
+    fn overrides() {
+        let global = "PACKAGER=\"Unknown Packager\"\nMAKEFLAGS=\"-j2\"\nPKGDEST=/var/pkgs";
+        let user = "MAKEFLAGS=\"-j16\"\n#PKGDEST=/home/colin/pkgs";
+        let vars = layered([global, user]);
+
+        assert_eq!("Unknown Packager", vars["PACKAGER"]);
+        assert_eq!("-j16", vars["MAKEFLAGS"]);
+        assert_eq!("/var/pkgs", vars["PKGDEST"]);
+    }
 }
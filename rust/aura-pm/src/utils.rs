@@ -6,6 +6,8 @@ use i18n_embed::fluent::FluentLanguageLoader;
 use i18n_embed_fl::fl;
 use karen::RunningAs;
 use nonempty_collections::NEVec;
+use serde::Serialize;
+use std::io::BufWriter;
 use std::io::Write;
 use std::iter::Peekable;
 use std::path::Path;
@@ -16,6 +18,16 @@ use unic_langid::LanguageIdentifier;
 /// An empty array to satisfy typechecking in a few places around the codebase.
 pub(crate) const NOTHING: [&str; 0] = [];
 
+/// Write some value to stdout as a single line of JSON, for scripts to consume.
+pub(crate) fn json<T>(value: &T) -> Result<(), serde_json::Error>
+where
+    T: Serialize + ?Sized,
+{
+    let mut w = BufWriter::new(std::io::stdout());
+    serde_json::to_writer(&mut w, value)?;
+    writeln!(w).map_err(serde_json::Error::io)
+}
+
 /// Injection of the `void` method into [`Result`], which is a common shorthand
 /// for "forgetting" the internal return value of a `Result`. Note that this
 /// also automatically lifts the Error type via [`From`], as it is intended as